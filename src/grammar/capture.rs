@@ -0,0 +1,272 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    context::RunContext,
+    error::GrammarError,
+    indentation::Indentation,
+    input::Input,
+    registry::Grammar,
+    state_machine::{ExecutionMode, StateMachine},
+    transition::{Transition, TransitionKind},
+};
+
+/// Text matched by a capturing transition. Offsets are byte offsets, and the
+/// spans captured within a group are its children.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    pub label: String,
+    pub start: usize,
+    pub end: usize,
+    pub children: Vec<Span>,
+}
+
+impl Span {
+    /// The text of `buffer` the span covers.
+    pub fn text<'a>(&self, buffer: &'a str) -> &'a str {
+        &buffer[self.start..self.end]
+    }
+}
+
+/// A state id, offset and indentation a run can be in.
+type Configuration = (usize, usize, Indentation);
+
+/// A transition of the accepted run and where it was taken.
+#[derive(Clone)]
+struct Step<'a> {
+    transition: &'a dyn Transition,
+    offset: usize,
+    indentation: Indentation,
+    consumed: usize,
+}
+
+impl StateMachine {
+    /// Validates `buffer` from its start and returns the spans captured by the
+    /// accepted run, in order, or `None` if the buffer is rejected.
+    pub fn captures(&self, buffer: impl AsRef<str>) -> Option<Vec<Span>> {
+        captures(self, buffer.as_ref(), RunContext::new())
+    }
+}
+
+impl Grammar {
+    /// Like `StateMachine::captures`, against the rule named `rule`.
+    pub fn captures(
+        &self,
+        rule: &str,
+        buffer: impl AsRef<str>,
+    ) -> Result<Option<Vec<Span>>, GrammarError> {
+        let machine = self.find_rule(rule)?;
+        let mut context = RunContext::new();
+        context.with_grammar(self);
+        Ok(captures(machine, buffer.as_ref(), context))
+    }
+}
+
+fn captures(machine: &StateMachine, buffer: &str, mut context: RunContext) -> Option<Vec<Span>> {
+    // groups are run again while looking for the accepted path
+    context.memoize();
+    let input = Input::new(buffer);
+    let indentation = Indentation::new();
+    let (accepted, end) = machine.validate_in_context(&input, 0, &indentation, &mut context);
+    if !accepted {
+        return None;
+    }
+    derive(machine, &input, 0, &indentation, end, &mut context)
+}
+
+/// The spans captured by a run of `machine` from `from` that is accepted at `end`.
+fn derive(
+    machine: &StateMachine,
+    input: &Input,
+    from: usize,
+    indentation: &Indentation,
+    end: usize,
+    context: &mut RunContext,
+) -> Option<Vec<Span>> {
+    let path = match machine.mode() {
+        ExecutionMode::Deterministic => replay(machine, input, from, indentation, end, context)?,
+        ExecutionMode::Backtracking => search(machine, input, from, indentation, end, context)?,
+    };
+
+    let mut spans = Vec::new();
+    for step in path {
+        let step_end = step.offset + step.consumed;
+        let children = match step.transition.kind() {
+            TransitionKind::Group(inner) => derive(
+                inner,
+                input,
+                step.offset,
+                &step.indentation,
+                step_end,
+                context,
+            )?,
+            TransitionKind::Rule(rule) => {
+                let inner = context.rule(rule)?;
+                derive(
+                    &inner,
+                    input,
+                    step.offset,
+                    &step.indentation,
+                    step_end,
+                    context,
+                )?
+            }
+            _ => Vec::new(),
+        };
+        match step.transition.capture() {
+            Some(label) => spans.push(Span {
+                label: label.to_string(),
+                start: step.offset,
+                end: step_end,
+                children,
+            }),
+            None => spans.extend(children),
+        }
+    }
+    Some(spans)
+}
+
+/// Takes the transitions a deterministic run takes.
+fn replay<'a>(
+    machine: &'a StateMachine,
+    input: &Input,
+    from: usize,
+    indentation: &Indentation,
+    end: usize,
+    context: &mut RunContext,
+) -> Option<Vec<Step<'a>>> {
+    let mut state = 0;
+    let mut offset = from;
+    let mut indentation = indentation.clone();
+    let mut path = Vec::new();
+    while !input.is_end(offset) {
+        let Some(next) = machine.step(
+            state,
+            input,
+            offset,
+            &indentation,
+            context,
+            &mut |_, _, _| {},
+        ) else {
+            break;
+        };
+        path.push(Step {
+            transition: machine.transitions()[next.transition].as_ref(),
+            offset,
+            indentation,
+            consumed: next.offset - offset,
+        });
+        state = next.target;
+        offset = next.offset;
+        indentation = next.indentation;
+    }
+    (offset == end && machine.states()[state].is_final()).then_some(path)
+}
+
+/// Looks for a path reaching a final state at `end`, trying transitions in the
+/// order a backtracking run explores them.
+fn search<'a>(
+    machine: &'a StateMachine,
+    input: &Input,
+    from: usize,
+    indentation: &Indentation,
+    end: usize,
+    context: &mut RunContext,
+) -> Option<Vec<Step<'a>>> {
+    let mut visited = HashSet::new();
+    // how each configuration was first reached
+    let mut parents: HashMap<Configuration, (Configuration, Step<'a>)> = HashMap::new();
+    let mut pending = vec![(0, from, indentation.clone())];
+
+    while let Some(configuration) = pending.pop() {
+        if !visited.insert(configuration.clone()) {
+            continue;
+        }
+        let (state, offset, indentation) = &configuration;
+        let (state, offset) = (*state, *offset);
+        if offset == end && machine.states()[state].is_final() {
+            let mut path = Vec::new();
+            let mut current = &configuration;
+            while let Some((previous, step)) = parents.get(current) {
+                path.push(step.clone());
+                current = previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        let mut successors = Vec::new();
+        for (transition, target) in machine.outgoing(state).iter().zip(machine.targets(state)) {
+            let next_indentation = transition.next_indentation(indentation, input, offset);
+            for consumed in transition.to_all(
+                input,
+                offset,
+                indentation,
+                machine.indentation_character(),
+                context,
+            ) {
+                let next = (*target, offset + consumed, next_indentation.clone());
+                if next.1 > end || visited.contains(&next) {
+                    continue;
+                }
+                let step = Step {
+                    transition: transition.as_ref(),
+                    offset,
+                    indentation: indentation.clone(),
+                    consumed,
+                };
+                parents
+                    .entry(next.clone())
+                    .or_insert_with(|| (configuration.clone(), step));
+                successors.push(next);
+            }
+        }
+        pending.extend(successors.into_iter().rev());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{grammar::class::CharClassBuilder, machine};
+
+    use super::Span;
+
+    #[test]
+    fn test_backtracking_captures_follow_the_accepted_path() {
+        let letters = CharClassBuilder::new()
+            .add_range('a', 'z')
+            .add_char(' ')
+            .build();
+        let word = machine! {
+            name: "word", indentation: 0, mode: Backtracking;
+            start -> letters*: letters.clone();
+            letters -> letters: letters;
+        };
+        // the word has to give its trailing space back
+        let machine = machine! {
+            name: "comment", indentation: 0, mode: Backtracking;
+            start -> word: word, capture: "word";
+            word -> space: ' ';
+            space -> comment*: '#', capture: "comment";
+        };
+        assert_eq!(
+            machine.captures("ab #").unwrap(),
+            vec![
+                Span {
+                    label: "word".to_string(),
+                    start: 0,
+                    end: 2,
+                    children: Vec::new(),
+                },
+                Span {
+                    label: "comment".to_string(),
+                    start: 3,
+                    end: 4,
+                    children: Vec::new(),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,417 @@
+use core::fmt;
+
+use unicode_general_category::{get_general_category, GeneralCategory};
+
+const LAST_BEFORE_SURROGATES: u32 = 0xD7FF;
+const FIRST_AFTER_SURROGATES: u32 = 0xE000;
+
+/// A set of characters described by ranges and Unicode general categories,
+/// optionally negated.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CharClass {
+    /// Sorted, disjoint and non adjacent ranges.
+    ranges: Vec<(char, char)>,
+    categories: Vec<GeneralCategory>,
+    negated: bool,
+}
+
+impl CharClass {
+    pub fn matches(&self, c: char) -> bool {
+        let index = self.ranges.partition_point(|(_, high)| *high < c);
+        let in_ranges = self.ranges.get(index).is_some_and(|(low, _)| *low <= c);
+        let in_categories =
+            !self.categories.is_empty() && self.categories.contains(&get_general_category(c));
+        (in_ranges || in_categories) != self.negated
+    }
+
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    /// The ranges the class was built with, categories left out.
+    pub fn ranges(&self) -> &[(char, char)] {
+        &self.ranges
+    }
+
+    pub fn categories(&self) -> &[GeneralCategory] {
+        &self.categories
+    }
+
+    /// The matched characters as sorted, disjoint ranges. Categories are expanded
+    /// by scanning every Unicode scalar value, so this is meant for compilation
+    /// passes rather than matching.
+    pub fn to_ranges(&self) -> Vec<(char, char)> {
+        let mut ranges = self.ranges.clone();
+        if !self.categories.is_empty() {
+            let mut run: Option<(char, char)> = None;
+            for c in (0..=char::MAX as u32).filter_map(char::from_u32) {
+                if self.categories.contains(&get_general_category(c)) {
+                    run = Some(match run {
+                        Some((low, _)) => (low, c),
+                        None => (c, c),
+                    });
+                } else if let Some(found) = run.take() {
+                    ranges.push(found);
+                }
+            }
+            ranges.extend(run);
+            ranges = normalize(ranges);
+        }
+        if self.negated {
+            ranges = complement(&ranges);
+        }
+        ranges
+    }
+}
+
+/// Builds a `CharClass` out of characters, ranges and categories.
+#[derive(Default)]
+pub struct CharClassBuilder {
+    ranges: Vec<(char, char)>,
+    categories: Vec<GeneralCategory>,
+    negated: bool,
+}
+
+impl CharClassBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_char(&mut self, c: char) -> &mut Self {
+        self.ranges.push((c, c));
+        self
+    }
+
+    pub fn add_chars(&mut self, chars: &str) -> &mut Self {
+        for c in chars.chars() {
+            self.add_char(c);
+        }
+        self
+    }
+
+    /// Adds every character from `low` to `high`, both included.
+    pub fn add_range(&mut self, low: char, high: char) -> &mut Self {
+        if low <= high {
+            self.ranges.push((low, high));
+        }
+        self
+    }
+
+    pub fn add_category(&mut self, category: GeneralCategory) -> &mut Self {
+        if !self.categories.contains(&category) {
+            self.categories.push(category);
+        }
+        self
+    }
+
+    /// Makes the class match every character the other additions do not.
+    pub fn negate(&mut self) -> &mut Self {
+        self.negated = !self.negated;
+        self
+    }
+
+    pub fn build(&self) -> CharClass {
+        CharClass {
+            ranges: normalize(self.ranges.clone()),
+            categories: self.categories.clone(),
+            negated: self.negated,
+        }
+    }
+}
+
+const CATEGORIES: [GeneralCategory; 30] = [
+    GeneralCategory::ClosePunctuation,
+    GeneralCategory::ConnectorPunctuation,
+    GeneralCategory::Control,
+    GeneralCategory::CurrencySymbol,
+    GeneralCategory::DashPunctuation,
+    GeneralCategory::DecimalNumber,
+    GeneralCategory::EnclosingMark,
+    GeneralCategory::FinalPunctuation,
+    GeneralCategory::Format,
+    GeneralCategory::InitialPunctuation,
+    GeneralCategory::LetterNumber,
+    GeneralCategory::LineSeparator,
+    GeneralCategory::LowercaseLetter,
+    GeneralCategory::MathSymbol,
+    GeneralCategory::ModifierLetter,
+    GeneralCategory::ModifierSymbol,
+    GeneralCategory::NonspacingMark,
+    GeneralCategory::OpenPunctuation,
+    GeneralCategory::OtherLetter,
+    GeneralCategory::OtherNumber,
+    GeneralCategory::OtherPunctuation,
+    GeneralCategory::OtherSymbol,
+    GeneralCategory::ParagraphSeparator,
+    GeneralCategory::PrivateUse,
+    GeneralCategory::SpaceSeparator,
+    GeneralCategory::SpacingMark,
+    GeneralCategory::Surrogate,
+    GeneralCategory::TitlecaseLetter,
+    GeneralCategory::Unassigned,
+    GeneralCategory::UppercaseLetter,
+];
+
+/// The category written `abbreviation` in `\p{..}`, such as `Lu`.
+pub fn category_from_abbreviation(abbreviation: &str) -> Option<GeneralCategory> {
+    CATEGORIES
+        .into_iter()
+        .find(|category| category.abbreviation() == abbreviation)
+}
+
+/// Reads the escapes and classes that regexes and grammar files share, each
+/// syntax reporting errors its own way.
+pub(crate) trait ClassSyntax {
+    type Error;
+
+    /// What a class missing its closing bracket is reported as.
+    const UNTERMINATED_CLASS: &'static str;
+
+    fn source(&self) -> &str;
+
+    fn offset(&self) -> usize;
+
+    fn set_offset(&mut self, offset: usize);
+
+    fn error(&self, offset: usize, message: impl Into<String>) -> Self::Error;
+
+    /// Parses an escape other than `\n`, `\t` and `\r`, `offset` being that of
+    /// its backslash.
+    fn other_escape(&mut self, offset: usize, c: Option<char>) -> Result<char, Self::Error>;
+
+    /// Adds to `builder` the class item started by `c` at `item` when this
+    /// syntax gives `c` a meaning of its own, telling whether it did.
+    fn special_item(
+        &mut self,
+        _builder: &mut CharClassBuilder,
+        _c: char,
+        _item: usize,
+    ) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source()[self.offset()..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.set_offset(self.offset() + c.len_utf8());
+        Some(c)
+    }
+
+    /// Parses what follows a backslash.
+    fn escape(&mut self) -> Result<char, Self::Error> {
+        let offset = self.offset() - 1;
+        match self.bump() {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('r') => Ok('\r'),
+            c => self.other_escape(offset, c),
+        }
+    }
+
+    /// Parses a class once its opening bracket, at `offset`, is consumed.
+    fn class(&mut self, offset: usize) -> Result<CharClass, Self::Error> {
+        let mut builder = CharClassBuilder::new();
+        if self.peek() == Some('^') {
+            self.bump();
+            builder.negate();
+        }
+        let mut empty = true;
+        loop {
+            let item = self.offset();
+            let low = match self.bump() {
+                None => return Err(self.error(offset, Self::UNTERMINATED_CLASS)),
+                Some(']') if empty => return Err(self.error(offset, "empty class")),
+                Some(']') => return Ok(builder.build()),
+                Some('\\') if self.peek() == Some('p') => {
+                    self.bump();
+                    builder.add_category(self.category(item)?);
+                    empty = false;
+                    continue;
+                }
+                Some(c) if self.special_item(&mut builder, c, item)? => {
+                    empty = false;
+                    continue;
+                }
+                Some('\\') => self.escape()?,
+                Some(c) => c,
+            };
+            empty = false;
+            if self.peek() == Some('-') && !self.source()[self.offset() + 1..].starts_with(']') {
+                self.bump();
+                let high = match self.bump() {
+                    Some('\\') => self.escape()?,
+                    Some(c) => c,
+                    None => return Err(self.error(offset, Self::UNTERMINATED_CLASS)),
+                };
+                if high < low {
+                    return Err(self.error(item, "range is out of order"));
+                }
+                builder.add_range(low, high);
+            } else {
+                builder.add_char(low);
+            }
+        }
+    }
+
+    /// Parses the `{Lu}` of `\p{Lu}`, `offset` being that of its backslash.
+    fn category(&mut self, offset: usize) -> Result<GeneralCategory, Self::Error> {
+        if self.bump() != Some('{') {
+            return Err(self.error(offset, "expected a category such as \\p{Lu}"));
+        }
+        let start = self.offset();
+        while self.peek().is_some_and(|c| c != '}') {
+            self.bump();
+        }
+        let end = self.offset();
+        if self.bump().is_none() {
+            return Err(self.error(offset, "unterminated category"));
+        }
+        let name = &self.source()[start..end];
+        match category_from_abbreviation(name) {
+            Some(category) => Ok(category),
+            None => Err(self.error(offset, format!("unknown category {}", name))),
+        }
+    }
+}
+
+fn next_char(c: char) -> Option<char> {
+    match c as u32 {
+        LAST_BEFORE_SURROGATES => char::from_u32(FIRST_AFTER_SURROGATES),
+        code => char::from_u32(code + 1),
+    }
+}
+
+fn previous_char(c: char) -> Option<char> {
+    match c as u32 {
+        0 => None,
+        FIRST_AFTER_SURROGATES => char::from_u32(LAST_BEFORE_SURROGATES),
+        code => char::from_u32(code - 1),
+    }
+}
+
+/// Sorts ranges and merges the ones that overlap or touch.
+pub(crate) fn normalize(mut ranges: Vec<(char, char)>) -> Vec<(char, char)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(char, char)> = Vec::with_capacity(ranges.len());
+    for (low, high) in ranges {
+        match merged.last_mut() {
+            Some(last) if next_char(last.1).is_none_or(|next| low <= next) => {
+                last.1 = last.1.max(high);
+            }
+            _ => merged.push((low, high)),
+        }
+    }
+    merged
+}
+
+/// Every character not covered by the normalized `ranges`.
+pub(crate) fn complement(ranges: &[(char, char)]) -> Vec<(char, char)> {
+    let mut gaps = Vec::new();
+    let mut low = Some('\0');
+    for (range_low, range_high) in ranges {
+        if let (Some(gap_low), Some(gap_high)) = (low, previous_char(*range_low)) {
+            if gap_low <= gap_high {
+                gaps.push((gap_low, gap_high));
+            }
+        }
+        low = next_char(*range_high);
+    }
+    if let Some(gap_low) = low {
+        gaps.push((gap_low, char::MAX));
+    }
+    gaps
+}
+
+fn write_class_char(f: &mut fmt::Formatter, c: char) -> fmt::Result {
+    match c {
+        '\\' | '[' | ']' | '^' | '-' => write!(f, "\\{}", c),
+        '\n' => write!(f, "\\n"),
+        '\t' => write!(f, "\\t"),
+        '\r' => write!(f, "\\r"),
+        c if c.is_control() => write!(f, "{}", c.escape_unicode()),
+        c => write!(f, "{}", c),
+    }
+}
+
+impl fmt::Display for CharClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        if self.negated {
+            write!(f, "^")?;
+        }
+        for (low, high) in &self.ranges {
+            write_class_char(f, *low)?;
+            if low != high {
+                write!(f, "-")?;
+                write_class_char(f, *high)?;
+            }
+        }
+        for category in &self.categories {
+            write!(f, "\\p{{{}}}", category.abbreviation())?;
+        }
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use unicode_general_category::GeneralCategory;
+
+    use super::CharClassBuilder;
+
+    #[test]
+    fn test_class_matches_ranges() {
+        let class = CharClassBuilder::new()
+            .add_range('a', 'z')
+            .add_range('A', 'Z')
+            .add_char(' ')
+            .build();
+        assert!(class.matches('q'));
+        assert!(class.matches('Q'));
+        assert!(class.matches(' '));
+        assert!(!class.matches(';'));
+        assert_eq!(class.to_string(), "[ A-Za-z]");
+    }
+
+    #[test]
+    fn test_class_matches_negated_set() {
+        let class = CharClassBuilder::new().add_chars(":#\n").negate().build();
+        assert!(class.matches('a'));
+        assert!(class.matches('é'));
+        assert!(!class.matches(':'));
+        assert!(!class.matches('\n'));
+        assert_eq!(class.to_string(), "[^\\n#:]");
+        assert_eq!(
+            class.to_ranges(),
+            vec![('\0', '\t'), ('\u{b}', '"'), ('$', '9'), (';', char::MAX)]
+        );
+    }
+
+    #[test]
+    fn test_class_matches_categories() {
+        let class = CharClassBuilder::new()
+            .add_category(GeneralCategory::UppercaseLetter)
+            .add_category(GeneralCategory::DecimalNumber)
+            .build();
+        assert!(class.matches('É'));
+        assert!(class.matches('7'));
+        assert!(!class.matches('é'));
+        assert_eq!(class.to_string(), "[\\p{Lu}\\p{Nd}]");
+        let ranges = class.to_ranges();
+        assert!(ranges.contains(&('0', '9')));
+        assert!(ranges.contains(&('A', 'Z')));
+    }
+
+    #[test]
+    fn test_class_merges_touching_ranges() {
+        let class = CharClassBuilder::new()
+            .add_range('a', 'f')
+            .add_range('d', 'k')
+            .add_range('l', 'm')
+            .build();
+        assert_eq!(class.to_ranges(), vec![('a', 'm')]);
+    }
+}
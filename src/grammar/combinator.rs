@@ -0,0 +1,380 @@
+use std::sync::Arc;
+
+use super::{
+    state::{create_state, State},
+    state_machine::{ExecutionMode, MatchStrategy, StateMachine, StateMachineBuilder},
+    transition::{EpsilonTransition, GroupTransition, IndentationOperation},
+};
+
+/// A machine copied into a composition: where it starts and the states it
+/// used to accept in.
+struct Part {
+    entry: Arc<State>,
+    exits: Vec<Arc<State>>,
+}
+
+/// Copies machines into a single one and glues them together with epsilon
+/// transitions. The states of the `n`th machine copied are labelled `n.`
+/// followed by their former label, so copies never clash. Copied transitions
+/// share their group machines with the originals.
+struct Composer {
+    builder: StateMachineBuilder,
+    start: Arc<State>,
+    parts: usize,
+}
+
+impl Composer {
+    /// Starts a composition in a new `start` state, indented like `like`.
+    fn new(like: &StateMachine) -> Self {
+        let start = Arc::new(create_state(false, "start"));
+        let builder = StateMachineBuilder::new(
+            start.clone(),
+            like.indentation_character(),
+            like.current_indentation(),
+        );
+        Composer {
+            builder,
+            start,
+            parts: 0,
+        }
+    }
+
+    fn state(&mut self, label: &str) -> Arc<State> {
+        let state = Arc::new(create_state(false, label));
+        self.builder.add_state(state.clone());
+        state
+    }
+
+    /// Copies the states and transitions of `machine`, unless it runs
+    /// differently from a backtracking, first match machine. It is then kept
+    /// whole as a group between `n.start` and `n.start.matched`, `start` being
+    /// the label of its start state.
+    fn part(&mut self, machine: &StateMachine) -> Part {
+        let prefix = format!("{}.", self.parts);
+        self.parts += 1;
+        let entry_label = format!("{}{}", prefix, machine.start().label);
+        if machine.mode() != ExecutionMode::Backtracking
+            || machine.strategy() != MatchStrategy::FirstMatch
+        {
+            let entry = self.state(&entry_label);
+            let exit = self.state(&format!("{}.matched", entry_label));
+            self.builder
+                .add_transition(Arc::new(GroupTransition::shared(
+                    entry.clone(),
+                    exit.clone(),
+                    Arc::new(machine.clone()),
+                    IndentationOperation::BYPASS,
+                )));
+            return Part {
+                entry,
+                exits: vec![exit],
+            };
+        }
+
+        let states: Vec<Arc<State>> = machine
+            .states()
+            .iter()
+            .map(|state| self.state(&format!("{}{}", prefix, state.label)))
+            .collect();
+        for (id, copy) in states.iter().enumerate() {
+            let outgoing = machine.outgoing(id).iter().zip(machine.targets(id));
+            for ((transition, target), priority) in outgoing.zip(machine.priorities(id)) {
+                self.builder.add_transition_with_priority(
+                    transition.reconnect(copy.clone(), states[*target].clone()),
+                    *priority,
+                );
+            }
+        }
+        let exits = machine
+            .states()
+            .iter()
+            .zip(&states)
+            .filter(|(state, _)| state.is_final())
+            .map(|(_, copy)| copy.clone())
+            .collect();
+        Part {
+            entry: states[0].clone(),
+            exits,
+        }
+    }
+
+    fn epsilon(&mut self, from: &[Arc<State>], to: &Arc<State>) {
+        for from in from {
+            self.builder
+                .add_transition(Arc::new(EpsilonTransition::new(from.clone(), to.clone())));
+        }
+    }
+
+    /// Builds a backtracking machine accepting once a run gets through `ends`.
+    fn build(mut self, ends: &[Arc<State>], name: String) -> StateMachine {
+        let accept = Arc::new(create_state(true, "accept"));
+        self.builder.add_state(accept.clone());
+        self.epsilon(ends, &accept);
+        self.builder
+            .name(&name)
+            .mode(ExecutionMode::Backtracking)
+            .strategy(MatchStrategy::FirstMatch)
+            .build()
+    }
+}
+
+/// Combinators building new machines out of copies of existing ones, like
+/// `FragmentBuilder` does out of fragments. The machines they build backtrack
+/// and take their indentation from the first machine. Machines that do not
+/// backtrack, or pick matches other than the first, are run as groups so they
+/// keep their own semantics.
+impl StateMachine {
+    /// Matches this machine then `other`.
+    pub fn concat(&self, other: &StateMachine) -> StateMachine {
+        let mut composer = Composer::new(self);
+        let first = composer.part(self);
+        let second = composer.part(other);
+        composer.epsilon(&[composer.start.clone()], &first.entry);
+        composer.epsilon(&first.exits, &second.entry);
+        let name = format!("concat({}, {})", self.name(), other.name());
+        composer.build(&second.exits, name)
+    }
+
+    /// Matches this machine or `other`.
+    pub fn union(&self, other: &StateMachine) -> StateMachine {
+        let mut composer = Composer::new(self);
+        let first = composer.part(self);
+        let second = composer.part(other);
+        let start = [composer.start.clone()];
+        composer.epsilon(&start, &first.entry);
+        composer.epsilon(&start, &second.entry);
+        let ends = [first.exits, second.exits].concat();
+        let name = format!("union({}, {})", self.name(), other.name());
+        composer.build(&ends, name)
+    }
+
+    /// Matches this machine any number of times, none included.
+    pub fn star(&self) -> StateMachine {
+        self.repeated(0, None, format!("star({})", self.name()))
+    }
+
+    /// Matches this machine at least once.
+    pub fn plus(&self) -> StateMachine {
+        self.repeated(1, None, format!("plus({})", self.name()))
+    }
+
+    /// Matches this machine or nothing.
+    pub fn optional(&self) -> StateMachine {
+        self.repeated(0, Some(1), format!("optional({})", self.name()))
+    }
+
+    /// Matches this machine at least `min` times and at most `max` times, or
+    /// without limit when `max` is `None`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is less than `min`.
+    pub fn repeat(&self, min: usize, max: Option<usize>) -> StateMachine {
+        let bound = max.map_or("_".to_string(), |max| max.to_string());
+        self.repeated(
+            min,
+            max,
+            format!("repeat({}, {}, {})", self.name(), min, bound),
+        )
+    }
+
+    fn repeated(&self, min: usize, max: Option<usize>, name: String) -> StateMachine {
+        assert!(
+            max.is_none_or(|max| max >= min),
+            "cannot repeat {} times at most and {} times at least",
+            max.unwrap_or_default(),
+            min
+        );
+        let mut composer = Composer::new(self);
+        let mut ends = vec![composer.start.clone()];
+        for _ in 0..min {
+            let part = composer.part(self);
+            composer.epsilon(&ends, &part.entry);
+            ends = part.exits;
+        }
+        match max {
+            None => {
+                let again = vec![composer.state("again")];
+                composer.epsilon(&ends, &again[0]);
+                let part = composer.part(self);
+                composer.epsilon(&again, &part.entry);
+                composer.epsilon(&part.exits, &again[0]);
+                ends = again;
+            }
+            Some(max) => {
+                // Every copy past `min` can be skipped, along with the ones after it.
+                let mut skipped = Vec::new();
+                for _ in min..max {
+                    let part = composer.part(self);
+                    composer.epsilon(&ends, &part.entry);
+                    skipped.append(&mut ends);
+                    ends = part.exits;
+                }
+                ends.append(&mut skipped);
+            }
+        }
+        composer.build(&ends, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        grammar::{state_machine::StateMachine, transition::TransitionKind},
+        machine,
+        yaml::{scalar::scalar_state_machine, sequence::sequence_state_machine},
+    };
+
+    fn word(word: &str) -> StateMachine {
+        machine! {
+            name: "word", indentation: 0;
+            start -> end*: word;
+        }
+    }
+
+    #[test]
+    fn test_combinators() {
+        let ab = word("a").concat(&word("b"));
+        assert!(ab.check("ab"));
+        assert!(!ab.check("a"));
+
+        let a_or_bc = word("a").union(&word("b").concat(&word("c")));
+        for (input, accepted) in [("a", true), ("bc", true), ("b", false), ("abc", false)] {
+            assert_eq!(a_or_bc.check(input), accepted, "{}", input);
+        }
+
+        let star = word("x").concat(&a_or_bc.star());
+        assert!(star.check("x"));
+        assert!(star.check("xabcabca"));
+        assert!(!star.check("xab"));
+        let plus = a_or_bc.plus();
+        assert!(!plus.check(""));
+        assert!(plus.check("bca"));
+        let optional = word("a").optional().concat(&word("b"));
+        assert!(optional.check("b"));
+        assert!(optional.check("ab"));
+        assert!(!optional.check("aab"));
+        assert_eq!(optional.name(), "concat(optional(word), word)");
+    }
+
+    #[test]
+    fn test_repeat() {
+        let bounded = word("ab").repeat(2, Some(3));
+        for (input, accepted) in [
+            ("ab", false),
+            ("abab", true),
+            ("ababab", true),
+            ("abababab", false),
+        ] {
+            assert_eq!(bounded.check(input), accepted, "{}", input);
+        }
+        let unbounded = word("ab").repeat(2, None);
+        assert!(!unbounded.check("ab"));
+        assert!(unbounded.check("abababab"));
+        assert!(word("x").concat(&word("ab").repeat(0, Some(0))).check("x"));
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot repeat")]
+    fn test_repeat_rejects_inverted_bounds() {
+        word("a").repeat(2, Some(1));
+    }
+
+    #[test]
+    fn test_states_are_renamed() {
+        let a = word("a");
+        let machine = a.concat(&a).union(&a);
+        let labels: Vec<&str> = machine
+            .states()
+            .iter()
+            .map(|state| state.label.as_str())
+            .collect();
+        assert!(labels.contains(&"0.0.start"));
+        assert!(labels.contains(&"0.1.start"));
+        assert!(labels.contains(&"1.start"));
+        assert_eq!(machine.start().label, "start");
+    }
+
+    #[test]
+    fn test_parts_keep_their_execution_mode() {
+        // a deterministic run stops on the `c` it expects after `ab`
+        let deterministic = machine! {
+            name: "abc", indentation: 0;
+            start -> a*: 'a';
+            a -> b: 'b';
+            b -> c*: 'c';
+        };
+        let backtracking = machine! {
+            name: "abc", indentation: 0, mode: Backtracking;
+            start -> a*: 'a';
+            a -> b: 'b';
+            b -> c*: 'c';
+        };
+        assert!(!deterministic.concat(&word("b")).check("ab"));
+        assert!(deterministic.concat(&word("b")).check("abcb"));
+        assert!(backtracking.concat(&word("b")).check("ab"));
+
+        let labels: Vec<String> = deterministic
+            .concat(&backtracking)
+            .states()
+            .iter()
+            .map(|state| state.label.clone())
+            .collect();
+        assert!(labels.contains(&"0.start.matched".to_string()));
+        assert!(!labels.contains(&"0.a".to_string()));
+        assert!(labels.contains(&"1.a".to_string()));
+    }
+
+    #[test]
+    fn test_copies_share_group_machines() {
+        let part = machine! {
+            name: "part", indentation: 0, mode: Backtracking;
+            start -> end*: scalar_state_machine(0);
+        };
+        let groups = |machine: &StateMachine| -> Vec<*const StateMachine> {
+            machine
+                .transitions()
+                .iter()
+                .filter_map(|transition| match transition.kind() {
+                    TransitionKind::Group(inner) => Some(inner as *const StateMachine),
+                    _ => None,
+                })
+                .collect()
+        };
+        let original = groups(&part)[0];
+        let copies = groups(&part.concat(&part).repeat(1, Some(2)));
+        assert_eq!(copies.len(), 4);
+        assert!(copies.iter().all(|copy| *copy == original));
+    }
+
+    #[test]
+    fn test_sequence_assembled_from_parts() {
+        let item = machine! {
+            name: "item", indentation: 0;
+            start -> tick: '-';
+            tick -> value*: scalar_state_machine(0), capture: "sequence_item";
+        };
+        let newline = machine! {
+            name: "newline", indentation: 0;
+            start -> end*: '\n' => CONSERVE;
+        };
+        let sequence = item.concat(&newline.concat(&item).star());
+        let handwritten = sequence_state_machine(0);
+        for input in ["-a", "-a\n-b\n-c", "-a\n", "a", "-a\n-", "-a\n -b"] {
+            assert_eq!(
+                sequence.check(input),
+                handwritten.check(input),
+                "{:?}",
+                input
+            );
+        }
+        let buffer = "-a\n-b";
+        let items: Vec<&str> = sequence
+            .captures(buffer)
+            .unwrap()
+            .iter()
+            .map(|span| span.text(buffer))
+            .collect();
+        assert_eq!(items, vec!["a", "b"]);
+    }
+}
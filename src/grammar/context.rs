@@ -0,0 +1,314 @@
+use core::fmt;
+use std::{collections::HashMap, sync::Arc};
+
+use super::{
+    error::RunError, indentation::Indentation, registry::Grammar, state_machine::StateMachine,
+    trace::TraceStep,
+};
+
+/// A group run is identified by the address of the inner machine, the offset
+/// and the indentation it starts at.
+type MemoKey = (usize, usize, Indentation);
+
+fn memo_key(machine: &StateMachine, offset: usize, indentation: &Indentation) -> MemoKey {
+    (
+        machine as *const StateMachine as usize,
+        offset,
+        indentation.clone(),
+    )
+}
+
+/// How often memoized group runs were reused.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+impl fmt::Display for MemoStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} hits, {} misses", self.hits, self.misses)
+    }
+}
+
+/// State shared by a run and by every group machine it enters.
+#[derive(Debug, Default)]
+pub struct RunContext {
+    /// How many group transitions deep the run currently is.
+    depth: usize,
+    /// Recorded steps, when tracing was asked for.
+    trace: Option<Vec<TraceStep>>,
+    /// Accepting ends of the group runs done so far, when memoizing.
+    memo: Option<HashMap<MemoKey, Vec<usize>>>,
+    stats: MemoStats,
+    /// Where rule transitions are resolved.
+    grammar: Option<Grammar>,
+    /// Group runs in progress, outermost first.
+    active: Vec<MemoKey>,
+    /// Transitions taken so far in the run.
+    steps: usize,
+    max_steps: Option<usize>,
+    /// Why the run was aborted, if it was.
+    error: Option<RunError>,
+}
+
+impl RunContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records every step taken.
+    pub fn record_trace(&mut self) -> &mut Self {
+        self.trace.get_or_insert_with(Vec::new);
+        self
+    }
+
+    /// Remembers the outcome of every group run so a group tried again at the
+    /// same offset and indentation is not run twice.
+    pub fn memoize(&mut self) -> &mut Self {
+        self.memo.get_or_insert_with(HashMap::new);
+        self
+    }
+
+    /// Resolves rule transitions against `grammar`.
+    pub fn with_grammar(&mut self, grammar: &Grammar) -> &mut Self {
+        self.grammar = Some(grammar.clone());
+        self
+    }
+
+    pub fn grammar(&self) -> Option<&Grammar> {
+        self.grammar.as_ref()
+    }
+
+    /// The machine of the rule named `name`, if the run has a grammar defining it.
+    pub fn rule(&self, name: &str) -> Option<Arc<StateMachine>> {
+        self.grammar.as_ref()?.rule(name).cloned()
+    }
+
+    /// Aborts the run once it has taken more than `limit` transitions.
+    pub fn max_steps(&mut self, limit: usize) -> &mut Self {
+        self.max_steps = Some(limit);
+        self
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Called when a group transition starts running `machine` from `offset`.
+    /// Returns `false`, aborting the run, when that very run is already in
+    /// progress: it would recurse forever without consuming input.
+    pub fn enter_group(
+        &mut self,
+        machine: &StateMachine,
+        offset: usize,
+        indentation: &Indentation,
+    ) -> bool {
+        let key = memo_key(machine, offset, indentation);
+        if self.active.contains(&key) {
+            self.abort(RunError::NoProgress {
+                machine: machine.name().to_string(),
+                state: machine.start().label.clone(),
+                offset,
+            });
+            return false;
+        }
+        self.active.push(key);
+        self.depth += 1;
+        true
+    }
+
+    /// Called when a group transition is done with its inner machine.
+    pub fn leave_group(&mut self) {
+        self.active.pop();
+        self.depth -= 1;
+    }
+
+    /// Counts a transition taken at `offset`. Returns `false`, aborting the run,
+    /// once the step limit is exceeded.
+    pub fn count_step(&mut self, offset: usize) -> bool {
+        self.steps += 1;
+        match self.max_steps {
+            Some(limit) if self.steps > limit => {
+                self.abort(RunError::StepLimit { limit, offset });
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Stops the run. Only the first reason is kept.
+    pub fn abort(&mut self, error: RunError) {
+        self.error.get_or_insert(error);
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.error.is_some()
+    }
+
+    pub fn error(&self) -> Option<&RunError> {
+        self.error.as_ref()
+    }
+
+    /// Why the run was aborted, leaving the context ready for another run.
+    pub fn take_error(&mut self) -> Option<RunError> {
+        self.error.take()
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    /// Records `step` if tracing. Callers check `is_tracing` first so untraced
+    /// runs do not build steps.
+    pub fn record(&mut self, step: TraceStep) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(step);
+        }
+    }
+
+    /// The steps recorded so far, leaving the trace empty.
+    pub fn take_trace(&mut self) -> Vec<TraceStep> {
+        self.trace.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    pub fn is_memoizing(&self) -> bool {
+        self.memo.is_some()
+    }
+
+    /// The remembered accepting ends of `machine` run from `offset`.
+    pub fn recall(
+        &mut self,
+        machine: &StateMachine,
+        offset: usize,
+        indentation: &Indentation,
+    ) -> Option<&[usize]> {
+        let memo = self.memo.as_ref()?;
+        match memo.get(&memo_key(machine, offset, indentation)) {
+            Some(ends) => {
+                self.stats.hits += 1;
+                Some(ends)
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn remember(
+        &mut self,
+        machine: &StateMachine,
+        offset: usize,
+        indentation: &Indentation,
+        ends: Vec<usize>,
+    ) {
+        if let Some(memo) = &mut self.memo {
+            memo.insert(memo_key(machine, offset, indentation), ends);
+        }
+    }
+
+    /// Forgets remembered group runs, as they are only valid for the input they
+    /// were computed on, and resets the step count and abort reason. Called
+    /// whenever a new run starts.
+    pub fn start_run(&mut self) {
+        if let Some(memo) = &mut self.memo {
+            memo.clear();
+        }
+        self.active.clear();
+        self.steps = 0;
+        self.error = None;
+    }
+
+    pub fn memo_stats(&self) -> MemoStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        grammar::{
+            state::create_state,
+            state_machine::{ExecutionMode, StateMachine, StateMachineBuilder},
+            transition::{CharTransition, GroupTransition, IndentationOperation},
+        },
+        yaml::{document::document_state_machine, scalar::scalar_state_machine},
+    };
+
+    use super::{MemoStats, RunContext};
+
+    // a scalar followed by either `#` or `;`, both branches sharing the scalar machine
+    fn branching_machine() -> StateMachine {
+        let scalar = Arc::new(scalar_state_machine(0));
+        let start = Arc::new(create_state(false, "start"));
+        let comment = Arc::new(create_state(false, "comment"));
+        let statement = Arc::new(create_state(false, "statement"));
+        let end = Arc::new(create_state(true, "end"));
+        StateMachineBuilder::new(start.clone(), " ", 0)
+            .mode(ExecutionMode::Backtracking)
+            .add_states(vec![comment.clone(), statement.clone(), end.clone()])
+            .add_transitions(vec![
+                Arc::new(GroupTransition::shared(
+                    start.clone(),
+                    comment.clone(),
+                    scalar.clone(),
+                    IndentationOperation::BYPASS,
+                )),
+                Arc::new(GroupTransition::shared(
+                    start,
+                    statement.clone(),
+                    scalar,
+                    IndentationOperation::BYPASS,
+                )),
+                Arc::new(CharTransition::new(
+                    comment,
+                    end.clone(),
+                    "#".to_string(),
+                    IndentationOperation::BYPASS,
+                )),
+                Arc::new(CharTransition::new(
+                    statement,
+                    end,
+                    ";".to_string(),
+                    IndentationOperation::BYPASS,
+                )),
+            ])
+            .build()
+    }
+
+    #[test]
+    fn test_memoized_run_reuses_shared_group() {
+        let machine = branching_machine();
+        let mut context = RunContext::new();
+        context.memoize();
+        assert_eq!(machine.validate_with("ab;", &mut context), (true, 3));
+        assert_eq!(context.memo_stats(), MemoStats { hits: 1, misses: 1 });
+
+        // a new run does not reuse results computed on another input
+        assert_eq!(machine.validate_with("ab#", &mut context), (true, 3));
+        assert_eq!(context.memo_stats(), MemoStats { hits: 2, misses: 2 });
+    }
+
+    #[test]
+    fn test_memoized_run_matches_plain_run() {
+        let machine = document_state_machine(0);
+        for buffer in [
+            "---\ntest:test\n---",
+            "---\nzob:test\nlist:\n -zob\n -zizi\n---",
+            "---\nzob:test\ntest:\n zob:\n  -\n---",
+        ] {
+            let mut context = RunContext::new();
+            context.memoize();
+            assert_eq!(
+                machine.validate_with(buffer, &mut context),
+                machine.validate(buffer)
+            );
+        }
+        let mut plain = RunContext::new();
+        machine.validate_with("---\ntest:test\n---", &mut plain);
+        assert_eq!(plain.memo_stats(), MemoStats::default());
+    }
+}
@@ -0,0 +1,416 @@
+use std::{collections::HashMap, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    class::{category_from_abbreviation, CharClass, CharClassBuilder},
+    error::{DefinitionError, GrammarError},
+    state::{create_state, State},
+    state_machine::{ExecutionMode, MatchStrategy, StateMachine, StateMachineBuilder},
+    transition::{
+        CaptureTransition, CharTransition, ClassTransition, EpsilonTransition, GroupTransition,
+        IndentationOperation, Lookahead, LookaheadTransition, NegativeLookaheadTransition,
+        RuleTransition, Transition, TransitionKind,
+    },
+};
+
+/// Version of the definition format written by `StateMachine::to_json`.
+pub const DEFINITION_VERSION: u32 = 1;
+
+/// A machine as written to JSON, along with the version of the format.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Definition {
+    pub version: u32,
+    pub machine: MachineDefinition,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MachineDefinition {
+    pub name: String,
+    pub mode: ExecutionMode,
+    pub strategy: MatchStrategy,
+    pub indentation_character: String,
+    pub indentation: i32,
+    /// The start state comes first.
+    pub states: Vec<StateDefinition>,
+    /// Grouped by source state, in the order they are tried.
+    pub transitions: Vec<TransitionDefinition>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateDefinition {
+    pub label: String,
+    #[serde(rename = "final")]
+    pub is_final: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransitionDefinition {
+    /// Label of the state the transition leaves.
+    pub from: String,
+    /// Label of the state the transition reaches.
+    pub to: String,
+    #[serde(flatten)]
+    pub matches: MatchDefinition,
+    pub operation: IndentationOperation,
+    pub priority: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture: Option<String>,
+}
+
+/// What a transition matches, along with its payload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MatchDefinition {
+    Char {
+        value: String,
+    },
+    Class {
+        class: ClassDefinition,
+    },
+    Epsilon,
+    Group {
+        machine: Box<MachineDefinition>,
+    },
+    Rule {
+        rule: String,
+    },
+    Lookahead {
+        negative: bool,
+        condition: LookaheadDefinition,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LookaheadDefinition {
+    Char { value: char },
+    Class { class: ClassDefinition },
+    Machine { machine: Box<MachineDefinition> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClassDefinition {
+    pub ranges: Vec<(char, char)>,
+    /// Abbreviations of the Unicode general categories, such as `Lu`.
+    pub categories: Vec<String>,
+    pub negated: bool,
+}
+
+impl StateMachine {
+    /// Writes the machine and the machines of its groups as a JSON definition.
+    pub fn to_json(&self) -> String {
+        let definition = Definition {
+            version: DEFINITION_VERSION,
+            machine: self.definition(),
+        };
+        serde_json::to_string_pretty(&definition).expect("a definition is always serialisable")
+    }
+
+    /// Reads a machine written by `to_json`.
+    pub fn from_json(json: &str) -> Result<StateMachine, DefinitionError> {
+        let definition: Definition =
+            serde_json::from_str(json).map_err(|error| DefinitionError::Json {
+                message: error.to_string(),
+            })?;
+        if definition.version != DEFINITION_VERSION {
+            return Err(DefinitionError::UnsupportedVersion {
+                version: definition.version,
+            });
+        }
+        StateMachine::from_definition(&definition.machine)
+    }
+
+    pub fn definition(&self) -> MachineDefinition {
+        let states = self.states();
+        let mut transitions = Vec::new();
+        for (id, state) in states.iter().enumerate() {
+            let outgoing = self.outgoing(id).iter().zip(self.targets(id));
+            for ((transition, target), priority) in outgoing.zip(self.priorities(id)) {
+                transitions.push(TransitionDefinition {
+                    from: state.label.clone(),
+                    to: states[*target].label.clone(),
+                    matches: match_definition(transition.kind()),
+                    operation: transition.indentation_operation(),
+                    priority: *priority,
+                    capture: transition.capture().map(str::to_string),
+                });
+            }
+        }
+        MachineDefinition {
+            name: self.name().to_string(),
+            mode: self.mode(),
+            strategy: self.strategy(),
+            indentation_character: self.indentation_character().to_string(),
+            indentation: self.current_indentation(),
+            states: states
+                .iter()
+                .map(|state| StateDefinition {
+                    label: state.label.clone(),
+                    is_final: state.is_final(),
+                })
+                .collect(),
+            transitions,
+        }
+    }
+
+    pub fn from_definition(
+        definition: &MachineDefinition,
+    ) -> Result<StateMachine, DefinitionError> {
+        let mut states: HashMap<&str, Arc<State>> = HashMap::new();
+        let mut ordered = Vec::new();
+        for state in &definition.states {
+            let created = Arc::new(create_state(state.is_final, &state.label));
+            if states.insert(&state.label, created.clone()).is_some() {
+                return Err(DefinitionError::Machine(GrammarError::DuplicateLabel {
+                    machine: definition.name.clone(),
+                    label: state.label.clone(),
+                }));
+            }
+            ordered.push(created);
+        }
+        let state = |label: &String| {
+            states.get(label.as_str()).cloned().ok_or_else(|| {
+                DefinitionError::Machine(GrammarError::UnknownState {
+                    machine: definition.name.clone(),
+                    state: label.clone(),
+                })
+            })
+        };
+
+        let start = ordered
+            .first()
+            .cloned()
+            .ok_or_else(|| DefinitionError::Json {
+                message: format!("machine {} has no start state", definition.name),
+            })?;
+        let mut builder = StateMachineBuilder::new(
+            start,
+            &definition.indentation_character,
+            definition.indentation,
+        );
+        builder
+            .name(&definition.name)
+            .mode(definition.mode)
+            .strategy(definition.strategy)
+            .add_states(ordered[1..].to_vec());
+        for transition in &definition.transitions {
+            let (from, to) = (state(&transition.from)?, state(&transition.to)?);
+            let operation = transition.operation.clone();
+            let mut built: Arc<dyn Transition> = match &transition.matches {
+                MatchDefinition::Char { value } => {
+                    Arc::new(CharTransition::new(from, to, value.clone(), operation))
+                }
+                MatchDefinition::Class { class } => Arc::new(ClassTransition::new(
+                    from,
+                    to,
+                    build_class(class)?,
+                    operation,
+                )),
+                MatchDefinition::Epsilon => Arc::new(EpsilonTransition::new(from, to)),
+                MatchDefinition::Group { machine } => Arc::new(GroupTransition::new(
+                    from,
+                    to,
+                    StateMachine::from_definition(machine)?,
+                    operation,
+                )),
+                MatchDefinition::Rule { rule } => {
+                    Arc::new(RuleTransition::new(from, to, rule, operation))
+                }
+                MatchDefinition::Lookahead {
+                    negative,
+                    condition,
+                } => {
+                    let condition = build_lookahead(condition)?;
+                    if *negative {
+                        Arc::new(NegativeLookaheadTransition::new(from, to, condition))
+                    } else {
+                        Arc::new(LookaheadTransition::new(from, to, condition))
+                    }
+                }
+            };
+            if let Some(label) = &transition.capture {
+                built = Arc::new(CaptureTransition::new(label, built));
+            }
+            builder.add_transition_with_priority(built, transition.priority);
+        }
+        Ok(builder.build())
+    }
+}
+
+fn match_definition(kind: TransitionKind) -> MatchDefinition {
+    match kind {
+        TransitionKind::Char(value) => MatchDefinition::Char {
+            value: value.to_string(),
+        },
+        TransitionKind::Class(class) => MatchDefinition::Class {
+            class: class_definition(class),
+        },
+        TransitionKind::Epsilon => MatchDefinition::Epsilon,
+        TransitionKind::Group(machine) => MatchDefinition::Group {
+            machine: Box::new(machine.definition()),
+        },
+        TransitionKind::Rule(rule) => MatchDefinition::Rule {
+            rule: rule.to_string(),
+        },
+        TransitionKind::Lookahead {
+            condition,
+            negative,
+        } => MatchDefinition::Lookahead {
+            negative,
+            condition: match condition {
+                Lookahead::Char(value) => LookaheadDefinition::Char { value: *value },
+                Lookahead::Class(class) => LookaheadDefinition::Class {
+                    class: class_definition(class),
+                },
+                Lookahead::Machine(machine) => LookaheadDefinition::Machine {
+                    machine: Box::new(machine.definition()),
+                },
+            },
+        },
+    }
+}
+
+fn class_definition(class: &CharClass) -> ClassDefinition {
+    ClassDefinition {
+        ranges: class.ranges().to_vec(),
+        categories: class
+            .categories()
+            .iter()
+            .map(|category| category.abbreviation().to_string())
+            .collect(),
+        negated: class.is_negated(),
+    }
+}
+
+fn build_class(definition: &ClassDefinition) -> Result<CharClass, DefinitionError> {
+    let mut builder = CharClassBuilder::new();
+    for (low, high) in &definition.ranges {
+        builder.add_range(*low, *high);
+    }
+    for abbreviation in &definition.categories {
+        let category = category_from_abbreviation(abbreviation).ok_or_else(|| {
+            DefinitionError::UnknownCategory {
+                category: abbreviation.clone(),
+            }
+        })?;
+        builder.add_category(category);
+    }
+    if definition.negated {
+        builder.negate();
+    }
+    Ok(builder.build())
+}
+
+fn build_lookahead(definition: &LookaheadDefinition) -> Result<Lookahead, DefinitionError> {
+    Ok(match definition {
+        LookaheadDefinition::Char { value } => Lookahead::Char(*value),
+        LookaheadDefinition::Class { class } => Lookahead::Class(build_class(class)?),
+        LookaheadDefinition::Machine { machine } => {
+            Lookahead::Machine(Arc::new(StateMachine::from_definition(machine)?))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use unicode_general_category::GeneralCategory;
+
+    use crate::{
+        grammar::{
+            class::CharClassBuilder,
+            error::{DefinitionError, GrammarError},
+            state::create_state,
+            state_machine::{MatchStrategy, StateMachine, StateMachineBuilder},
+            transition::{CaptureTransition, Lookahead, NegativeLookaheadTransition},
+        },
+        yaml::document::document_state_machine,
+    };
+
+    #[test]
+    fn test_round_tripped_document_machine_behaves_the_same() {
+        let machine = document_state_machine(0);
+        let json = machine.to_json();
+        let imported = StateMachine::from_json(&json).unwrap();
+        assert_eq!(imported.to_json(), json);
+        for buffer in [
+            "---\ntest:test\n---",
+            "---\nzob:test\nlist:\n -zob\n -zizi\n---",
+            "---\nzob:test\ntest:\n zob:\n  -test\n---",
+            "---\nzob:test\ntest:\n zob:\n  -\n---",
+            "---\nkey:\n   nested:x\n---",
+            "---\nlist:\n -a\n\t-b\n---",
+            "---\nclé:valeur\n---",
+            "---\ntest:te;st\n---",
+            "---\n",
+            "",
+        ] {
+            assert_eq!(
+                imported.validate(buffer),
+                machine.validate(buffer),
+                "{:?}",
+                buffer
+            );
+            assert_eq!(imported.captures(buffer), machine.captures(buffer));
+        }
+    }
+
+    #[test]
+    fn test_definition_keeps_classes_lookaheads_and_priorities() {
+        let start = Arc::new(create_state(false, "start"));
+        let end = Arc::new(create_state(true, "end"));
+        let class = CharClassBuilder::new()
+            .add_range('0', '9')
+            .add_category(GeneralCategory::UppercaseLetter)
+            .negate()
+            .build();
+        let machine = StateMachineBuilder::new(start.clone(), " ", 0)
+            .name("sample")
+            .strategy(MatchStrategy::LongestMatch)
+            .add_state(end.clone())
+            .add_transition_with_priority(
+                Arc::new(CaptureTransition::new(
+                    "word",
+                    Arc::new(NegativeLookaheadTransition::new(
+                        start,
+                        end,
+                        Lookahead::Class(class),
+                    )),
+                )),
+                3,
+            )
+            .build();
+        let imported = StateMachine::from_json(&machine.to_json()).unwrap();
+        assert_eq!(imported.definition(), machine.definition());
+        assert_eq!(imported.strategy(), MatchStrategy::LongestMatch);
+        assert_eq!(imported.priorities(0), &[3]);
+        assert_eq!(imported.transitions()[0].capture(), Some("word"));
+        assert_eq!(
+            imported.transitions()[0].kind().to_string(),
+            "![^0-9\\p{Lu}]"
+        );
+    }
+
+    #[test]
+    fn test_invalid_definitions_are_reported() {
+        let json = document_state_machine(0).to_json();
+        assert_eq!(
+            StateMachine::from_json(&json.replacen("\"version\": 1", "\"version\": 7", 1)).err(),
+            Some(DefinitionError::UnsupportedVersion { version: 7 })
+        );
+        assert_eq!(
+            StateMachine::from_json(&json.replacen("\"to\": \"header\"", "\"to\": \"nowhere\"", 1))
+                .err(),
+            Some(DefinitionError::Machine(GrammarError::UnknownState {
+                machine: "document".to_string(),
+                state: "nowhere".to_string()
+            }))
+        );
+        assert!(matches!(
+            StateMachine::from_json("{\"version\": 1}"),
+            Err(DefinitionError::Json { .. })
+        ));
+    }
+}
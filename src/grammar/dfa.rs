@@ -0,0 +1,914 @@
+use core::fmt;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::Arc,
+};
+
+use super::{
+    class::{complement, normalize},
+    error::DeterminizeError,
+    state::State,
+    state_machine::{ExecutionMode, StateMachine},
+    transition::{IndentationOperation, Lookahead, TransitionKind},
+};
+
+/// Where a state accepts: at the end of the input and before which characters.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+struct Acceptance {
+    end: bool,
+    /// Sorted, disjoint ranges.
+    before: Vec<(char, char)>,
+}
+
+impl Acceptance {
+    fn always() -> Self {
+        Acceptance {
+            end: true,
+            before: vec![('\0', char::MAX)],
+        }
+    }
+
+    /// Where a char or class lookahead holds.
+    fn of(condition: &Lookahead, negative: bool) -> Option<Self> {
+        let ranges = match condition {
+            Lookahead::Char(c) => vec![(*c, *c)],
+            Lookahead::Class(class) => class.to_ranges(),
+            Lookahead::Machine(_) => return None,
+        };
+        Some(match negative {
+            true => Acceptance {
+                end: true,
+                before: complement(&ranges),
+            },
+            false => Acceptance {
+                end: false,
+                before: ranges,
+            },
+        })
+    }
+
+    fn add(&mut self, other: &Acceptance) {
+        self.end |= other.end;
+        self.before = normalize([self.before.as_slice(), &other.before].concat());
+    }
+
+    fn holds(&self, next: Option<char>) -> bool {
+        match next {
+            None => self.end,
+            Some(c) => {
+                let index = self.before.partition_point(|(_, high)| *high < c);
+                self.before.get(index).is_some_and(|(low, _)| *low <= c)
+            }
+        }
+    }
+}
+
+/// A deterministic automaton with a dense transition table, compiled from a
+/// `StateMachine` made of char and epsilon transitions only. Char and class
+/// lookaheads are supported when nothing but epsilon transitions follows them,
+/// like the one `$` compiles to in `StateMachine::from_regex`.
+///
+/// Input characters are grouped into disjoint ranges and each range gets a column
+/// in the table, so a run costs one lookup per character.
+#[derive(Debug, Clone)]
+pub struct Dfa {
+    /// Sorted, disjoint character ranges, one table column each.
+    ranges: Vec<(char, char)>,
+    /// `table[state * ranges.len() + column]` is the next state, if any.
+    table: Vec<Option<u32>>,
+    accepts: Vec<Acceptance>,
+    /// Labels of the source states each DFA state stands for.
+    labels: Vec<String>,
+}
+
+impl Dfa {
+    /// Builds a DFA accepting the same language as `machine` using subset construction.
+    pub fn from_machine(machine: &StateMachine) -> Result<Dfa, DeterminizeError> {
+        if machine.mode() == ExecutionMode::Deterministic {
+            check_first_match(machine)?;
+        }
+        let mut ids: HashMap<Arc<State>, usize> = HashMap::new();
+        let mut states: Vec<Arc<State>> = Vec::new();
+        let mut id_of = |state: &Arc<State>| -> usize {
+            *ids.entry(state.clone()).or_insert_with(|| {
+                states.push(state.clone());
+                states.len() - 1
+            })
+        };
+
+        let start = id_of(machine.start());
+        for state in machine.states() {
+            id_of(state);
+        }
+
+        let mut epsilons: Vec<(usize, usize)> = Vec::new();
+        let mut moves: Vec<(usize, (char, char), usize)> = Vec::new();
+        let mut lookaheads: Vec<(usize, Acceptance, usize, String)> = Vec::new();
+        for transition in machine.transitions() {
+            let from = id_of(&transition.from());
+            let kind = transition.kind();
+            let ranges = match kind {
+                TransitionKind::Epsilon => {
+                    epsilons.push((from, id_of(&transition.target())));
+                    continue;
+                }
+                TransitionKind::Lookahead {
+                    condition,
+                    negative,
+                } => match Acceptance::of(condition, negative) {
+                    Some(acceptance) => {
+                        let to = id_of(&transition.target());
+                        lookaheads.push((from, acceptance, to, kind.to_string()));
+                        continue;
+                    }
+                    None => {
+                        return Err(DeterminizeError::UnsupportedTransition {
+                            from: transition.from().label.clone(),
+                            transition: kind.to_string(),
+                        })
+                    }
+                },
+                TransitionKind::Char(value) => {
+                    // a char transition only ever matches a single character
+                    let mut chars = value.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => vec![(c, c)],
+                        _ => Vec::new(),
+                    }
+                }
+                TransitionKind::Class(class) => class.to_ranges(),
+                kind => {
+                    return Err(DeterminizeError::UnsupportedTransition {
+                        from: transition.from().label.clone(),
+                        transition: kind.to_string(),
+                    })
+                }
+            };
+            match transition.indentation_operation() {
+                IndentationOperation::BYPASS | IndentationOperation::RESET => {}
+                _ => {
+                    return Err(DeterminizeError::UnsupportedTransition {
+                        from: transition.from().label.clone(),
+                        transition: kind.to_string(),
+                    })
+                }
+            }
+            let to = id_of(&transition.target());
+            moves.extend(ranges.into_iter().map(|range| (from, range, to)));
+        }
+
+        let ranges = partition(moves.iter().map(|(_, range, _)| *range));
+
+        let closure = |set: BTreeSet<usize>| -> BTreeSet<usize> {
+            let mut closed = set.clone();
+            let mut pending: Vec<usize> = set.into_iter().collect();
+            while let Some(state) = pending.pop() {
+                for (from, to) in &epsilons {
+                    if *from == state && closed.insert(*to) {
+                        pending.push(*to);
+                    }
+                }
+            }
+            closed
+        };
+
+        // a lookahead only decides whether the run accepts where it stands
+        let mut anchors: Vec<(usize, Acceptance)> = Vec::new();
+        for (from, acceptance, to, transition) in &lookaheads {
+            let reached = closure(BTreeSet::from([*to]));
+            let moving = moves.iter().map(|(from, _, _)| from);
+            let looking = lookaheads.iter().map(|(from, _, _, _)| from);
+            if moving.chain(looking).any(|state| reached.contains(state)) {
+                return Err(DeterminizeError::UnsupportedTransition {
+                    from: states[*from].label.clone(),
+                    transition: transition.clone(),
+                });
+            }
+            if reached.iter().any(|state| states[*state].is_final()) {
+                anchors.push((*from, acceptance.clone()));
+            }
+        }
+
+        let mut subsets: Vec<BTreeSet<usize>> = vec![closure(BTreeSet::from([start]))];
+        let mut subset_ids: HashMap<BTreeSet<usize>, usize> = HashMap::new();
+        subset_ids.insert(subsets[0].clone(), 0);
+        let mut table: Vec<Option<u32>> = Vec::new();
+
+        let mut current = 0;
+        while current < subsets.len() {
+            for (column, (symbol, _)) in ranges.iter().enumerate() {
+                let reached: BTreeSet<usize> = moves
+                    .iter()
+                    .filter(|(from, (low, high), _)| {
+                        low <= symbol && symbol <= high && subsets[current].contains(from)
+                    })
+                    .map(|(_, _, to)| *to)
+                    .collect();
+                debug_assert_eq!(table.len(), current * ranges.len() + column);
+                if reached.is_empty() {
+                    table.push(None);
+                    continue;
+                }
+                let reached = closure(reached);
+                let next = match subset_ids.get(&reached) {
+                    Some(id) => *id,
+                    None => {
+                        subsets.push(reached.clone());
+                        subset_ids.insert(reached, subsets.len() - 1);
+                        subsets.len() - 1
+                    }
+                };
+                table.push(Some(next as u32));
+            }
+            current += 1;
+        }
+
+        let accepts = subsets
+            .iter()
+            .map(|subset| {
+                if subset.iter().any(|state| states[*state].is_final()) {
+                    return Acceptance::always();
+                }
+                let mut acceptance = Acceptance::default();
+                for (from, condition) in &anchors {
+                    if subset.contains(from) {
+                        acceptance.add(condition);
+                    }
+                }
+                acceptance
+            })
+            .collect();
+        let labels = subsets
+            .iter()
+            .map(|subset| {
+                let labels: Vec<&str> = subset
+                    .iter()
+                    .map(|state| states[*state].label.as_str())
+                    .collect();
+                format!("{{{}}}", labels.join(","))
+            })
+            .collect();
+
+        Ok(Dfa {
+            ranges,
+            table,
+            accepts,
+            labels,
+        })
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.accepts.len()
+    }
+
+    pub fn transition_count(&self) -> usize {
+        self.table.iter().filter(|next| next.is_some()).count()
+    }
+
+    /// Whether `state` accepts at the end of the input.
+    pub fn is_final(&self, state: usize) -> bool {
+        self.accepts[state].end
+    }
+
+    /// Whether `state` accepts before `next`, or at the end of the input when
+    /// `next` is `None`.
+    pub fn accepts(&self, state: usize, next: Option<char>) -> bool {
+        self.accepts[state].holds(next)
+    }
+
+    pub fn label(&self, state: usize) -> &str {
+        &self.labels[state]
+    }
+
+    /// The state reached from `state` on `c`, if any. The start state is `0`.
+    pub fn next(&self, state: usize, c: char) -> Option<usize> {
+        let column = self.column(c)?;
+        self.table[state * self.ranges.len() + column].map(|next| next as usize)
+    }
+
+    fn column(&self, c: char) -> Option<usize> {
+        let column = self.ranges.partition_point(|(_, high)| *high < c);
+        match self.ranges.get(column) {
+            Some((low, _)) if *low <= c => Some(column),
+            _ => None,
+        }
+    }
+
+    /// Runs the automaton over `buffer` and returns the longest accepting run, like
+    /// a backtracking `StateMachine` would. On failure the offset is where the run
+    /// got stuck.
+    pub fn validate(&self, buffer: &str) -> (bool, usize) {
+        let mut state = 0;
+        let mut longest = None;
+        let mut offset = 0;
+        let mut chars = buffer.char_indices().peekable();
+        while let Some((index, c)) = chars.next() {
+            match self.next(state, c) {
+                Some(next) => {
+                    state = next;
+                    offset = index + c.len_utf8();
+                    if self.accepts(state, chars.peek().map(|(_, c)| *c)) {
+                        longest = Some(offset);
+                    }
+                }
+                None => break,
+            }
+        }
+        match longest {
+            Some(longest) => (true, longest),
+            None => (false, offset),
+        }
+    }
+
+    /// Whether the whole buffer is accepted. Like `StateMachine::check`, an empty
+    /// buffer never is.
+    pub fn check(&self, buffer: &str) -> bool {
+        let (validated, offset) = self.validate(buffer);
+        validated && offset == buffer.len()
+    }
+
+    /// Merges equivalent states with Hopcroft's partition refinement, then merges
+    /// adjacent character ranges that every state treats alike.
+    pub fn minimize(&self) -> (Dfa, MinimizeStats) {
+        let columns = self.ranges.len();
+        // an explicit dead state makes the automaton complete
+        let dead = self.state_count();
+        let states = dead + 1;
+        let next = |state: usize, column: usize| -> usize {
+            if state == dead {
+                return dead;
+            }
+            self.table[state * columns + column].map_or(dead, |next| next as usize)
+        };
+
+        let mut inverse: Vec<Vec<Vec<usize>>> = vec![vec![Vec::new(); states]; columns];
+        for state in 0..states {
+            for (column, predecessors) in inverse.iter_mut().enumerate() {
+                predecessors[next(state, column)].push(state);
+            }
+        }
+
+        // states start in one block per way of accepting, the dead state along
+        // with the ones that never accept
+        let never = Acceptance::default();
+        let mut blocks: Vec<Vec<usize>> = Vec::new();
+        let mut block_ids: HashMap<&Acceptance, usize> = HashMap::new();
+        let mut block_of: Vec<usize> = (0..states)
+            .map(|state| {
+                let acceptance = self.accepts.get(state).unwrap_or(&never);
+                let block = *block_ids.entry(acceptance).or_insert_with(|| {
+                    blocks.push(Vec::new());
+                    blocks.len() - 1
+                });
+                blocks[block].push(state);
+                block
+            })
+            .collect();
+
+        // every block but the largest one splits the others
+        let largest = (0..blocks.len())
+            .max_by_key(|block| blocks[*block].len())
+            .unwrap_or(0);
+        let mut pending: Vec<(usize, usize)> = (0..blocks.len())
+            .filter(|block| blocks.len() == 1 || *block != largest)
+            .flat_map(|block| (0..columns).map(move |c| (block, c)))
+            .collect();
+        let mut in_pending: HashSet<(usize, usize)> = pending.iter().cloned().collect();
+
+        while let Some((splitter, column)) = pending.pop() {
+            in_pending.remove(&(splitter, column));
+
+            let mut touched: HashMap<usize, Vec<usize>> = HashMap::new();
+            for target in blocks[splitter].clone() {
+                for state in &inverse[column][target] {
+                    touched.entry(block_of[*state]).or_default().push(*state);
+                }
+            }
+
+            for (block, mut moved) in touched {
+                moved.sort_unstable();
+                moved.dedup();
+                if moved.len() == blocks[block].len() {
+                    continue;
+                }
+                let split = blocks.len();
+                blocks[block].retain(|state| moved.binary_search(state).is_err());
+                for state in &moved {
+                    block_of[*state] = split;
+                }
+                blocks.push(moved);
+
+                for c in 0..columns {
+                    if in_pending.contains(&(block, c)) {
+                        pending.push((split, c));
+                        in_pending.insert((split, c));
+                    } else {
+                        let smaller = if blocks[split].len() < blocks[block].len() {
+                            split
+                        } else {
+                            block
+                        };
+                        pending.push((smaller, c));
+                        in_pending.insert((smaller, c));
+                    }
+                }
+            }
+        }
+
+        // renumber the live blocks breadth first from the start state
+        let dead_block = block_of[dead];
+        let mut ids: HashMap<usize, usize> = HashMap::new();
+        let mut order: Vec<usize> = Vec::new();
+        let start_block = block_of[0];
+        if start_block != dead_block {
+            ids.insert(start_block, 0);
+            order.push(start_block);
+        }
+        let mut current = 0;
+        while current < order.len() {
+            let representative = blocks[order[current]][0];
+            for column in 0..columns {
+                let target = block_of[next(representative, column)];
+                if target != dead_block && !ids.contains_key(&target) {
+                    ids.insert(target, order.len());
+                    order.push(target);
+                }
+            }
+            current += 1;
+        }
+
+        let mut table: Vec<Option<u32>> = Vec::with_capacity(order.len() * columns);
+        let mut accepts = Vec::with_capacity(order.len());
+        let mut labels = Vec::with_capacity(order.len());
+        for block in &order {
+            let representative = blocks[*block][0];
+            for column in 0..columns {
+                let target = block_of[next(representative, column)];
+                table.push(ids.get(&target).map(|id| *id as u32));
+            }
+            accepts.push(self.accepts[representative].clone());
+            let mut members = blocks[*block].clone();
+            members.sort_unstable();
+            let members: Vec<&str> = members.iter().map(|state| self.label(*state)).collect();
+            labels.push(members.join("|"));
+        }
+        if order.is_empty() {
+            // nothing is accepted: keep a lone, rejecting start state
+            table = vec![None; columns];
+            accepts.push(never);
+            labels.push(self.label(0).to_string());
+        }
+
+        let minimized = Dfa {
+            ranges: self.ranges.clone(),
+            table,
+            accepts,
+            labels,
+        }
+        .merge_ranges();
+
+        let stats = MinimizeStats {
+            states_before: self.state_count(),
+            states_after: minimized.state_count(),
+            transitions_before: self.transition_count(),
+            transitions_after: minimized.transition_count(),
+        };
+        (minimized, stats)
+    }
+
+    /// Merges adjacent ranges whose columns are identical in every state.
+    fn merge_ranges(self) -> Dfa {
+        let columns = self.ranges.len();
+        let column_of = |column: usize| -> Vec<Option<u32>> {
+            (0..self.state_count())
+                .map(|state| self.table[state * columns + column])
+                .collect()
+        };
+
+        let mut kept: Vec<usize> = Vec::new();
+        let mut ranges: Vec<(char, char)> = Vec::new();
+        for (column, (low, high)) in self.ranges.iter().enumerate() {
+            if let (Some(previous), Some(last)) = (kept.last(), ranges.last_mut()) {
+                let adjacent = last.1 as u32 + 1 == *low as u32;
+                if adjacent && column_of(*previous) == column_of(column) {
+                    last.1 = *high;
+                    continue;
+                }
+            }
+            kept.push(column);
+            ranges.push((*low, *high));
+        }
+
+        let mut table = Vec::with_capacity(self.state_count() * kept.len());
+        for state in 0..self.state_count() {
+            for column in &kept {
+                table.push(self.table[state * columns + column]);
+            }
+        }
+        Dfa {
+            ranges,
+            table,
+            accepts: self.accepts,
+            labels: self.labels,
+        }
+    }
+
+    /// Whether both automata accept exactly the same inputs, checked by walking
+    /// their product over a common refinement of the two sets of ranges.
+    pub fn equivalent(&self, other: &Dfa) -> bool {
+        let mut bounds: Vec<u32> = Vec::new();
+        for (low, high) in self.ranges.iter().chain(other.ranges.iter()) {
+            bounds.push(*low as u32);
+            bounds.push(*high as u32 + 1);
+        }
+        bounds.sort_unstable();
+        bounds.dedup();
+        // one representative character per interval between two bounds
+        let symbols: Vec<char> = bounds
+            .windows(2)
+            .filter_map(|window| (window[0]..window[1]).find_map(char::from_u32))
+            .collect();
+
+        let never = Acceptance::default();
+        let accepts = |dfa: &Dfa, state: Option<usize>| {
+            state.map_or(never.clone(), |state| dfa.accepts[state].clone())
+        };
+        let mut visited: HashSet<(Option<usize>, Option<usize>)> = HashSet::new();
+        let mut pending = vec![(Some(0), Some(0))];
+        while let Some((left, right)) = pending.pop() {
+            if !visited.insert((left, right)) {
+                continue;
+            }
+            if accepts(self, left) != accepts(other, right) {
+                return false;
+            }
+            for c in &symbols {
+                let next = (
+                    left.and_then(|state| self.next(state, *c)),
+                    right.and_then(|state| other.next(state, *c)),
+                );
+                if next != (None, None) {
+                    pending.push(next);
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Checks that a deterministic run of `machine` follows the only path the
+/// input allows and keeps accepting once it did, so it ends where the longest
+/// run does. Transitions the `Dfa` cannot compile are left to `from_machine`.
+fn check_first_match(machine: &StateMachine) -> Result<(), DeterminizeError> {
+    let states = machine.states();
+    for (id, state) in states.iter().enumerate() {
+        let error = || DeterminizeError::FirstMatch {
+            machine: machine.name().to_string(),
+            state: state.label.clone(),
+        };
+        let outgoing = machine.outgoing(id);
+        if state.is_final() && machine.targets(id).iter().any(|to| !states[*to].is_final()) {
+            return Err(error());
+        }
+        let mut ranges = Vec::new();
+        for transition in outgoing {
+            match transition.kind() {
+                TransitionKind::Epsilon | TransitionKind::Lookahead { .. }
+                    if outgoing.len() > 1 =>
+                {
+                    return Err(error())
+                }
+                TransitionKind::Char(value) => ranges.extend(value.chars().map(|c| (c, c))),
+                TransitionKind::Class(class) => ranges.extend(class.to_ranges()),
+                _ => {}
+            }
+        }
+        ranges.sort_unstable();
+        if ranges.windows(2).any(|pair| pair[1].0 <= pair[0].1) {
+            return Err(error());
+        }
+    }
+    Ok(())
+}
+
+/// Splits the union of `ranges` into sorted, disjoint ranges such that each of
+/// them lies either entirely inside or entirely outside every input range.
+fn partition(ranges: impl Iterator<Item = (char, char)>) -> Vec<(char, char)> {
+    let ranges: Vec<(char, char)> = ranges.collect();
+    let mut bounds: Vec<u32> = Vec::new();
+    for (low, high) in &ranges {
+        bounds.push(*low as u32);
+        bounds.push(*high as u32 + 1);
+    }
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut parts = Vec::new();
+    for window in bounds.windows(2) {
+        // skip the surrogate code points, which are not characters
+        let low = (window[0]..window[1]).find_map(char::from_u32);
+        let high = (window[0]..window[1]).rev().find_map(char::from_u32);
+        if let (Some(low), Some(high)) = (low, high) {
+            if ranges.iter().any(|(l, h)| *l <= low && high <= *h) {
+                parts.push((low, high));
+            }
+        }
+    }
+    parts
+}
+
+/// State and transition counts before and after `Dfa::minimize`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinimizeStats {
+    pub states_before: usize,
+    pub states_after: usize,
+    pub transitions_before: usize,
+    pub transitions_after: usize,
+}
+
+impl fmt::Display for MinimizeStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "states: {} -> {}, transitions: {} -> {}",
+            self.states_before, self.states_after, self.transitions_before, self.transitions_after
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        grammar::{
+            class::CharClassBuilder,
+            error::DeterminizeError,
+            state::{create_state, State},
+            state_machine::{ExecutionMode, StateMachine, StateMachineBuilder},
+            transition::{
+                create_class_transition, CharTransition, EpsilonTransition, IndentationOperation,
+                Lookahead, NegativeLookaheadTransition, Transition, TransitionKind,
+            },
+        },
+        machine,
+        yaml::{document::document_state_machine, scalar::scalar_state_machine},
+    };
+
+    use super::Dfa;
+
+    /// Every string of length `1..=length` over `alphabet`.
+    fn inputs(alphabet: &str, length: usize) -> Vec<String> {
+        let mut inputs = vec![String::new()];
+        let mut all = Vec::new();
+        for _ in 0..length {
+            inputs = inputs
+                .iter()
+                .flat_map(|prefix| alphabet.chars().map(move |c| format!("{}{}", prefix, c)))
+                .collect();
+            all.extend(inputs.clone());
+        }
+        all
+    }
+
+    fn assert_same_language(machine: &StateMachine, dfa: &Dfa, alphabet: &str, length: usize) {
+        for input in inputs(alphabet, length) {
+            assert_eq!(
+                machine.check(input.clone()),
+                dfa.check(&input),
+                "disagree on {:?}",
+                input
+            );
+            assert_eq!(
+                machine.validate(input.clone()),
+                dfa.validate(&input),
+                "disagree on {:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_determinize_scalar_state_machine() {
+        let machine = scalar_state_machine(0);
+        let dfa = machine.determinize().unwrap();
+        assert_eq!(dfa.state_count(), 3);
+        assert_same_language(&machine, &dfa, "aZ ;", 5);
+    }
+
+    #[test]
+    fn test_determinize_word_state_machine() {
+        let document = document_state_machine(0);
+        let TransitionKind::Group(machine) = document.transitions()[0].kind() else {
+            panic!("expected the header word transition");
+        };
+        let dfa = machine.determinize().unwrap();
+        assert_eq!(dfa.state_count(), 4);
+        assert!(dfa.check("---"));
+        assert!(!dfa.check("--"));
+        assert_same_language(machine, &dfa, "-a", 4);
+    }
+
+    #[test]
+    fn test_determinize_epsilon_alternation() {
+        let start = Arc::new(create_state(false, "start"));
+        let left = Arc::new(create_state(false, "left"));
+        let right = Arc::new(create_state(false, "right"));
+        let left_a = Arc::new(create_state(false, "left_a"));
+        let right_a = Arc::new(create_state(false, "right_a"));
+        let end = Arc::new(create_state(true, "end"));
+        let char_transition =
+            |from: &Arc<State>, to: &Arc<State>, c: &str| -> Arc<dyn Transition> {
+                Arc::new(CharTransition::new(
+                    from.clone(),
+                    to.clone(),
+                    c.to_string(),
+                    IndentationOperation::BYPASS,
+                ))
+            };
+        let machine = StateMachineBuilder::new(start.clone(), " ", 0)
+            .mode(ExecutionMode::Backtracking)
+            .add_states(vec![
+                left.clone(),
+                right.clone(),
+                left_a.clone(),
+                right_a.clone(),
+                end.clone(),
+            ])
+            .add_transitions(vec![
+                Arc::new(EpsilonTransition::new(start.clone(), left.clone())),
+                Arc::new(EpsilonTransition::new(start, right.clone())),
+                char_transition(&left, &left_a, "a"),
+                char_transition(&left_a, &end, "b"),
+                char_transition(&right, &right_a, "a"),
+                char_transition(&right_a, &end, "c"),
+            ])
+            .build();
+
+        let dfa = machine.determinize().unwrap();
+        assert_eq!(dfa.state_count(), 3);
+        assert!(dfa.check("ab"));
+        assert!(dfa.check("ac"));
+        assert_same_language(&machine, &dfa, "abc", 4);
+    }
+
+    #[test]
+    fn test_determinize_deterministic_machine() {
+        let lower = CharClassBuilder::new().add_range('a', 'z').build();
+        let upper = CharClassBuilder::new().add_range('A', 'Z').build();
+        let machine = machine! {
+            name: "case", indentation: 0;
+            start -> lower*: lower.clone();
+            start -> upper*: upper.clone();
+            lower -> lower*: lower;
+            upper -> upper*: upper;
+            upper -> digit*: '1';
+        };
+        let dfa = machine.determinize().unwrap();
+        assert_same_language(&machine, &dfa, "aZ1", 4);
+    }
+
+    #[test]
+    fn test_determinize_rejects_first_match_choices() {
+        let choice = machine! {
+            name: "choice", indentation: 0;
+            start -> s1*: 'a';
+            start -> s2: 'a';
+            s2 -> end*: 'b';
+        };
+        assert!(!choice.check("ab"));
+        assert_eq!(
+            choice.determinize().unwrap_err(),
+            DeterminizeError::FirstMatch {
+                machine: "choice".to_string(),
+                state: "start".to_string()
+            }
+        );
+
+        // a deterministic run going on past `1` rejects `1.`
+        let decimal = machine! {
+            name: "decimal", indentation: 0;
+            start -> int*: '1';
+            int -> dot: '.';
+            dot -> frac*: '1';
+        };
+        assert!(!decimal.validate("1.").0);
+        assert_eq!(
+            decimal.determinize().unwrap_err(),
+            DeterminizeError::FirstMatch {
+                machine: "decimal".to_string(),
+                state: "int".to_string()
+            }
+        );
+
+        let backtracking = machine! {
+            name: "choice", indentation: 0, mode: Backtracking;
+            start -> s1*: 'a';
+            start -> s2: 'a';
+            s2 -> end*: 'b';
+        };
+        let dfa = backtracking.determinize().unwrap();
+        assert_same_language(&backtracking, &dfa, "ab", 3);
+    }
+
+    #[test]
+    fn test_determinize_rejects_group_transitions() {
+        let machine = document_state_machine(0);
+        assert!(machine.determinize().is_err());
+    }
+
+    #[test]
+    fn test_determinize_rejects_lookaheads_followed_by_input() {
+        let start = Arc::new(create_state(false, "start"));
+        let looked = Arc::new(create_state(false, "looked"));
+        let end = Arc::new(create_state(true, "end"));
+        let machine = StateMachineBuilder::new(start.clone(), " ", 0)
+            .add_states(vec![looked.clone(), end.clone()])
+            .add_transition(Arc::new(NegativeLookaheadTransition::new(
+                start,
+                looked.clone(),
+                Lookahead::Char('b'),
+            )))
+            .add_transition(Arc::new(CharTransition::new(
+                looked,
+                end,
+                "a".to_string(),
+                IndentationOperation::BYPASS,
+            )))
+            .build();
+        let error = machine.determinize().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "cannot determinize transition !'b' from state start"
+        );
+    }
+
+    #[test]
+    fn test_minimize_merges_scalar_end_states() {
+        let machine = scalar_state_machine(0);
+        let dfa = machine.determinize().unwrap();
+        let (minimized, stats) = dfa.minimize();
+        assert_eq!(stats.states_before, 3);
+        assert_eq!(stats.states_after, 2);
+        // one column each for the space, A-Z and a-z
+        assert_eq!(stats.transitions_before, 3 * 3);
+        assert_eq!(stats.transitions_after, 2 * 3);
+        assert!(dfa.equivalent(&minimized));
+        assert_same_language(&machine, &minimized, "aZ ;", 5);
+    }
+
+    #[test]
+    fn test_minimize_keeps_empty_language() {
+        let start = Arc::new(create_state(false, "start"));
+        let end = Arc::new(create_state(false, "end"));
+        let machine = StateMachineBuilder::new(start.clone(), " ", 0)
+            .add_state(end.clone())
+            .add_transition(Arc::new(CharTransition::new(
+                start,
+                end,
+                "a".to_string(),
+                IndentationOperation::BYPASS,
+            )))
+            .build();
+        let (minimized, stats) = machine.determinize().unwrap().minimize();
+        assert_eq!(stats.states_after, 1);
+        assert_eq!(stats.transitions_after, 0);
+        assert!(!minimized.check("a"));
+    }
+
+    #[test]
+    fn test_equivalent_dfas() {
+        let scalar = scalar_state_machine(0).determinize().unwrap();
+
+        // the same language written with a single looping end state
+        let start = Arc::new(create_state(false, "start"));
+        let end = Arc::new(create_state(true, "end"));
+        let alphabet = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz ";
+        let looping = StateMachineBuilder::new(start.clone(), " ", 0)
+            .add_state(end.clone())
+            .add_transition(create_class_transition(
+                start,
+                end.clone(),
+                alphabet,
+                IndentationOperation::BYPASS,
+            ))
+            .add_transition(create_class_transition(
+                end.clone(),
+                end,
+                alphabet,
+                IndentationOperation::BYPASS,
+            ))
+            .build()
+            .determinize()
+            .unwrap();
+        assert!(scalar.equivalent(&looping));
+        assert!(looping.equivalent(&scalar));
+
+        let document = document_state_machine(0);
+        let TransitionKind::Group(word) = document.transitions()[0].kind() else {
+            panic!("expected the header word transition");
+        };
+        let word = word.determinize().unwrap();
+        assert!(!scalar.equivalent(&word));
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt::Write;
+
+use super::{state_machine::StateMachine, transition::TransitionKind};
+
+/// How many levels of nested groups `to_dot` expands.
+pub const DEFAULT_DOT_DEPTH: usize = 4;
+
+impl StateMachine {
+    /// Renders the machine as a Graphviz graph, expanding nested groups up to
+    /// `DEFAULT_DOT_DEPTH` levels.
+    pub fn to_dot(&self) -> String {
+        self.to_dot_with_depth(DEFAULT_DOT_DEPTH)
+    }
+
+    /// Renders the machine as a Graphviz graph. Each group transition is drawn as
+    /// a cluster holding its inner machine, entered from the group's source state
+    /// and left from the inner final states. Groups deeper than `max_depth` are
+    /// drawn as a single edge.
+    pub fn to_dot_with_depth(&self, max_depth: usize) -> String {
+        let mut dot = String::new();
+        let mut clusters = 0;
+        writeln!(dot, "digraph \"{}\" {{", escape(self.name())).unwrap();
+        writeln!(dot, "    rankdir=LR;").unwrap();
+        writeln!(dot, "    node [shape=circle];").unwrap();
+        write_machine(&mut dot, self, "m0", 1, max_depth, &mut clusters);
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+fn write_machine(
+    dot: &mut String,
+    machine: &StateMachine,
+    prefix: &str,
+    depth: usize,
+    max_depth: usize,
+    clusters: &mut usize,
+) {
+    let indent = "    ".repeat(depth);
+    for (id, state) in machine.states().iter().enumerate() {
+        let shape = if state.is_final() {
+            ", shape=doublecircle"
+        } else {
+            ""
+        };
+        writeln!(
+            dot,
+            "{}{}_{} [label=\"{}\"{}];",
+            indent,
+            prefix,
+            id,
+            escape(&state.label),
+            shape
+        )
+        .unwrap();
+    }
+
+    for from in 0..machine.states().len() {
+        for (transition, to) in machine.outgoing(from).iter().zip(machine.targets(from)) {
+            let label = format!(
+                "{} {:?}",
+                transition.kind(),
+                transition.indentation_operation()
+            );
+            let source = format!("{}_{}", prefix, from);
+            let target = format!("{}_{}", prefix, to);
+            match transition.kind() {
+                TransitionKind::Group(inner) if depth < max_depth => {
+                    *clusters += 1;
+                    let inner_prefix = format!("c{}", clusters);
+                    writeln!(dot, "{}subgraph cluster_{} {{", indent, clusters).unwrap();
+                    writeln!(dot, "{}    label=\"{}\";", indent, escape(inner.name())).unwrap();
+                    write_machine(dot, inner, &inner_prefix, depth + 1, max_depth, clusters);
+                    writeln!(dot, "{}}}", indent).unwrap();
+                    writeln!(
+                        dot,
+                        "{}{} -> {}_0 [label=\"{}\", style=dashed];",
+                        indent,
+                        source,
+                        inner_prefix,
+                        escape(&label)
+                    )
+                    .unwrap();
+                    for (id, state) in inner.states().iter().enumerate() {
+                        if state.is_final() {
+                            writeln!(
+                                dot,
+                                "{}{}_{} -> {} [style=dashed];",
+                                indent, inner_prefix, id, target
+                            )
+                            .unwrap();
+                        }
+                    }
+                }
+                _ => {
+                    writeln!(
+                        dot,
+                        "{}{} -> {} [label=\"{}\"];",
+                        indent,
+                        source,
+                        target,
+                        escape(&label)
+                    )
+                    .unwrap();
+                }
+            }
+        }
+    }
+}
+
+/// Escapes a string for a double quoted DOT identifier.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        grammar::{
+            state::create_state,
+            state_machine::StateMachineBuilder,
+            transition::{CharTransition, IndentationOperation},
+        },
+        yaml::kv::kv_state_machine,
+    };
+
+    #[test]
+    fn test_dot_marks_final_states_and_labels_edges() {
+        let start = Arc::new(create_state(false, "start"));
+        let end = Arc::new(create_state(true, "end"));
+        let machine = StateMachineBuilder::new(start.clone(), " ", 0)
+            .name("newline")
+            .add_state(end.clone())
+            .add_transition(Arc::new(CharTransition::new(
+                start,
+                end,
+                "\n".to_string(),
+                IndentationOperation::INCREMENT,
+            )))
+            .build();
+        let dot = machine.to_dot();
+        assert!(dot.starts_with("digraph \"newline\" {"));
+        assert!(dot.contains("m0_0 [label=\"start\"];"));
+        assert!(dot.contains("m0_1 [label=\"end\", shape=doublecircle];"));
+        assert!(dot.contains("m0_0 -> m0_1 [label=\"\\\"\\\\n\\\" INCREMENT\"];"));
+    }
+
+    #[test]
+    fn test_dot_draws_groups_as_clusters_up_to_depth() {
+        let machine = kv_state_machine(0);
+        let dot = machine.to_dot_with_depth(2);
+        assert!(dot.contains("subgraph cluster_1 {"));
+        assert!(dot.contains("label=\"key\";"));
+        assert!(dot.contains("label=\"value\";"));
+        // groups inside the value machine are past the depth limit
+        assert!(!dot.contains("label=\"scalar\";"));
+        assert!(dot.contains("[label=\"<scalar> BYPASS\"]"));
+
+        let flat = machine.to_dot_with_depth(1);
+        assert!(!flat.contains("subgraph"));
+        assert!(flat.contains("m0_0 -> m0_1 [label=\"<key> BYPASS\"];"));
+    }
+}
@@ -0,0 +1,372 @@
+use core::fmt;
+
+/// Describes why a buffer was rejected by a state machine.
+///
+/// The location points at the furthest failure, which may sit inside a nested
+/// `GroupTransition` machine rather than in the machine `validate` was called on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// Offset in the buffer where the failure occurred.
+    pub offset: usize,
+    /// 1-based line of the failure.
+    pub line: usize,
+    /// 1-based column of the failure.
+    pub column: usize,
+    /// Label of the state the failing machine was in.
+    pub state: String,
+    /// Names of the nested group machines that were active, outermost first.
+    pub groups: Vec<String>,
+    /// Inputs that would have been accepted at that point.
+    pub expected: Vec<String>,
+}
+
+impl ValidationError {
+    pub fn new(
+        buffer: &str,
+        offset: usize,
+        state: String,
+        groups: Vec<String>,
+        expected: Vec<String>,
+    ) -> Self {
+        let (line, column) = line_column(buffer, offset);
+        ValidationError {
+            offset,
+            line,
+            column,
+            state,
+            groups,
+            expected,
+        }
+    }
+}
+
+/// Computes the 1-based line and column of byte `offset` in `buffer`. Columns
+/// count characters, not bytes.
+pub fn line_column(buffer: &str, offset: usize) -> (usize, usize) {
+    let mut line = 1;
+    let mut column = 1;
+    let before = buffer.get(..offset).unwrap_or(buffer);
+    for c in before.chars() {
+        if c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+    (line, column)
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: ", self.line, self.column)?;
+        write!(f, "stuck in state {}", self.state)?;
+        if !self.groups.is_empty() {
+            write!(f, " of {}", self.groups.join(" > "))?;
+        }
+        if self.expected.is_empty() {
+            write!(f, ", no input expected")
+        } else {
+            write!(f, ", expected one of {}", self.expected.join(", "))
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Why a state machine could not be compiled into a `Dfa`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeterminizeError {
+    /// Only char transitions without indentation checks, epsilon transitions and
+    /// char or class lookaheads followed by epsilon transitions can be compiled.
+    UnsupportedTransition { from: String, transition: String },
+    /// A deterministic machine either chooses between transitions at `state`,
+    /// or leaves the final `state` for one that is not. Its first-match run
+    /// would then not always end where the longest run of a `Dfa` does.
+    FirstMatch { machine: String, state: String },
+}
+
+impl fmt::Display for DeterminizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeterminizeError::UnsupportedTransition { from, transition } => {
+                write!(
+                    f,
+                    "cannot determinize transition {} from state {}",
+                    transition, from
+                )
+            }
+            DeterminizeError::FirstMatch { machine, state } => {
+                write!(
+                    f,
+                    "cannot determinize machine {}, its first match at state {} may not be the longest",
+                    machine, state
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for DeterminizeError {}
+
+/// Why a grammar could not be built.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrammarError {
+    /// A rule transition of `machine` names a rule the grammar does not define.
+    UnknownRule { rule: String, machine: String },
+    /// A caller asked for a rule the grammar does not define.
+    MissingRule { rule: String },
+    /// The grammar definition is malformed at the 1-based `line` and `column`.
+    Syntax {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// The grammar file could not be read.
+    Io { path: String, message: String },
+    /// Several states of `machine` are labelled `label`.
+    DuplicateLabel { machine: String, label: String },
+    /// A transition of `machine` goes from or to `state`, which was never added.
+    UnknownState { machine: String, state: String },
+    /// `machine` can never accept.
+    NoFinalState { machine: String },
+    /// `state` cannot be reached from the start state of `machine`.
+    UnreachableState { machine: String, state: String },
+    /// No final state of `machine` can be reached from `state`.
+    DeadState { machine: String, state: String },
+    /// Epsilon transitions of `machine` lead from the first of `states` through
+    /// the others and back, so a run could loop without consuming input.
+    EpsilonCycle {
+        machine: String,
+        states: Vec<String>,
+    },
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GrammarError::UnknownRule { rule, machine } => {
+                write!(f, "machine {} refers to unknown rule {}", machine, rule)
+            }
+            GrammarError::MissingRule { rule } => write!(f, "no rule named {}", rule),
+            GrammarError::Syntax {
+                line,
+                column,
+                message,
+            } => write!(f, "line {}, column {}: {}", line, column, message),
+            GrammarError::Io { path, message } => write!(f, "cannot read {}: {}", path, message),
+            GrammarError::DuplicateLabel { machine, label } => {
+                write!(
+                    f,
+                    "machine {} has several states labelled {}",
+                    machine, label
+                )
+            }
+            GrammarError::UnknownState { machine, state } => write!(
+                f,
+                "machine {} has transitions on state {} which was never added",
+                machine, state
+            ),
+            GrammarError::NoFinalState { machine } => {
+                write!(f, "machine {} has no final state", machine)
+            }
+            GrammarError::UnreachableState { machine, state } => {
+                write!(f, "state {} of machine {} is unreachable", state, machine)
+            }
+            GrammarError::DeadState { machine, state } => write!(
+                f,
+                "state {} of machine {} cannot reach a final state",
+                state, machine
+            ),
+            GrammarError::EpsilonCycle { machine, states } => write!(
+                f,
+                "machine {} has an epsilon cycle through {}",
+                machine,
+                states.join(" -> ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GrammarError {}
+
+/// Why a run was aborted before it could accept or reject its input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunError {
+    /// `machine` came back to `state` at `offset` without consuming anything,
+    /// or entered itself again there as a group.
+    NoProgress {
+        machine: String,
+        state: String,
+        offset: usize,
+    },
+    /// The run took more than `limit` steps and stopped at `offset`.
+    StepLimit { limit: usize, offset: usize },
+    /// Several `transitions` of the same priority leaving `state` matched at
+    /// `offset`, in a machine that does not allow it.
+    Ambiguous {
+        machine: String,
+        state: String,
+        offset: usize,
+        transitions: Vec<String>,
+    },
+    /// The blanks indenting a line at `offset` mix tabs and spaces, or differ
+    /// from the ones the blocks around it are indented with.
+    MixedIndentation { offset: usize },
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunError::NoProgress {
+                machine,
+                state,
+                offset,
+            } => write!(
+                f,
+                "machine {} loops in state {} at offset {} without consuming input",
+                machine, state, offset
+            ),
+            RunError::StepLimit { limit, offset } => {
+                write!(f, "run exceeded {} steps at offset {}", limit, offset)
+            }
+            RunError::Ambiguous {
+                machine,
+                state,
+                offset,
+                transitions,
+            } => write!(
+                f,
+                "machine {} is ambiguous in state {} at offset {}: {} all match",
+                machine,
+                state,
+                offset,
+                transitions.join(", ")
+            ),
+            RunError::MixedIndentation { offset } => {
+                write!(f, "indentation mixes tabs and spaces at offset {}", offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+/// Why a stream could not be validated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamError {
+    /// Reading the stream failed, or it is not valid UTF-8.
+    Io { message: String },
+    /// The run needed more than the `window` bytes of input kept in memory,
+    /// starting at `offset`.
+    WindowExceeded { offset: usize, window: usize },
+    /// The run was aborted.
+    Run(RunError),
+    /// The rule to validate against does not exist.
+    Grammar(GrammarError),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StreamError::Io { message } => write!(f, "cannot read stream: {}", message),
+            StreamError::WindowExceeded { offset, window } => write!(
+                f,
+                "validating from offset {} needs more than the {} bytes window",
+                offset, window
+            ),
+            StreamError::Run(error) => write!(f, "{}", error),
+            StreamError::Grammar(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+/// Why a run could not be resumed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResumeError {
+    /// Frame `frame` of the execution state does not fit the machine or the
+    /// text: the group it should run, its state or its transition do not exist,
+    /// or its offsets are out of order or outside the text.
+    InvalidFrame { frame: usize },
+    /// The resumed run was aborted.
+    Run(RunError),
+}
+
+impl fmt::Display for ResumeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResumeError::InvalidFrame { frame } => {
+                write!(f, "frame {} does not fit the machine", frame)
+            }
+            ResumeError::Run(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for ResumeError {}
+
+/// Why a machine definition could not be imported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DefinitionError {
+    /// The JSON is malformed or does not describe a machine.
+    Json { message: String },
+    /// The definition is written in `version` of the format, which is not
+    /// supported.
+    UnsupportedVersion { version: u32 },
+    /// `category` is not the abbreviation of a Unicode general category.
+    UnknownCategory { category: String },
+    /// A machine of the definition has duplicate state labels or transitions
+    /// on states it does not list.
+    Machine(GrammarError),
+}
+
+impl fmt::Display for DefinitionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DefinitionError::Json { message } => write!(f, "invalid definition: {}", message),
+            DefinitionError::UnsupportedVersion { version } => {
+                write!(f, "definition version {} is not supported", version)
+            }
+            DefinitionError::UnknownCategory { category } => {
+                write!(f, "unknown general category {}", category)
+            }
+            DefinitionError::Machine(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for DefinitionError {}
+
+/// Why a regular expression could not be compiled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegexError {
+    /// Byte offset in the pattern of the faulty syntax.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "offset {}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for RegexError {}
+
+#[cfg(test)]
+mod tests {
+    use super::line_column;
+
+    #[test]
+    fn test_line_column() {
+        let buffer = "ab\ncd\n\nef";
+        assert_eq!(line_column(buffer, 0), (1, 1));
+        assert_eq!(line_column(buffer, 1), (1, 2));
+        assert_eq!(line_column(buffer, 3), (2, 1));
+        assert_eq!(line_column(buffer, 7), (4, 1));
+        assert_eq!(line_column(buffer, 8), (4, 2));
+        assert_eq!(line_column("clé:x", 5), (1, 5));
+    }
+}
@@ -0,0 +1,1022 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::{ControlFlow, Range},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    context::RunContext,
+    error::{ResumeError, RunError, StreamError},
+    indentation::Indentation,
+    input::Input,
+    overlap::can_start_with,
+    registry::Grammar,
+    state_machine::{ExecutionMode, MatchStrategy, StateMachine},
+    transition::TransitionKind,
+};
+
+/// Where the run of one machine stands.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FrameState {
+    /// Name of the machine, checked when resuming.
+    pub machine: String,
+    pub state: usize,
+    pub offset: usize,
+    pub indentation: Indentation,
+    /// Where the run of the machine started.
+    pub start: usize,
+    /// Index among the transitions leaving `state` of the one being tried. Every
+    /// frame but the last one is trying the group the next frame runs.
+    pub next: usize,
+    /// Whether the run may read the input at `offset` again once the group it
+    /// runs is over.
+    pub rewind: bool,
+    /// States and indentations reached since the run last consumed input, sorted.
+    pub stalled: Vec<(usize, Indentation)>,
+}
+
+/// A paused run: the runs of the machine it was started on and of every group
+/// it is in, outermost first.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ExecutionState {
+    pub frames: Vec<FrameState>,
+}
+
+impl ExecutionState {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("an execution state is always serialisable")
+    }
+
+    pub fn from_json(json: &str) -> Result<ExecutionState, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+/// A point a run can be resumed from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// One past the furthest offset looked at to get there.
+    pub examined: usize,
+    pub state: ExecutionState,
+}
+
+/// The outcome of a run and the checkpoints it went through, in order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedRun {
+    pub accepted: bool,
+    pub offset: usize,
+    pub checkpoints: Vec<Checkpoint>,
+    /// Checkpoints this run actually went through, the others being reused from
+    /// a previous run.
+    pub steps: usize,
+}
+
+/// Replaces the bytes in `range` with `replacement`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub replacement: String,
+}
+
+impl TextEdit {
+    /// Where `offset` of the text before the edit ends up, if it is not within
+    /// the replaced range.
+    fn map(&self, offset: usize) -> Option<usize> {
+        if offset < self.range.start {
+            Some(offset)
+        } else if offset >= self.range.end {
+            Some(offset - self.range.end + self.range.start + self.replacement.len())
+        } else {
+            None
+        }
+    }
+
+    /// `state` as it is after the edit, if every frame is past the edit so
+    /// nothing it reads from then on changed.
+    fn map_state(&self, state: &ExecutionState) -> Option<ExecutionState> {
+        let frames = state
+            .frames
+            .iter()
+            .map(|frame| {
+                if frame.offset < self.range.end {
+                    return None;
+                }
+                Some(FrameState {
+                    offset: self.map(frame.offset)?,
+                    start: self.map(frame.start)?,
+                    ..frame.clone()
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(ExecutionState { frames })
+    }
+
+    fn map_checkpoint(&self, checkpoint: &Checkpoint) -> Option<Checkpoint> {
+        let end = self.range.start + self.replacement.len();
+        Some(Checkpoint {
+            examined: self.map(checkpoint.examined).unwrap_or(end).max(end),
+            state: self.map_state(&checkpoint.state)?,
+        })
+    }
+}
+
+impl StateMachine {
+    /// Validates `buffer` like `validate`, recording a checkpoint every time the
+    /// run reaches a state. Machines that are not deterministic first-match
+    /// ones are run in one go, without checkpoints.
+    pub fn validate_recorded(&self, buffer: &str) -> Result<RecordedRun, RunError> {
+        let frames = vec![Frame::new(self, 0, Indentation::new())];
+        record(self, buffer, frames, 0, Vec::new(), None)
+    }
+
+    /// Goes on with a run paused in `state`, over `buffer`.
+    pub fn resume(&self, buffer: &str, state: &ExecutionState) -> Result<RecordedRun, ResumeError> {
+        let frames = restore(self, buffer, state)?;
+        record(self, buffer, frames, 0, Vec::new(), None).map_err(ResumeError::Run)
+    }
+
+    /// Validates `buffer`, the text once `edit` was applied to the text of the
+    /// `previous` run. The run restarts from the last checkpoint that looked at
+    /// nothing the edit changed, and stops as soon as it reaches a checkpoint
+    /// of the previous run again, as it would go on the same way from there.
+    ///
+    /// Runs of machines that are not deterministic first-match ones have no
+    /// checkpoints, so for them this is a full run of `buffer`. Fails if the
+    /// checkpoint to restart from does not fit this machine, as when `previous`
+    /// was recorded on another one.
+    pub fn revalidate(
+        &self,
+        previous: &RecordedRun,
+        buffer: &str,
+        edit: &TextEdit,
+    ) -> Result<RecordedRun, ResumeError> {
+        let Some(restart) = previous
+            .checkpoints
+            .iter()
+            .rposition(|checkpoint| checkpoint.examined <= edit.range.start)
+        else {
+            return self.validate_recorded(buffer).map_err(ResumeError::Run);
+        };
+        let checkpoint = &previous.checkpoints[restart];
+        let frames = restore(self, buffer, &checkpoint.state)?;
+
+        let resync: HashMap<ExecutionState, usize> = previous.checkpoints[restart + 1..]
+            .iter()
+            .enumerate()
+            .filter_map(|(index, checkpoint)| {
+                Some((edit.map_state(&checkpoint.state)?, restart + 1 + index))
+            })
+            .collect();
+        let resynced = Resync {
+            previous,
+            edit,
+            at: &resync,
+        };
+        record(
+            self,
+            buffer,
+            frames,
+            checkpoint.examined,
+            previous.checkpoints[..restart].to_vec(),
+            Some(resynced),
+        )
+        .map_err(ResumeError::Run)
+    }
+}
+
+/// The checkpoints of a previous run a run can join again.
+struct Resync<'a> {
+    previous: &'a RecordedRun,
+    edit: &'a TextEdit,
+    /// Index of the previous checkpoint each state, once edited, comes from.
+    at: &'a HashMap<ExecutionState, usize>,
+}
+
+/// Runs from `frames` over the whole of `buffer`, recording checkpoints after
+/// `checkpoints`.
+fn record(
+    machine: &StateMachine,
+    buffer: &str,
+    mut frames: Vec<Frame>,
+    examined: usize,
+    mut checkpoints: Vec<Checkpoint>,
+    resync: Option<Resync>,
+) -> Result<RecordedRun, RunError> {
+    let mut context = RunContext::new();
+    let mut text = Text { buffer, examined };
+    if !is_streamed(machine) {
+        let (accepted, offset) =
+            machine.validate_in_context(&Input::new(buffer), 0, &Indentation::new(), &mut context);
+        return match context.take_error() {
+            Some(error) => Err(error),
+            None => Ok(RecordedRun {
+                accepted,
+                offset,
+                checkpoints,
+                steps: 0,
+            }),
+        };
+    }
+
+    let mut steps = 0;
+    let mut joined = None;
+    let nesting = Nesting::default();
+    let outcome = drive(
+        &mut frames,
+        &mut text,
+        &mut context,
+        nesting,
+        |frames, text| {
+            let state = save(frames);
+            steps += 1;
+            if let Some(index) = resync.as_ref().and_then(|resync| resync.at.get(&state)) {
+                joined = Some(*index);
+                return ControlFlow::Break(());
+            }
+            checkpoints.push(Checkpoint {
+                examined: text.examined,
+                state,
+            });
+            ControlFlow::Continue(())
+        },
+    );
+    let outcome = match outcome {
+        Ok(outcome) => outcome,
+        Err(StreamError::Run(error)) => return Err(error),
+        Err(error) => unreachable!("a whole text is always available: {}", error),
+    };
+
+    match (outcome, joined, resync) {
+        (Outcome::Done(accepted, offset), _, _) => Ok(RecordedRun {
+            accepted,
+            offset,
+            checkpoints,
+            steps,
+        }),
+        (Outcome::Paused, Some(index), Some(resync)) => {
+            let previous = resync.previous;
+            checkpoints.extend(
+                previous.checkpoints[index..]
+                    .iter()
+                    .filter_map(|checkpoint| resync.edit.map_checkpoint(checkpoint)),
+            );
+            Ok(RecordedRun {
+                accepted: previous.accepted,
+                offset: resync.edit.map(previous.offset).unwrap_or(previous.offset),
+                checkpoints,
+                steps,
+            })
+        }
+        (Outcome::Paused, _, _) => unreachable!("runs only pause to join a previous run"),
+    }
+}
+
+/// The frames of a paused run.
+fn save(frames: &[Frame]) -> ExecutionState {
+    ExecutionState {
+        frames: frames
+            .iter()
+            .map(|frame| {
+                let mut stalled: Vec<(usize, Indentation)> =
+                    frame.stalled.iter().cloned().collect();
+                stalled.sort_unstable();
+                FrameState {
+                    machine: frame.machine.name().to_string(),
+                    state: frame.state,
+                    offset: frame.offset,
+                    indentation: frame.indentation.clone(),
+                    start: frame.start,
+                    next: frame.next,
+                    rewind: frame.rewind,
+                    stalled,
+                }
+            })
+            .collect(),
+    }
+}
+
+/// The frames `state` describes, each machine being found through the group
+/// transition the frame before it tries. The offsets of each frame must lie in
+/// `buffer`, after the offset of the frame before it.
+fn restore<'m>(
+    root: &'m StateMachine,
+    buffer: &str,
+    state: &ExecutionState,
+) -> Result<Vec<Frame<'m>>, ResumeError> {
+    let mut frames: Vec<Frame<'m>> = Vec::new();
+    for (index, saved) in state.frames.iter().enumerate() {
+        let machine = match frames.last() {
+            None => Some(root),
+            Some(parent) => parent
+                .machine
+                .outgoing(parent.state)
+                .get(parent.next)
+                .and_then(|transition| match transition.kind() {
+                    TransitionKind::Group(machine) if is_streamed(machine) => Some(machine),
+                    _ => None,
+                }),
+        };
+        let machine = machine
+            .filter(|machine| {
+                machine.name() == saved.machine
+                    && saved.state < machine.states().len()
+                    && saved.next <= machine.outgoing(saved.state).len()
+            })
+            .ok_or(ResumeError::InvalidFrame { frame: index })?;
+        let within = saved.start <= saved.offset
+            && buffer.is_char_boundary(saved.offset)
+            && frames
+                .last()
+                .is_none_or(|parent| parent.offset <= saved.start);
+        if !within {
+            return Err(ResumeError::InvalidFrame { frame: index });
+        }
+        frames.push(Frame {
+            machine,
+            state: saved.state,
+            offset: saved.offset,
+            indentation: saved.indentation.clone(),
+            start: saved.start,
+            next: saved.next,
+            rewind: saved.rewind,
+            stalled: saved.stalled.iter().cloned().collect(),
+            exploration: None,
+        });
+    }
+    if frames.is_empty() {
+        return Err(ResumeError::InvalidFrame { frame: 0 });
+    }
+    Ok(frames)
+}
+
+/// Where a run reads its input from.
+pub(super) trait Source {
+    /// Runs `attempt` over the input available, reading more of it and running
+    /// `attempt` again for as long as it looks past what is available.
+    fn run<T>(
+        &mut self,
+        offset: usize,
+        context: &mut RunContext,
+        attempt: impl FnMut(&Input, &mut RunContext) -> T,
+    ) -> Result<T, StreamError>;
+
+    /// Tells the source the run will not read the input before `offset` again.
+    fn forget_before(&mut self, _offset: usize) {}
+}
+
+/// A whole text.
+struct Text<'a> {
+    buffer: &'a str,
+    /// One past the furthest offset looked at so far.
+    examined: usize,
+}
+
+impl Source for Text<'_> {
+    fn run<T>(
+        &mut self,
+        _offset: usize,
+        context: &mut RunContext,
+        mut attempt: impl FnMut(&Input, &mut RunContext) -> T,
+    ) -> Result<T, StreamError> {
+        let input = Input::new(self.buffer);
+        let result = attempt(&input, context);
+        self.examined = self.examined.max(input.examined());
+        Ok(result)
+    }
+}
+
+/// How `drive` stopped.
+pub(super) enum Outcome {
+    /// The run of the root machine is over.
+    Done(bool, usize),
+    /// `at_state` asked to stop. The frames are left as they were.
+    Paused,
+}
+
+/// Whether runs of `machine` can be done one transition at a time.
+pub(super) fn is_streamed(machine: &StateMachine) -> bool {
+    machine.mode() == ExecutionMode::Deterministic
+        && machine.strategy() == MatchStrategy::FirstMatch
+}
+
+/// Which groups `drive` runs as frames of their own rather than in one go.
+#[derive(Clone, Copy, Default)]
+pub(super) struct Nesting<'m> {
+    /// Runs groups over backtracking machines as frames exploring their
+    /// configurations in the order of their offsets. Such frames cannot be saved.
+    pub backtracking: bool,
+    /// Runs the rules of `grammar` as frames too.
+    pub grammar: Option<&'m Grammar>,
+}
+
+impl<'m> Nesting<'m> {
+    /// Whether a run of `machine` can be a frame.
+    pub(super) fn runs(&self, machine: &StateMachine) -> bool {
+        is_streamed(machine) || self.backtracking && machine.mode() == ExecutionMode::Backtracking
+    }
+
+    /// The machine a transition matching `kind` runs as a frame, if any.
+    fn group(&self, kind: TransitionKind<'m>) -> Option<&'m StateMachine> {
+        let machine = match kind {
+            TransitionKind::Group(machine) => machine,
+            TransitionKind::Rule(rule) => self.grammar?.rule(rule)?,
+            _ => return None,
+        };
+        self.runs(machine).then_some(machine)
+    }
+
+    /// A frame running `machine` from `offset`, for a caller that only takes its
+    /// longest match if `longest`.
+    pub(super) fn frame(
+        &self,
+        machine: &'m StateMachine,
+        offset: usize,
+        indentation: Indentation,
+        longest: bool,
+    ) -> Frame<'m> {
+        match machine.mode() {
+            ExecutionMode::Backtracking if self.backtracking => {
+                Frame::exploring(machine, offset, indentation, longest)
+            }
+            _ => Frame::new(machine, offset, indentation),
+        }
+    }
+}
+
+/// Runs `frames` one transition at a time, as `validate_in_context` would run
+/// them, entering the groups `nesting` allows as new frames and running any
+/// other transition in one go. `at_state` is called every time the innermost
+/// frame, when deterministic, reaches a state, before it looks at the input
+/// there.
+pub(super) fn drive<'m, S: Source>(
+    frames: &mut Vec<Frame<'m>>,
+    source: &mut S,
+    context: &mut RunContext,
+    nesting: Nesting<'m>,
+    mut at_state: impl FnMut(&[Frame<'m>], &S) -> ControlFlow<()>,
+) -> Result<Outcome, StreamError> {
+    // lengths the group frame that just ended matched, longest first, for its parent
+    let mut returned: Option<Vec<usize>> = None;
+    loop {
+        if let Some(error) = context.take_error() {
+            return Err(StreamError::Run(error));
+        }
+        let outcome = returned.take();
+        if outcome.is_none()
+            && frames
+                .last()
+                .is_some_and(|frame| frame.next == 0 && frame.exploration.is_none())
+            && at_state(frames, source).is_break()
+        {
+            return Ok(Outcome::Paused);
+        }
+        let frame = frames.last_mut().expect("the root frame ends the run");
+        let step = if frame.exploration.is_some() {
+            frame.explore(outcome, source, context, nesting)?
+        } else {
+            frame.advance(outcome, source, context, nesting)?
+        };
+
+        match step {
+            Step::Take(consumed, indentation) => frame.take(consumed, indentation, context),
+            Step::Enter(inner, rewind) => {
+                frame.rewind = rewind;
+                let (offset, indentation) = (frame.offset, frame.indentation.clone());
+                let longest = frame.exploration.is_none();
+                // every frame from there on started at `offset`, so none consumed anything
+                if frames
+                    .iter()
+                    .any(|frame| std::ptr::eq(frame.machine, inner) && frame.start == offset)
+                {
+                    context.abort(RunError::NoProgress {
+                        machine: inner.name().to_string(),
+                        state: inner.start().label.clone(),
+                        offset,
+                    });
+                    continue;
+                }
+                frames.push(nesting.frame(inner, offset, indentation, longest));
+            }
+            Step::Explored => {}
+            Step::Stop => {
+                let frame = frames.pop().expect("checked above");
+                if let Some(error) = context.take_error() {
+                    return Err(StreamError::Run(error));
+                }
+                let (validated, offset, ends) = frame.outcome();
+                if frames.is_empty() {
+                    return Ok(Outcome::Done(validated, offset));
+                }
+                returned = Some(ends);
+            }
+        }
+        source.forget_before(keep_from(frames));
+    }
+}
+
+/// What the innermost frame does next.
+enum Step<'m> {
+    /// Takes the transition it tried, which consumed that many bytes, going on
+    /// with the indentation it left.
+    Take(usize, Indentation),
+    /// Runs the group it tried, `true` if it may come back to the current offset.
+    Enter(&'m StateMachine, bool),
+    /// Went on to another configuration to explore.
+    Explored,
+    /// Ends its run.
+    Stop,
+}
+
+/// The offset before which no frame can need the input again.
+fn keep_from(frames: &[Frame]) -> usize {
+    let (innermost, outer) = frames.split_last().expect("frames are never empty here");
+    outer
+        .iter()
+        .map(|frame| frame.keep_from(false))
+        .fold(innermost.keep_from(true), usize::min)
+}
+
+/// A run of a machine in progress, the ones before it in the stack running the
+/// groups that contain it.
+pub(super) struct Frame<'m> {
+    machine: &'m StateMachine,
+    state: usize,
+    offset: usize,
+    indentation: Indentation,
+    start: usize,
+    next: usize,
+    rewind: bool,
+    stalled: HashSet<(usize, Indentation)>,
+    /// For a backtracking machine, the configurations it has yet to explore.
+    /// The state, offset and indentation of the frame are then those of the
+    /// configuration being expanded.
+    exploration: Option<Box<Exploration>>,
+}
+
+/// Where the run of a backtracking machine stands. Configurations are explored
+/// in the order of their offsets, so the input before the offset of the one
+/// being expanded is only needed by the ones still pending, and by the caller
+/// going on from where the run accepted.
+struct Exploration {
+    /// Configurations left, by offset. The ones at the same offset are a stack,
+    /// so the first transition is tried first.
+    pending: BTreeMap<usize, Vec<(usize, Indentation)>>,
+    /// Configurations expanded at the offset being explored and after it.
+    visited: BTreeMap<usize, HashSet<(usize, Indentation)>>,
+    /// Where the transitions of the current configuration tried so far lead.
+    successors: Vec<(usize, usize, Indentation)>,
+    /// Offsets the run accepted at in increasing order, or only the last one
+    /// if the caller only takes the longest match.
+    ends: Vec<usize>,
+    longest: bool,
+    furthest: usize,
+}
+
+impl<'m> Frame<'m> {
+    pub(super) fn new(machine: &'m StateMachine, offset: usize, indentation: Indentation) -> Self {
+        Frame {
+            machine,
+            state: 0,
+            offset,
+            indentation: indentation.clone(),
+            start: offset,
+            next: 0,
+            rewind: true,
+            stalled: HashSet::from([(0, indentation)]),
+            exploration: None,
+        }
+    }
+
+    /// A frame exploring every run of the backtracking `machine` from `offset`.
+    fn exploring(
+        machine: &'m StateMachine,
+        offset: usize,
+        indentation: Indentation,
+        longest: bool,
+    ) -> Self {
+        let mut frame = Frame::new(machine, offset, indentation.clone());
+        frame.exploration = Some(Box::new(Exploration {
+            pending: BTreeMap::from([(offset, vec![(0, indentation)])]),
+            visited: BTreeMap::new(),
+            successors: Vec::new(),
+            ends: Vec::new(),
+            longest,
+            furthest: offset,
+        }));
+        frame.next_configuration();
+        frame
+    }
+
+    /// Whether the run comes back to `offset` when the group tried there fails
+    /// or matches: either another transition could match there, or the run can
+    /// stop in a final state and its caller goes on from there.
+    fn can_come_back(&self, c: Option<char>) -> bool {
+        self.machine.states()[self.state].is_final()
+            || self.machine.outgoing(self.state)[self.next + 1..]
+                .iter()
+                .any(|transition| can_start_with(&transition.kind(), c))
+    }
+
+    /// The offset before which the frame no longer needs the input.
+    fn keep_from(&self, innermost: bool) -> usize {
+        let mut keep = if innermost || self.rewind {
+            self.offset
+        } else {
+            usize::MAX
+        };
+        if let Some(exploration) = &self.exploration {
+            let pending = exploration.pending.keys().next();
+            let successors = exploration.successors.iter().map(|(_, offset, _)| offset);
+            let end = exploration.ends.first();
+            keep = pending
+                .into_iter()
+                .chain(successors)
+                .chain(end)
+                .fold(keep, |keep, offset| keep.min(*offset));
+        }
+        keep
+    }
+
+    /// Tries the transitions of a deterministic run from the current one on,
+    /// `outcome` being what the group it entered matched, if it did.
+    fn advance<S: Source>(
+        &mut self,
+        outcome: Option<Vec<usize>>,
+        source: &mut S,
+        context: &mut RunContext,
+        nesting: Nesting<'m>,
+    ) -> Result<Step<'m>, StreamError> {
+        let machine = self.machine;
+        let outgoing = machine.outgoing(self.state);
+        match outcome {
+            Some(ends) => match ends.first() {
+                Some(consumed) => {
+                    let (offset, indentation) = (self.offset, &self.indentation);
+                    let transition = &outgoing[self.next];
+                    let next = source.run(offset, context, |input, _| {
+                        transition.next_indentation(indentation, input, offset)
+                    })?;
+                    return Ok(Step::Take(*consumed, next));
+                }
+                None => self.next += 1,
+            },
+            None if self.next == 0 => {
+                // a run stops at the end of the input
+                let offset = self.offset;
+                if source.run(offset, context, |input, _| input.is_end(offset))? {
+                    self.next = outgoing.len();
+                }
+            }
+            None => {}
+        }
+
+        while self.next < outgoing.len() {
+            let transition = &outgoing[self.next];
+            let (offset, indentation) = (self.offset, self.indentation.clone());
+            if let Some(inner) = nesting.group(transition.kind()) {
+                let frame = &*self;
+                let rewind = source.run(offset, context, |input, _| {
+                    frame.can_come_back(input.peek(offset))
+                })?;
+                return Ok(Step::Enter(inner, rewind));
+            }
+            let character = machine.indentation_character();
+            match source.run(offset, context, |input, context| {
+                transition
+                    .to(input, offset, &indentation, character, context)
+                    .map(|consumed| {
+                        let next = transition.next_indentation(&indentation, input, offset);
+                        (consumed, next)
+                    })
+            })? {
+                Ok((consumed, next)) => return Ok(Step::Take(consumed, next)),
+                Err(_) if context.is_aborted() => break,
+                Err(_) => self.next += 1,
+            }
+        }
+        Ok(Step::Stop)
+    }
+
+    /// Tries the transitions of the configuration a backtracking run expands
+    /// from the current one on, then goes on to the next configuration.
+    /// `outcome` is what the group it entered matched.
+    fn explore<S: Source>(
+        &mut self,
+        outcome: Option<Vec<usize>>,
+        source: &mut S,
+        context: &mut RunContext,
+        nesting: Nesting<'m>,
+    ) -> Result<Step<'m>, StreamError> {
+        let machine = self.machine;
+        let outgoing = machine.outgoing(self.state);
+        let targets = machine.targets(self.state);
+        let (offset, indentation) = (self.offset, self.indentation.clone());
+        if let Some(ends) = outcome {
+            let transition = &outgoing[self.next];
+            let next = source.run(offset, context, |input, _| {
+                transition.next_indentation(&indentation, input, offset)
+            })?;
+            self.push_successors(targets[self.next], ends, next, context);
+            self.next += 1;
+        }
+
+        // transitions are tried even at the end of the input, epsilon ones
+        // can still lead to a final state
+        while self.next < outgoing.len() && !context.is_aborted() {
+            let transition = &outgoing[self.next];
+            if let Some(inner) = nesting.group(transition.kind()) {
+                let frame = &*self;
+                let rewind = source.run(offset, context, |input, _| {
+                    frame.can_come_back(input.peek(offset))
+                })?;
+                return Ok(Step::Enter(inner, rewind));
+            }
+            let character = machine.indentation_character();
+            let (ends, next) = source.run(offset, context, |input, context| {
+                let ends = transition.to_all(input, offset, &indentation, character, context);
+                let next = transition.next_indentation(&indentation, input, offset);
+                (ends, next)
+            })?;
+            self.push_successors(targets[self.next], ends, next, context);
+            self.next += 1;
+        }
+
+        if self.next_configuration() {
+            Ok(Step::Explored)
+        } else {
+            Ok(Step::Stop)
+        }
+    }
+
+    /// Records that the transition tried reaches `target` after consuming any
+    /// of `ends` bytes.
+    fn push_successors(
+        &mut self,
+        target: usize,
+        ends: Vec<usize>,
+        indentation: Indentation,
+        context: &mut RunContext,
+    ) {
+        let offset = self.offset;
+        let exploration = self.exploration.as_mut().expect("only explored runs");
+        for consumed in ends {
+            if !context.count_step(offset) {
+                break;
+            }
+            exploration
+                .successors
+                .push((target, offset + consumed, indentation.clone()));
+        }
+    }
+
+    /// Moves on to the pending configuration with the lowest offset not explored
+    /// yet. Returns `false` once there is none left.
+    fn next_configuration(&mut self) -> bool {
+        let exploration = self.exploration.as_mut().expect("only explored runs");
+        for (target, offset, indentation) in exploration.successors.drain(..).rev() {
+            exploration
+                .pending
+                .entry(offset)
+                .or_default()
+                .push((target, indentation));
+        }
+        while let Some(mut entry) = exploration.pending.first_entry() {
+            let offset = *entry.key();
+            let (state, indentation) = entry.get_mut().pop().expect("no empty entries");
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+            // configurations are never explored at lower offsets from now on
+            if exploration
+                .visited
+                .first_key_value()
+                .is_some_and(|(first, _)| *first < offset)
+            {
+                exploration.visited = exploration.visited.split_off(&offset);
+            }
+            if !exploration
+                .visited
+                .entry(offset)
+                .or_default()
+                .insert((state, indentation.clone()))
+            {
+                continue;
+            }
+            if self.machine.states()[state].is_final() && offset >= 1 {
+                if exploration.longest {
+                    exploration.ends.clear();
+                }
+                if exploration.ends.last() != Some(&offset) {
+                    exploration.ends.push(offset);
+                }
+            }
+            exploration.furthest = exploration.furthest.max(offset);
+            self.state = state;
+            self.offset = offset;
+            self.indentation = indentation;
+            self.next = 0;
+            return true;
+        }
+        false
+    }
+
+    /// Whether the run accepted, where it stopped, and the lengths it can match
+    /// as a group, longest first.
+    fn outcome(&self) -> (bool, usize, Vec<usize>) {
+        match &self.exploration {
+            None => {
+                let validated = self.machine.states()[self.state].is_final() && self.offset >= 1;
+                let ends = match validated {
+                    true => vec![self.offset - self.start],
+                    false => Vec::new(),
+                };
+                (validated, self.offset, ends)
+            }
+            Some(exploration) => {
+                let ends = exploration
+                    .ends
+                    .iter()
+                    .rev()
+                    .map(|end| end - self.start)
+                    .collect();
+                match exploration.ends.last() {
+                    Some(longest) => (true, *longest, ends),
+                    None => (false, exploration.furthest, ends),
+                }
+            }
+        }
+    }
+
+    /// Takes the transition tried, which consumed `consumed` bytes and left
+    /// `indentation`.
+    fn take(&mut self, consumed: usize, indentation: Indentation, context: &mut RunContext) {
+        let target = self.machine.targets(self.state)[self.next];
+        if consumed > 0 {
+            self.stalled.clear();
+        }
+        if !self.stalled.insert((target, indentation.clone())) {
+            context.abort(RunError::NoProgress {
+                machine: self.machine.name().to_string(),
+                state: self.machine.states()[target].label.clone(),
+                offset: self.offset,
+            });
+            return;
+        }
+        if !context.count_step(self.offset) {
+            return;
+        }
+        self.state = target;
+        self.offset += consumed;
+        self.indentation = indentation;
+        self.next = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        grammar::error::ResumeError,
+        yaml::{document::document_state_machine, kv::kv_state_machine},
+    };
+
+    use super::{Checkpoint, ExecutionState, RecordedRun, TextEdit};
+
+    fn document(values: &[&str]) -> String {
+        let mut text = String::from("---\n");
+        for value in values {
+            text.push_str(&format!("key:{}\n", value));
+        }
+        text.push_str("---");
+        text
+    }
+
+    fn states(run: &RecordedRun) -> Vec<&ExecutionState> {
+        run.checkpoints
+            .iter()
+            .map(|checkpoint: &Checkpoint| &checkpoint.state)
+            .collect()
+    }
+
+    #[test]
+    fn test_run_resumes_from_serialised_state() {
+        let machine = document_state_machine(0);
+        let text = document(&["a", "b", "c"]);
+        let run = machine.validate_recorded(&text).unwrap();
+        assert_eq!((run.accepted, run.offset), machine.validate(&text));
+
+        let middle = &run.checkpoints[run.checkpoints.len() / 2].state;
+        assert!(middle.frames.len() > 1);
+        let state = ExecutionState::from_json(&middle.to_json()).unwrap();
+        let resumed = machine.resume(&text, &state).unwrap();
+        assert_eq!(
+            (resumed.accepted, resumed.offset),
+            (run.accepted, run.offset)
+        );
+
+        let mut broken = state.clone();
+        broken.frames[1].machine = "sequence".to_string();
+        assert_eq!(
+            machine.resume(&text, &broken),
+            Err(ResumeError::InvalidFrame { frame: 1 })
+        );
+    }
+
+    #[test]
+    fn test_resume_rejects_tampered_offsets() {
+        let machine = document_state_machine(0);
+        let text = document(&["a", "b", "c"]);
+        let run = machine.validate_recorded(&text).unwrap();
+        let middle = &run.checkpoints[run.checkpoints.len() / 2].state;
+        let json: serde_json::Value = serde_json::from_str(&middle.to_json()).unwrap();
+        let parent = json["frames"][0]["offset"].as_u64().unwrap();
+        assert!(parent > 0);
+        let tampered = |frame: usize, field: &str, value: u64| {
+            let mut json = json.clone();
+            json["frames"][frame][field] = value.into();
+            ExecutionState::from_json(&json.to_string()).unwrap()
+        };
+
+        let cases = [
+            (tampered(0, "start", parent + 1), 0),
+            (tampered(0, "offset", 1000), 0),
+            (tampered(1, "start", parent - 1), 1),
+        ];
+        for (state, frame) in cases {
+            assert_eq!(
+                machine.resume(&text, &state),
+                Err(ResumeError::InvalidFrame { frame })
+            );
+        }
+        let inside_char = format!("é{}", &text[2..]);
+        assert_eq!(
+            machine.resume(&inside_char, &tampered(0, "offset", 1)),
+            Err(ResumeError::InvalidFrame { frame: 0 })
+        );
+    }
+
+    #[test]
+    fn test_revalidation_stops_once_resynchronised() {
+        let machine = document_state_machine(0);
+        let values = vec!["value"; 40];
+        let before = document(&values);
+        let previous = machine.validate_recorded(&before).unwrap();
+
+        // `key:value\n` is 10 bytes, edit the value of the 21st entry
+        let start = "---\n".len() + 20 * 10 + "key:".len();
+        let edit = TextEdit {
+            range: start..start + "value".len(),
+            replacement: "other words".to_string(),
+        };
+        let mut after = before.clone();
+        after.replace_range(edit.range.clone(), &edit.replacement);
+
+        let revalidated = machine.revalidate(&previous, &after, &edit).unwrap();
+        let full = machine.validate_recorded(&after).unwrap();
+        assert_eq!(
+            (revalidated.accepted, revalidated.offset),
+            (full.accepted, full.offset)
+        );
+        assert_eq!(states(&revalidated), states(&full));
+        assert!(revalidated.steps * 5 < full.steps);
+    }
+
+    #[test]
+    fn test_revalidation_reports_broken_edits() {
+        let machine = document_state_machine(0);
+        let before = document(&["a", "b", "c"]);
+        let previous = machine.validate_recorded(&before).unwrap();
+        let edit = TextEdit {
+            range: 15..15,
+            replacement: ";".to_string(),
+        };
+        let mut after = before.clone();
+        after.replace_range(edit.range.clone(), &edit.replacement);
+
+        let revalidated = machine.revalidate(&previous, &after, &edit).unwrap();
+        assert_eq!(
+            (revalidated.accepted, revalidated.offset),
+            machine.validate(&after)
+        );
+        assert!(!revalidated.accepted);
+    }
+
+    #[test]
+    fn test_revalidation_rejects_runs_of_other_machines() {
+        let before = document(&["a", "b", "c"]);
+        let previous = document_state_machine(0)
+            .validate_recorded(&before)
+            .unwrap();
+        let edit = TextEdit {
+            range: 15..16,
+            replacement: "d".to_string(),
+        };
+        let mut after = before.clone();
+        after.replace_range(edit.range.clone(), &edit.replacement);
+
+        assert_eq!(
+            kv_state_machine(0).revalidate(&previous, &after, &edit),
+            Err(ResumeError::InvalidFrame { frame: 0 })
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use std::sync::Arc;
+
+use super::{
+    state::{create_state, State},
+    state_machine::{ExecutionMode, StateMachine, StateMachineBuilder},
+    transition::{EpsilonTransition, Transition},
+};
+
+/// A piece of machine with a single entry and a single exit state, as in
+/// Thompson's construction.
+#[derive(Clone)]
+pub struct Fragment {
+    pub start: Arc<State>,
+    pub end: Arc<State>,
+}
+
+/// Builds machines out of fragments glued together with epsilon transitions.
+/// Alternatives are only told apart by trying them all, so the machines it
+/// builds backtrack.
+pub struct FragmentBuilder {
+    prefix: String,
+    states: Vec<Arc<State>>,
+    transitions: Vec<Arc<dyn Transition>>,
+}
+
+impl FragmentBuilder {
+    /// States are labelled `prefix` followed by their index.
+    pub fn new(prefix: &str) -> Self {
+        FragmentBuilder {
+            prefix: prefix.to_string(),
+            states: Vec::new(),
+            transitions: Vec::new(),
+        }
+    }
+
+    pub fn state(&mut self) -> Arc<State> {
+        self.new_state(false)
+    }
+
+    fn new_state(&mut self, is_final: bool) -> Arc<State> {
+        let label = format!("{}{}", self.prefix, self.states.len());
+        let state = Arc::new(create_state(is_final, &label));
+        self.states.push(state.clone());
+        state
+    }
+
+    fn epsilon(&mut self, from: &Arc<State>, to: &Arc<State>) {
+        self.transitions
+            .push(Arc::new(EpsilonTransition::new(from.clone(), to.clone())));
+    }
+
+    /// A fragment made of the single transition `make` builds between two new states.
+    pub fn transition(
+        &mut self,
+        make: impl FnOnce(Arc<State>, Arc<State>) -> Arc<dyn Transition>,
+    ) -> Fragment {
+        let start = self.state();
+        let end = self.state();
+        self.transitions.push(make(start.clone(), end.clone()));
+        Fragment { start, end }
+    }
+
+    /// Matches the empty string.
+    pub fn empty(&mut self) -> Fragment {
+        let start = self.state();
+        let end = self.state();
+        self.epsilon(&start, &end);
+        Fragment { start, end }
+    }
+
+    /// Matches every fragment one after the other.
+    pub fn concat(&mut self, fragments: Vec<Fragment>) -> Fragment {
+        let mut fragments = fragments.into_iter();
+        let Some(first) = fragments.next() else {
+            return self.empty();
+        };
+        let mut end = first.end;
+        for fragment in fragments {
+            self.epsilon(&end, &fragment.start);
+            end = fragment.end;
+        }
+        Fragment {
+            start: first.start,
+            end,
+        }
+    }
+
+    /// Matches any of the fragments, tried in order.
+    pub fn union(&mut self, fragments: Vec<Fragment>) -> Fragment {
+        let start = self.state();
+        let end = self.state();
+        for fragment in fragments {
+            self.epsilon(&start, &fragment.start);
+            self.epsilon(&fragment.end, &end);
+        }
+        Fragment { start, end }
+    }
+
+    /// Matches the fragment any number of times, none included.
+    pub fn star(&mut self, fragment: Fragment) -> Fragment {
+        let start = self.state();
+        let end = self.state();
+        self.epsilon(&start, &fragment.start);
+        self.epsilon(&fragment.end, &fragment.start);
+        self.epsilon(&fragment.end, &end);
+        self.epsilon(&start, &end);
+        Fragment { start, end }
+    }
+
+    /// Matches the fragment at least once.
+    pub fn plus(&mut self, fragment: Fragment) -> Fragment {
+        let end = self.state();
+        self.epsilon(&fragment.end, &fragment.start);
+        self.epsilon(&fragment.end, &end);
+        Fragment {
+            start: fragment.start,
+            end,
+        }
+    }
+
+    /// Matches the fragment or nothing.
+    pub fn optional(&mut self, fragment: Fragment) -> Fragment {
+        let start = self.state();
+        let end = self.state();
+        self.epsilon(&start, &fragment.start);
+        self.epsilon(&fragment.end, &end);
+        self.epsilon(&start, &end);
+        Fragment { start, end }
+    }
+
+    /// Turns `fragment` into a backtracking machine accepting once it is through.
+    pub fn build(
+        mut self,
+        fragment: Fragment,
+        name: &str,
+        indentation_character: &str,
+    ) -> StateMachine {
+        let accept = self.new_state(true);
+        self.epsilon(&fragment.end, &accept);
+        StateMachineBuilder::new(fragment.start, indentation_character, 0)
+            .name(name)
+            .mode(ExecutionMode::Backtracking)
+            .add_states(self.states)
+            .add_transitions(self.transitions)
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::grammar::transition::{CharTransition, IndentationOperation};
+
+    use super::{Fragment, FragmentBuilder};
+
+    fn char_fragment(builder: &mut FragmentBuilder, c: &str) -> Fragment {
+        let c = c.to_string();
+        builder.transition(|from, to| {
+            Arc::new(CharTransition::new(
+                from,
+                to,
+                c,
+                IndentationOperation::BYPASS,
+            ))
+        })
+    }
+
+    #[test]
+    fn test_fragments_compose() {
+        // a(b|c)*d?
+        let mut builder = FragmentBuilder::new("s");
+        let a = char_fragment(&mut builder, "a");
+        let b = char_fragment(&mut builder, "b");
+        let c = char_fragment(&mut builder, "c");
+        let d = char_fragment(&mut builder, "d");
+        let alternatives = builder.union(vec![b, c]);
+        let repeated = builder.star(alternatives);
+        let optional = builder.optional(d);
+        let fragment = builder.concat(vec![a, repeated, optional]);
+        let machine = builder.build(fragment, "abcd", " ");
+
+        for accepted in ["a", "ab", "acbd", "ad"] {
+            assert!(machine.check(accepted), "{}", accepted);
+        }
+        for rejected in ["", "b", "add", "abdc"] {
+            assert!(!machine.check(rejected), "{}", rejected);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use serde::{Deserialize, Serialize};
+
+use super::input::Input;
+
+/// The blocks a run is nested in, as the stack of the columns they start at,
+/// innermost last. The outermost block starts at column 0 and is not stored.
+///
+/// A block can be indented by any number of blanks more than the block around
+/// it, but every block is indented with the same blank.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Indentation {
+    columns: Vec<usize>,
+    /// The blank blocks are indented with, once one of them is.
+    character: Option<char>,
+}
+
+/// The blanks a line starts with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineIndentation {
+    /// How many blanks there are.
+    pub column: usize,
+    pub character: Option<char>,
+    /// Offset of the first character after the blanks.
+    pub end: usize,
+}
+
+impl Indentation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `levels` blocks deep, each one column further in than the one around it.
+    pub fn levels(levels: usize) -> Self {
+        Indentation {
+            columns: (1..=levels).collect(),
+            character: None,
+        }
+    }
+
+    /// How many blocks deep the run is.
+    pub fn depth(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// The column the innermost block starts at.
+    pub fn column(&self) -> usize {
+        self.columns.last().copied().unwrap_or(0)
+    }
+
+    /// The column the block around the innermost one starts at, if there is one.
+    pub fn parent_column(&self) -> Option<usize> {
+        let depth = self.columns.len();
+        (depth > 0).then(|| {
+            depth
+                .checked_sub(2)
+                .map_or(0, |parent| self.columns[parent])
+        })
+    }
+
+    pub fn columns(&self) -> &[usize] {
+        &self.columns
+    }
+
+    pub fn character(&self) -> Option<char> {
+        self.character
+    }
+
+    /// Opens a block at `column`, indented with `character`.
+    pub fn push(&mut self, column: usize, character: Option<char>) {
+        self.columns.push(column);
+        self.character = self.character.or(character);
+    }
+
+    /// Closes the innermost block.
+    pub fn pop(&mut self) {
+        self.columns.pop();
+    }
+}
+
+/// Measures the blanks starting at `start`. Fails with the offset of the first
+/// blank that differs from the ones before it, or from the blank the blocks of
+/// `indentation` are indented with.
+pub fn measure(
+    input: &Input,
+    start: usize,
+    indentation: &Indentation,
+) -> Result<LineIndentation, usize> {
+    let mut line = LineIndentation {
+        column: 0,
+        character: None,
+        end: start,
+    };
+    while let Some(c) = input.peek(line.end).filter(|c| matches!(c, ' ' | '\t')) {
+        match indentation.character.or(line.character) {
+            Some(expected) if expected != c => return Err(line.end),
+            _ => line.character = Some(c),
+        }
+        line.column += 1;
+        line.end += c.len_utf8();
+    }
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grammar::input::Input;
+
+    use super::{measure, Indentation};
+
+    #[test]
+    fn test_measure_reports_mixed_blanks() {
+        let indentation = Indentation::new();
+        let line = measure(&Input::new("\n   x"), 1, &indentation).unwrap();
+        assert_eq!((line.column, line.character, line.end), (3, Some(' '), 4));
+        assert_eq!(measure(&Input::new("\n \tx"), 1, &indentation), Err(2));
+
+        let mut spaces = Indentation::new();
+        spaces.push(2, Some(' '));
+        assert_eq!(measure(&Input::new("\n\tx"), 1, &spaces), Err(1));
+        assert_eq!(measure(&Input::new("\nx"), 1, &spaces).unwrap().column, 0);
+    }
+
+    #[test]
+    fn test_indentation_stack() {
+        let mut indentation = Indentation::levels(2);
+        assert_eq!(indentation.columns(), &[1, 2]);
+        assert_eq!(indentation.parent_column(), Some(1));
+        indentation.push(6, Some('\t'));
+        assert_eq!((indentation.depth(), indentation.column()), (3, 6));
+        indentation.pop();
+        indentation.pop();
+        assert_eq!(indentation.parent_column(), Some(0));
+        indentation.pop();
+        assert_eq!(
+            (indentation.column(), indentation.parent_column()),
+            (0, None)
+        );
+        assert_eq!(indentation.character(), Some('\t'));
+    }
+}
@@ -0,0 +1,118 @@
+use std::cell::Cell;
+
+/// The text a machine runs over. Offsets are byte offsets into the text and always
+/// fall on character boundaries.
+///
+/// An input can also be a window over a longer stream, starting at offset `base`.
+/// Looking past the end of an incomplete window marks the input as starved: the
+/// run has to be done again once more of the stream is available.
+#[derive(Debug, Clone)]
+pub struct Input<'a> {
+    text: &'a str,
+    base: usize,
+    /// Whether `text` runs to the end of the input.
+    complete: bool,
+    starved: Cell<bool>,
+    /// One past the furthest offset looked at.
+    examined: Cell<usize>,
+}
+
+impl<'a> Input<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Input::window(text, 0, true)
+    }
+
+    /// The part of a stream starting at offset `base`, `complete` if the stream
+    /// ends with it.
+    pub fn window(text: &'a str, base: usize, complete: bool) -> Self {
+        Input {
+            text,
+            base,
+            complete,
+            starved: Cell::new(false),
+            examined: Cell::new(0),
+        }
+    }
+
+    /// The character starting at `offset`, if any.
+    pub fn peek(&self, offset: usize) -> Option<char> {
+        self.examine(offset);
+        let index = offset.checked_sub(self.base)?;
+        if index >= self.text.len() {
+            self.starve();
+            return None;
+        }
+        self.text.get(index..)?.chars().next()
+    }
+
+    pub fn is_end(&self, offset: usize) -> bool {
+        self.examine(offset);
+        let end = offset >= self.len();
+        if end {
+            self.starve();
+        }
+        end
+    }
+
+    /// The offset the text ends at.
+    pub fn len(&self) -> usize {
+        self.base + self.text.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.text
+    }
+
+    /// Whether a run looked past the end of this window before the stream ended.
+    pub fn is_starved(&self) -> bool {
+        self.starved.get()
+    }
+
+    /// One past the furthest offset a run looked at, so the result of the run
+    /// only depends on the text before it.
+    pub fn examined(&self) -> usize {
+        self.examined.get()
+    }
+
+    fn examine(&self, offset: usize) {
+        self.examined.set(self.examined.get().max(offset + 1));
+    }
+
+    fn starve(&self) {
+        if !self.complete {
+            self.starved.set(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Input;
+
+    #[test]
+    fn test_input_peeks_multi_byte_characters() {
+        let input = Input::new("clé:x");
+        assert_eq!(input.peek(2), Some('é'));
+        assert_eq!(input.peek(4), Some(':'));
+        assert_eq!(input.peek(3), None);
+        assert_eq!(input.peek(6), None);
+        assert!(input.is_end(6));
+    }
+
+    #[test]
+    fn test_window_is_starved_past_its_end() {
+        let input = Input::window("b:c", 2, false);
+        assert_eq!(input.peek(2), Some('b'));
+        assert!(!input.is_starved());
+        assert_eq!(input.peek(5), None);
+        assert!(input.is_starved());
+
+        let input = Input::window("b:c", 2, true);
+        assert!(input.is_end(5));
+        assert!(!input.is_starved());
+    }
+}
@@ -0,0 +1,279 @@
+use std::sync::Arc;
+
+use super::{
+    class::CharClass,
+    state::{create_state, State},
+    state_machine::{ExecutionMode, StateMachine, StateMachineBuilder},
+    transition::{
+        create_word_transition, CaptureTransition, CharTransition, ClassTransition,
+        EpsilonTransition, GroupTransition, IndentationOperation, Transition,
+    },
+};
+
+/// What an edge of `machine!` matches.
+pub enum EdgeLabel {
+    Char(char),
+    Word(String),
+    Class(CharClass),
+    Group(StateMachine),
+    Epsilon,
+}
+
+impl From<char> for EdgeLabel {
+    fn from(value: char) -> Self {
+        EdgeLabel::Char(value)
+    }
+}
+
+impl From<&str> for EdgeLabel {
+    fn from(value: &str) -> Self {
+        EdgeLabel::Word(value.to_string())
+    }
+}
+
+impl From<CharClass> for EdgeLabel {
+    fn from(value: CharClass) -> Self {
+        EdgeLabel::Class(value)
+    }
+}
+
+impl From<StateMachine> for EdgeLabel {
+    fn from(value: StateMachine) -> Self {
+        EdgeLabel::Group(value)
+    }
+}
+
+struct Edge {
+    from: usize,
+    to: usize,
+    label: EdgeLabel,
+    operation: IndentationOperation,
+    capture: Option<String>,
+    priority: i32,
+}
+
+/// Collects the states and edges written in `machine!`. States are created
+/// once every edge is known, as any mention of a state can mark it final.
+pub struct MachineDraft {
+    states: Vec<(String, bool)>,
+    edges: Vec<Edge>,
+}
+
+impl Default for MachineDraft {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MachineDraft {
+    pub fn new() -> Self {
+        MachineDraft {
+            states: Vec::new(),
+            edges: Vec::new(),
+        }
+    }
+
+    fn state(&mut self, label: &str, is_final: bool) -> usize {
+        match self.states.iter().position(|(known, _)| known == label) {
+            Some(index) => {
+                self.states[index].1 |= is_final;
+                index
+            }
+            None => {
+                self.states.push((label.to_string(), is_final));
+                self.states.len() - 1
+            }
+        }
+    }
+
+    /// Adds an edge, the first state of the first edge being the start state.
+    pub fn edge(
+        &mut self,
+        (from, from_final): (&str, bool),
+        (to, to_final): (&str, bool),
+        label: EdgeLabel,
+        operation: IndentationOperation,
+    ) -> &mut Self {
+        let from = self.state(from, from_final);
+        let to = self.state(to, to_final);
+        self.edges.push(Edge {
+            from,
+            to,
+            label,
+            operation,
+            capture: None,
+            priority: 0,
+        });
+        self
+    }
+
+    /// Sets the priority of the last edge added, see
+    /// `StateMachineBuilder::add_transition_with_priority`.
+    pub fn priority(&mut self, priority: i32) -> &mut Self {
+        if let Some(edge) = self.edges.last_mut() {
+            edge.priority = priority;
+        }
+        self
+    }
+
+    /// Labels the text matched by the last edge added, see `StateMachine::captures`.
+    pub fn capture(&mut self, label: &str) -> &mut Self {
+        if let Some(edge) = self.edges.last_mut() {
+            edge.capture = Some(label.to_string());
+        }
+        self
+    }
+
+    pub fn build(self, name: &str, indentation: i32, mode: ExecutionMode) -> StateMachine {
+        let states: Vec<Arc<State>> = self
+            .states
+            .iter()
+            .map(|(label, is_final)| Arc::new(create_state(*is_final, label)))
+            .collect();
+        let transitions: Vec<(Arc<dyn Transition>, i32)> = self
+            .edges
+            .into_iter()
+            .map(|edge| {
+                let from = states[edge.from].clone();
+                let to = states[edge.to].clone();
+                let transition: Arc<dyn Transition> = match edge.label {
+                    EdgeLabel::Char(c) => {
+                        Arc::new(CharTransition::new(from, to, c.to_string(), edge.operation))
+                    }
+                    EdgeLabel::Word(word) => {
+                        create_word_transition(from, to, word, edge.operation, indentation)
+                    }
+                    EdgeLabel::Class(class) => {
+                        Arc::new(ClassTransition::new(from, to, class, edge.operation))
+                    }
+                    EdgeLabel::Group(machine) => {
+                        Arc::new(GroupTransition::new(from, to, machine, edge.operation))
+                    }
+                    EdgeLabel::Epsilon => Arc::new(EpsilonTransition::new(from, to)),
+                };
+                let transition: Arc<dyn Transition> = match edge.capture {
+                    Some(label) => Arc::new(CaptureTransition::new(&label, transition)),
+                    None => transition,
+                };
+                (transition, edge.priority)
+            })
+            .collect();
+
+        let start = states
+            .first()
+            .cloned()
+            .expect("a machine needs at least one edge");
+        let mut builder = StateMachineBuilder::new(start, " ", indentation);
+        builder.name(name).mode(mode).add_states(states);
+        for (transition, priority) in transitions {
+            builder.add_transition_with_priority(transition, priority);
+        }
+        builder.try_build().unwrap_or_else(|errors| {
+            let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            panic!("{}", errors.join("\n"))
+        })
+    }
+}
+
+/// Builds a `StateMachine` out of edges:
+///
+/// ```ignore
+/// machine! {
+///     name: "kv", indentation: indentation;
+///     start -> key: key_state_machine(indentation);
+///     key -> column: ':';
+///     column -> value*: value_state_machine(indentation);
+///     column -> nested: '\n' => INCREMENT;
+///     nested -> key: epsilon;
+/// }
+/// ```
+///
+/// An edge matches a `char`, a word given as `&str`, a `CharClass`, a nested
+/// `StateMachine` run as a group, or `epsilon`. A `*` after a state marks it
+/// final, `=> OPERATION` sets the `IndentationOperation` of the edge,
+/// `, capture: "label"` makes it capture what it matches and `, priority: n`
+/// sets its priority. The source of the first edge is the start state.
+/// `mode: Backtracking` can follow the indentation. Panics if the machine is
+/// not well formed, see `StateMachineBuilder::try_build`.
+#[macro_export]
+macro_rules! machine {
+    (
+        name: $name:expr, indentation: $indentation:expr $(, mode: $mode:ident)? ;
+        $($edges:tt)*
+    ) => {{
+        let mut draft = $crate::grammar::macros::MachineDraft::new();
+        $crate::machine!(@edges draft; $($edges)*);
+        draft.build($name, $indentation, $crate::machine!(@mode $($mode)?))
+    }};
+
+    (@mode) => { $crate::grammar::state_machine::ExecutionMode::default() };
+    (@mode $mode:ident) => { $crate::grammar::state_machine::ExecutionMode::$mode };
+    (@operation) => { $crate::grammar::transition::IndentationOperation::BYPASS };
+    (@operation $operation:ident) => {
+        $crate::grammar::transition::IndentationOperation::$operation
+    };
+
+    (@edges $draft:ident;) => {};
+    (@edges $draft:ident; $from:ident * -> $to:ident * : $($rest:tt)*) => {
+        $crate::machine!(@label $draft, (stringify!($from), true), (stringify!($to), true); $($rest)*);
+    };
+    (@edges $draft:ident; $from:ident * -> $to:ident : $($rest:tt)*) => {
+        $crate::machine!(@label $draft, (stringify!($from), true), (stringify!($to), false); $($rest)*);
+    };
+    (@edges $draft:ident; $from:ident -> $to:ident * : $($rest:tt)*) => {
+        $crate::machine!(@label $draft, (stringify!($from), false), (stringify!($to), true); $($rest)*);
+    };
+    (@edges $draft:ident; $from:ident -> $to:ident : $($rest:tt)*) => {
+        $crate::machine!(@label $draft, (stringify!($from), false), (stringify!($to), false); $($rest)*);
+    };
+
+    (@label $draft:ident, $from:expr, $to:expr; epsilon ; $($rest:tt)*) => {
+        $draft.edge(
+            $from,
+            $to,
+            $crate::grammar::macros::EdgeLabel::Epsilon,
+            $crate::grammar::transition::IndentationOperation::BYPASS,
+        );
+        $crate::machine!(@edges $draft; $($rest)*);
+    };
+    (@label $draft:ident, $from:expr, $to:expr;
+        $label:expr $(=> $operation:ident)? $(, capture: $capture:expr)?
+        $(, priority: $priority:expr)? ; $($rest:tt)*) => {
+        $draft.edge(
+            $from,
+            $to,
+            $crate::grammar::macros::EdgeLabel::from($label),
+            $crate::machine!(@operation $($operation)?),
+        )$(.capture($capture))?$(.priority($priority))?;
+        $crate::machine!(@edges $draft; $($rest)*);
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grammar::class::CharClassBuilder;
+
+    #[test]
+    fn test_machine_macro_builds_every_kind_of_edge() {
+        let letters = CharClassBuilder::new().add_range('a', 'z').build();
+        let machine = machine! {
+            name: "greeting", indentation: 0, mode: Backtracking;
+            start -> hello: "hello";
+            hello -> space: ' ';
+            space -> name*: letters.clone();
+            name -> name: letters;
+            name -> newline: '\n' => INCREMENT;
+            newline -> start: epsilon;
+        };
+        assert_eq!(machine.name(), "greeting");
+        assert_eq!(machine.start().label, "start");
+        assert!(machine
+            .states()
+            .iter()
+            .any(|state| state.label == "name" && state.is_final()));
+        assert!(machine.check("hello bob"));
+        assert!(machine.check("hello bob\n hello alice"));
+        assert!(!machine.check("hello bob\nhello alice"));
+        assert!(!machine.check("hello "));
+    }
+}
@@ -1,3 +1,22 @@
+pub mod capture;
+pub mod class;
+pub mod combinator;
+pub mod context;
+pub mod definition;
+pub mod dfa;
+pub mod dot;
+pub mod error;
+pub mod execution;
+pub mod fragment;
+pub mod indentation;
+pub mod input;
+pub mod macros;
+pub mod overlap;
+pub mod regex;
+pub mod registry;
 pub mod state;
 pub mod state_machine;
+pub mod stream;
+pub mod syntax;
+pub mod trace;
 pub mod transition;
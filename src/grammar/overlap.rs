@@ -0,0 +1,236 @@
+use core::fmt;
+
+use super::{
+    state_machine::StateMachine,
+    transition::{Lookahead, TransitionKind},
+};
+
+/// How many group machines deep the first characters of a group are looked for.
+const MAX_GROUP_DEPTH: usize = 8;
+
+/// Two transitions leaving the same state, with the same priority, that can
+/// match at the same offset. Which one a run takes depends on the order they
+/// were added in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overlap {
+    pub state: String,
+    pub priority: i32,
+    pub first: String,
+    pub second: String,
+}
+
+impl fmt::Display for Overlap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "in state {}, {} and {} can both match at priority {}",
+            self.state, self.first, self.second, self.priority
+        )
+    }
+}
+
+/// The characters a transition can start with.
+enum First {
+    /// Anything, including nothing at all.
+    Any,
+    /// Sorted, disjoint ranges.
+    Ranges(Vec<(char, char)>),
+}
+
+impl First {
+    fn overlaps(&self, other: &First) -> bool {
+        let (First::Ranges(left), First::Ranges(right)) = (self, other) else {
+            return true;
+        };
+        let (mut i, mut j) = (0, 0);
+        while i < left.len() && j < right.len() {
+            let (low, high) = (left[i].0.max(right[j].0), left[i].1.min(right[j].1));
+            if low <= high {
+                return true;
+            }
+            if left[i].1 < right[j].1 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        false
+    }
+}
+
+fn first(kind: &TransitionKind, depth: usize) -> First {
+    match kind {
+        TransitionKind::Char(value) => match value.chars().next() {
+            Some(c) => First::Ranges(vec![(c, c)]),
+            None => First::Ranges(Vec::new()),
+        },
+        TransitionKind::Class(class) => First::Ranges(class.to_ranges()),
+        TransitionKind::Group(machine) if depth < MAX_GROUP_DEPTH => {
+            first_of_machine(machine, depth + 1)
+        }
+        // epsilon transitions consume nothing, and rules are only known at run time
+        _ => First::Any,
+    }
+}
+
+fn first_of_machine(machine: &StateMachine, depth: usize) -> First {
+    let mut ranges = Vec::new();
+    let mut pending = vec![0];
+    let mut seen = vec![false; machine.states().len()];
+    while let Some(state) = pending.pop() {
+        if std::mem::replace(&mut seen[state], true) {
+            continue;
+        }
+        // a group accepting the empty word matches anywhere
+        if machine.states()[state].is_final() {
+            return First::Any;
+        }
+        for (transition, target) in machine.outgoing(state).iter().zip(machine.targets(state)) {
+            match transition.kind() {
+                // a lookahead may only narrow what follows it
+                TransitionKind::Epsilon | TransitionKind::Lookahead { .. } => pending.push(*target),
+                kind => match first(&kind, depth) {
+                    First::Any => return First::Any,
+                    First::Ranges(found) => ranges.extend(found),
+                },
+            }
+        }
+    }
+    ranges.sort_unstable();
+    let mut merged: Vec<(char, char)> = Vec::new();
+    for (low, high) in ranges {
+        match merged.last_mut() {
+            Some(last) if low <= last.1 => last.1 = last.1.max(high),
+            _ => merged.push((low, high)),
+        }
+    }
+    First::Ranges(merged)
+}
+
+/// Whether a transition of `kind` can match at an offset where the input
+/// starts with `c`, `None` being the end of the input. Errs on the side of yes.
+pub(super) fn can_start_with(kind: &TransitionKind, c: Option<char>) -> bool {
+    can_start_with_at(kind, c, 0)
+}
+
+fn can_start_with_at(kind: &TransitionKind, c: Option<char>, depth: usize) -> bool {
+    match (kind, c) {
+        (TransitionKind::Char(value), Some(c)) => value.starts_with(c),
+        (TransitionKind::Class(class), Some(c)) => class.matches(c),
+        (TransitionKind::Char(_) | TransitionKind::Class(_), None) => false,
+        (
+            TransitionKind::Lookahead {
+                condition: Lookahead::Char(_) | Lookahead::Class(_),
+                negative,
+            },
+            None,
+        ) => *negative,
+        (
+            TransitionKind::Lookahead {
+                condition: Lookahead::Char(expected),
+                negative,
+            },
+            Some(c),
+        ) => (*expected == c) != *negative,
+        (
+            TransitionKind::Lookahead {
+                condition: Lookahead::Class(class),
+                negative,
+            },
+            Some(c),
+        ) => class.matches(c) != *negative,
+        (TransitionKind::Group(machine), _) if depth < MAX_GROUP_DEPTH => {
+            let mut pending = vec![0];
+            let mut seen = vec![false; machine.states().len()];
+            while let Some(state) = pending.pop() {
+                if std::mem::replace(&mut seen[state], true) {
+                    continue;
+                }
+                if machine.states()[state].is_final() {
+                    return true;
+                }
+                for (transition, target) in
+                    machine.outgoing(state).iter().zip(machine.targets(state))
+                {
+                    match transition.kind() {
+                        TransitionKind::Epsilon => pending.push(*target),
+                        kind @ TransitionKind::Lookahead { .. }
+                            if can_start_with_at(&kind, c, depth + 1) =>
+                        {
+                            pending.push(*target)
+                        }
+                        kind if can_start_with_at(&kind, c, depth + 1) => return true,
+                        _ => {}
+                    }
+                }
+            }
+            false
+        }
+        _ => true,
+    }
+}
+
+impl StateMachine {
+    /// Pairs of transitions whose order decides which one a run takes: both
+    /// leave the same state with the same priority and can start with the same
+    /// character. Groups are compared by the characters they can start with, so
+    /// some reported pairs may never match the same input.
+    pub fn overlaps(&self) -> Vec<Overlap> {
+        let mut overlaps = Vec::new();
+        for state in 0..self.states().len() {
+            let transitions = self.outgoing(state);
+            let priorities = self.priorities(state);
+            let firsts: Vec<First> = transitions
+                .iter()
+                .map(|transition| first(&transition.kind(), 0))
+                .collect();
+            for i in 0..transitions.len() {
+                for j in i + 1..transitions.len() {
+                    if priorities[i] == priorities[j] && firsts[i].overlaps(&firsts[j]) {
+                        overlaps.push(Overlap {
+                            state: self.states()[state].label.clone(),
+                            priority: priorities[i],
+                            first: transitions[i].kind().to_string(),
+                            second: transitions[j].kind().to_string(),
+                        });
+                    }
+                }
+            }
+        }
+        overlaps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{grammar::class::CharClassBuilder, machine, yaml::value::value_state_machine};
+
+    use super::Overlap;
+
+    #[test]
+    fn test_overlaps_lists_transitions_of_same_priority() {
+        let letters = CharClassBuilder::new().add_range('a', 'z').build();
+        let digits = CharClassBuilder::new().add_range('0', '9').build();
+        let machine = machine! {
+            name: "overlapping", indentation: 0;
+            start -> word*: letters;
+            start -> number*: digits;
+            start -> ex*: 'x';
+            start -> why*: 'y', priority: 1;
+        };
+        assert_eq!(
+            machine.overlaps(),
+            vec![Overlap {
+                state: "start".to_string(),
+                priority: 0,
+                first: "[a-z]".to_string(),
+                second: "\"x\"".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_value_machine_does_not_depend_on_transition_order() {
+        assert!(value_state_machine(0).overlaps().is_empty());
+    }
+}
@@ -0,0 +1,579 @@
+use std::sync::Arc;
+
+use super::{
+    class::{CharClass, CharClassBuilder, ClassSyntax},
+    error::RegexError,
+    fragment::{Fragment, FragmentBuilder},
+    state_machine::StateMachine,
+    transition::{
+        CharTransition, ClassTransition, IndentationOperation, Lookahead,
+        NegativeLookaheadTransition,
+    },
+};
+
+/// The most copies of an expression a bounded repetition can make.
+const MAX_REPETITION: u32 = 1000;
+
+/// The most transitions a pattern can compile to, nested repetitions
+/// multiplying the copies they make.
+const MAX_TRANSITIONS: usize = 100_000;
+
+enum Expr {
+    Char(char),
+    Class(CharClass),
+    /// Holds at the end of the input or of a line.
+    End,
+    Sequence(Vec<Expr>),
+    Alternation(Vec<Expr>),
+    Star(Box<Expr>),
+    Plus(Box<Expr>),
+    Optional(Box<Expr>),
+    Repeat {
+        expr: Box<Expr>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+impl Expr {
+    /// Roughly how many transitions the expression compiles to.
+    fn size(&self) -> usize {
+        match self {
+            Expr::Char(_) | Expr::Class(_) | Expr::End => 1,
+            Expr::Sequence(items) => items.iter().map(Expr::size).fold(0, usize::saturating_add),
+            Expr::Alternation(items) => items
+                .iter()
+                .map(|item| item.size().saturating_add(2))
+                .fold(0, usize::saturating_add),
+            Expr::Star(expr) | Expr::Plus(expr) | Expr::Optional(expr) => {
+                expr.size().saturating_add(2)
+            }
+            Expr::Repeat { expr, min, max } => {
+                let copies = max.unwrap_or(*min) as usize + 1;
+                expr.size().saturating_add(2).saturating_mul(copies)
+            }
+        }
+    }
+}
+
+impl StateMachine {
+    /// Compiles the regular expression `pattern` into a machine named `name`,
+    /// to be run on its own or as a group.
+    ///
+    /// Patterns are made of characters, `.`, classes like `[^a-z\p{Lu}]`, the
+    /// `\d`, `\w` and `\s` shorthands, also allowed in classes, and their
+    /// negations, groups, alternatives separated by `|`, the `*`, `+` and `?`
+    /// repetitions and bounded ones like `{2}`, `{2,}` or `{2,4}`. Patterns
+    /// expanding to more than 100000 transitions are rejected. A machine always
+    /// matches from the offset it is started at, so `^` may only start the
+    /// pattern; `$` may only end it and holds at the end of the input or of a
+    /// line, which `determinize` supports.
+    pub fn from_regex(pattern: &str, name: &str) -> Result<StateMachine, RegexError> {
+        let expr = Parser::new(pattern).pattern()?;
+        let mut builder = FragmentBuilder::new("r");
+        let fragment = compile(&mut builder, &expr);
+        Ok(builder.build(fragment, name, " "))
+    }
+}
+
+fn compile(builder: &mut FragmentBuilder, expr: &Expr) -> Fragment {
+    match expr {
+        Expr::Char(c) => builder.transition(|from, to| {
+            Arc::new(CharTransition::new(
+                from,
+                to,
+                c.to_string(),
+                IndentationOperation::BYPASS,
+            ))
+        }),
+        Expr::Class(class) => builder.transition(|from, to| {
+            Arc::new(ClassTransition::new(
+                from,
+                to,
+                class.clone(),
+                IndentationOperation::BYPASS,
+            ))
+        }),
+        Expr::End => {
+            let rest_of_line = CharClassBuilder::new().negate().add_char('\n').build();
+            builder.transition(|from, to| {
+                Arc::new(NegativeLookaheadTransition::new(
+                    from,
+                    to,
+                    Lookahead::Class(rest_of_line),
+                ))
+            })
+        }
+        Expr::Sequence(items) => {
+            let fragments = items.iter().map(|item| compile(builder, item)).collect();
+            builder.concat(fragments)
+        }
+        Expr::Alternation(items) => {
+            let fragments = items.iter().map(|item| compile(builder, item)).collect();
+            builder.union(fragments)
+        }
+        Expr::Star(inner) => {
+            let fragment = compile(builder, inner);
+            builder.star(fragment)
+        }
+        Expr::Plus(inner) => {
+            let fragment = compile(builder, inner);
+            builder.plus(fragment)
+        }
+        Expr::Optional(inner) => {
+            let fragment = compile(builder, inner);
+            builder.optional(fragment)
+        }
+        Expr::Repeat { expr, min, max } => {
+            // Each copy needs states of its own, so the expression is compiled
+            // once per copy.
+            let mut fragments: Vec<Fragment> = (0..*min).map(|_| compile(builder, expr)).collect();
+            match max {
+                None => {
+                    let fragment = compile(builder, expr);
+                    fragments.push(builder.star(fragment));
+                }
+                Some(max) => {
+                    // x{1,3} is x(x(x)?)?, so shorter matches are tried last.
+                    let mut optional: Option<Fragment> = None;
+                    for _ in *min..*max {
+                        let fragment = compile(builder, expr);
+                        let fragment = match optional.take() {
+                            Some(rest) => builder.concat(vec![fragment, rest]),
+                            None => fragment,
+                        };
+                        optional = Some(builder.optional(fragment));
+                    }
+                    fragments.extend(optional);
+                }
+            }
+            builder.concat(fragments)
+        }
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Self {
+        Parser { source, offset: 0 }
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.bump();
+            return true;
+        }
+        false
+    }
+
+    fn pattern(&mut self) -> Result<Expr, RegexError> {
+        self.eat('^');
+        let expr = self.alternation()?;
+        if self.peek().is_some() {
+            return Err(self.error(self.offset, "unmatched )"));
+        }
+        if expr.size() > MAX_TRANSITIONS {
+            return Err(self.error(
+                0,
+                format!(
+                    "pattern expands to more than {} transitions",
+                    MAX_TRANSITIONS
+                ),
+            ));
+        }
+        Ok(expr)
+    }
+
+    fn alternation(&mut self) -> Result<Expr, RegexError> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.eat('|') {
+            alternatives.push(self.sequence()?);
+        }
+        Ok(match alternatives.len() {
+            1 => alternatives.remove(0),
+            _ => Expr::Alternation(alternatives),
+        })
+    }
+
+    fn sequence(&mut self) -> Result<Expr, RegexError> {
+        let mut items = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some('|' | ')') => break,
+                Some('$') => {
+                    let offset = self.offset;
+                    self.bump();
+                    if self.peek().is_some() {
+                        return Err(self.error(offset, "$ may only end the pattern"));
+                    }
+                    items.push(Expr::End);
+                }
+                Some(_) => items.push(self.postfix()?),
+            }
+        }
+        Ok(match items.len() {
+            1 => items.remove(0),
+            _ => Expr::Sequence(items),
+        })
+    }
+
+    fn postfix(&mut self) -> Result<Expr, RegexError> {
+        let expr = Box::new(self.atom()?);
+        let offset = self.offset;
+        let expr = match self.peek() {
+            Some('{') => {
+                let (min, max) = self.bounds()?;
+                let repeat = Expr::Repeat { expr, min, max };
+                if repeat.size() > MAX_TRANSITIONS {
+                    return Err(self.error(
+                        offset,
+                        format!(
+                            "repetition expands to more than {} transitions",
+                            MAX_TRANSITIONS
+                        ),
+                    ));
+                }
+                repeat
+            }
+            Some('*') => Expr::Star(expr),
+            Some('+') => Expr::Plus(expr),
+            Some('?') => Expr::Optional(expr),
+            _ => return Ok(*expr),
+        };
+        if matches!(self.source[offset..].chars().next(), Some('*' | '+' | '?')) {
+            self.bump();
+        }
+        match self.peek() {
+            Some('?') => Err(self.error(offset, "lazy repetitions are not supported")),
+            Some('+') => Err(self.error(offset, "possessive repetitions are not supported")),
+            Some('*' | '{') => Err(self.error(self.offset, "nothing to repeat")),
+            _ => Ok(expr),
+        }
+    }
+
+    /// Parses a bounded repetition such as `{2,4}`.
+    fn bounds(&mut self) -> Result<(u32, Option<u32>), RegexError> {
+        let offset = self.offset;
+        self.bump();
+        let min = self
+            .number()?
+            .ok_or_else(|| self.error(self.offset, "expected a repetition count"))?;
+        let max = if self.eat(',') {
+            self.number()?
+        } else {
+            Some(min)
+        };
+        if !self.eat('}') {
+            return Err(self.error(self.offset, "expected }"));
+        }
+        if max.is_some_and(|max| max < min) {
+            return Err(self.error(offset, "repetition bounds are out of order"));
+        }
+        if max.unwrap_or(min) > MAX_REPETITION {
+            return Err(self.error(
+                offset,
+                format!("repetitions are limited to {}", MAX_REPETITION),
+            ));
+        }
+        Ok((min, max))
+    }
+
+    fn number(&mut self) -> Result<Option<u32>, RegexError> {
+        let start = self.offset;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+        }
+        let digits = &self.source[start..self.offset];
+        if digits.is_empty() {
+            return Ok(None);
+        }
+        digits
+            .parse()
+            .map(Some)
+            .map_err(|_| self.error(start, "repetition count is too large"))
+    }
+
+    fn atom(&mut self) -> Result<Expr, RegexError> {
+        let offset = self.offset;
+        match self.bump() {
+            Some('(') => {
+                if self.peek() == Some('?') {
+                    return Err(self.error(offset, "lookarounds and group flags are not supported"));
+                }
+                let expr = self.alternation()?;
+                if !self.eat(')') {
+                    return Err(self.error(offset, "unmatched ("));
+                }
+                Ok(expr)
+            }
+            Some('[') => Ok(Expr::Class(self.class(offset)?)),
+            Some('.') => Ok(Expr::Class(
+                CharClassBuilder::new().negate().add_char('\n').build(),
+            )),
+            Some('\\') => match self.shorthand()? {
+                Some(class) => Ok(Expr::Class(class)),
+                None => Ok(Expr::Char(self.escape()?)),
+            },
+            Some('*' | '+' | '?' | '{') => Err(self.error(offset, "nothing to repeat")),
+            Some('^') => Err(self.error(offset, "^ may only start the pattern")),
+            Some(c) => Ok(Expr::Char(c)),
+            None => Err(self.error(offset, "expected an expression")),
+        }
+    }
+
+    /// Parses a `\d`, `\w` or `\s` shorthand or their negation once its
+    /// backslash is consumed, if one follows.
+    fn shorthand(&mut self) -> Result<Option<CharClass>, RegexError> {
+        let mut builder = CharClassBuilder::new();
+        let Some(c) = self.peek() else {
+            return Ok(None);
+        };
+        if !add_shorthand(&mut builder, c.to_ascii_lowercase()) {
+            return Ok(None);
+        }
+        self.bump();
+        if c.is_ascii_uppercase() {
+            builder.negate();
+        }
+        Ok(Some(builder.build()))
+    }
+}
+
+impl ClassSyntax for Parser<'_> {
+    type Error = RegexError;
+
+    const UNTERMINATED_CLASS: &'static str = "unmatched [";
+
+    fn source(&self) -> &str {
+        self.source
+    }
+
+    fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn set_offset(&mut self, offset: usize) {
+        self.offset = offset;
+    }
+
+    fn error(&self, offset: usize, message: impl Into<String>) -> RegexError {
+        RegexError {
+            offset,
+            message: message.into(),
+        }
+    }
+
+    fn other_escape(&mut self, offset: usize, c: Option<char>) -> Result<char, RegexError> {
+        match c {
+            Some('1'..='9') => Err(self.error(offset, "backreferences are not supported")),
+            Some('b' | 'B') => Err(self.error(offset, "word boundaries are not supported")),
+            Some(c) if c.is_ascii_punctuation() => Ok(c),
+            Some(_) => Err(self.error(offset, "unknown escape sequence")),
+            None => Err(self.error(offset, "pattern ends with a backslash")),
+        }
+    }
+
+    /// Takes the `\d`, `\w` and `\s` shorthands and rejects their negations
+    /// and POSIX classes.
+    fn special_item(
+        &mut self,
+        builder: &mut CharClassBuilder,
+        c: char,
+        item: usize,
+    ) -> Result<bool, RegexError> {
+        match (c, self.peek()) {
+            ('\\', Some(c @ ('d' | 'w' | 's'))) => {
+                self.bump();
+                Ok(add_shorthand(builder, c))
+            }
+            ('\\', Some('D' | 'W' | 'S')) => {
+                Err(self.error(item, "negated shorthands are not supported in classes"))
+            }
+            ('[', Some(':')) => Err(self.error(item, "POSIX classes are not supported")),
+            _ => Ok(false),
+        }
+    }
+}
+
+/// Adds the characters of the `\d`, `\w` or `\s` shorthand named by `c`, if
+/// it names one.
+fn add_shorthand(builder: &mut CharClassBuilder, c: char) -> bool {
+    match c {
+        'd' => builder.add_range('0', '9'),
+        'w' => builder
+            .add_range('a', 'z')
+            .add_range('A', 'Z')
+            .add_range('0', '9')
+            .add_char('_'),
+        's' => builder.add_chars(" \t\n\r"),
+        _ => return false,
+    };
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::grammar::{
+        error::RegexError,
+        state::create_state,
+        state_machine::{StateMachine, StateMachineBuilder},
+        transition::{CharTransition, GroupTransition, IndentationOperation},
+    };
+
+    #[test]
+    fn test_version_regex() {
+        let machine =
+            StateMachine::from_regex(r"^v?\d+\.\d+(\.\d+)?(-[a-z0-9.]+)?$", "version").unwrap();
+        for accepted in ["1.2", "v1.2.3", "10.0.1-rc.1"] {
+            assert!(machine.check(accepted), "{}", accepted);
+        }
+        for rejected in ["", "1", "1.", "v.1", "1.2.3.4", "1.2-"] {
+            assert!(!machine.check(rejected), "{}", rejected);
+        }
+    }
+
+    #[test]
+    fn test_alternation_and_bounded_repetition() {
+        let machine = StateMachine::from_regex("(ab|c){2,3}x{2}y{1,}", "repeat").unwrap();
+        for accepted in ["abcxxy", "cccxxyyy", "ababcxxy"] {
+            assert!(machine.check(accepted), "{}", accepted);
+        }
+        for rejected in ["cxxy", "ccccxxy", "ccxy", "ccxx"] {
+            assert!(!machine.check(rejected), "{}", rejected);
+        }
+
+        let optional = StateMachine::from_regex("a{0,2}b", "optional").unwrap();
+        for accepted in ["b", "ab", "aab"] {
+            assert!(optional.check(accepted), "{}", accepted);
+        }
+        assert!(!optional.check("aaab"));
+    }
+
+    #[test]
+    fn test_regex_as_group() {
+        let version = StateMachine::from_regex(r"\d+(\.\d+)*$", "version").unwrap();
+        let s0 = Arc::new(create_state(false, "s0"));
+        let s1 = Arc::new(create_state(false, "s1"));
+        let s2 = Arc::new(create_state(true, "s2"));
+        let machine = StateMachineBuilder::new(s0.clone(), " ", 0)
+            .add_states(vec![s0.clone(), s1.clone(), s2.clone()])
+            .add_transition(Arc::new(CharTransition::new(
+                s0,
+                s1.clone(),
+                "v".to_string(),
+                IndentationOperation::BYPASS,
+            )))
+            .add_transition(Arc::new(GroupTransition::new(
+                s1,
+                s2,
+                version,
+                IndentationOperation::BYPASS,
+            )))
+            .build();
+        assert!(machine.check("v1.22.3"));
+        assert!(!machine.check("v1..2"));
+        assert_eq!(machine.validate("v1.2\nrest"), (true, 4));
+        assert!(!machine.validate("v1.2 rest").0);
+    }
+
+    #[test]
+    fn test_shorthands_in_classes() {
+        let word = StateMachine::from_regex(r"[\w-]+", "word").unwrap();
+        for accepted in ["snake_case", "kebab-case", "Rust2024"] {
+            assert!(word.check(accepted), "{}", accepted);
+        }
+        assert!(!word.check("two words"));
+
+        let blank = StateMachine::from_regex(r"a[\s,]b", "blank").unwrap();
+        for accepted in ["a b", "a\tb", "a\nb", "a,b"] {
+            assert!(blank.check(accepted), "{:?}", accepted);
+        }
+        assert!(!blank.check("a_b"));
+    }
+
+    #[test]
+    fn test_nested_repetitions_are_budgeted() {
+        let machine = StateMachine::from_regex("((ab){10}){10}", "nested").unwrap();
+        assert!(machine.check("ab".repeat(100)));
+        assert!(!machine.check("ab".repeat(99)));
+        let error = StateMachine::from_regex(&"(a|b)".repeat(20_000), "long")
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "offset 0: pattern expands to more than 100000 transitions"
+        );
+    }
+
+    #[test]
+    fn test_anchored_regex_determinizes() {
+        let machine = StateMachine::from_regex(r"^[0-9]+\.[0-9]+$", "decimal").unwrap();
+        let dfa = machine.determinize().unwrap();
+        for input in ["1.2", "10.25", "1.2\nrest", "1.2 rest", "1.", ".2", "1.2.3"] {
+            assert_eq!(dfa.validate(input), machine.validate(input), "{:?}", input);
+        }
+        let (minimized, _) = dfa.minimize();
+        assert!(dfa.equivalent(&minimized));
+        assert!(minimized.check("3.14"));
+        assert!(!minimized.check("3.14 "));
+
+        let machine = StateMachine::from_regex("(a|ab)$", "anchored").unwrap();
+        let dfa = machine.determinize().unwrap();
+        for input in ["a", "ab", "a\n", "ab\nb", "aab", "abb", "b"] {
+            assert_eq!(dfa.validate(input), machine.validate(input), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_unsupported_syntax() {
+        let error = |pattern: &str| StateMachine::from_regex(pattern, "bad").err().unwrap();
+        let cases = [
+            ("a(b", 1, "unmatched ("),
+            ("ab)", 2, "unmatched )"),
+            ("*a", 0, "nothing to repeat"),
+            ("a**", 2, "nothing to repeat"),
+            ("a*?", 1, "lazy repetitions are not supported"),
+            (r"(a)\1", 3, "backreferences are not supported"),
+            ("(?=a)", 0, "lookarounds and group flags are not supported"),
+            ("a{3,1}", 1, "repetition bounds are out of order"),
+            ("a{,2}", 2, "expected a repetition count"),
+            ("a$b", 1, "$ may only end the pattern"),
+            ("a^", 1, "^ may only start the pattern"),
+            ("[a-", 0, "unmatched ["),
+            (r"\q", 0, "unknown escape sequence"),
+            (
+                r"[\W]",
+                1,
+                "negated shorthands are not supported in classes",
+            ),
+            (
+                "((a{100}){100}){100}",
+                15,
+                "repetition expands to more than 100000 transitions",
+            ),
+            (
+                "(a{1000}){1000}",
+                9,
+                "repetition expands to more than 100000 transitions",
+            ),
+        ];
+        for (pattern, offset, message) in cases {
+            assert_eq!(
+                error(pattern),
+                RegexError {
+                    offset,
+                    message: message.to_string()
+                },
+                "{}",
+                pattern
+            );
+        }
+        assert_eq!(error("a{2").to_string(), "offset 3: expected }".to_string());
+    }
+}
@@ -0,0 +1,273 @@
+use core::fmt;
+use std::{collections::HashMap, sync::Arc};
+
+use super::{
+    context::RunContext,
+    error::{GrammarError, ValidationError},
+    indentation::Indentation,
+    input::Input,
+    state_machine::StateMachine,
+    transition::{Lookahead, TransitionKind},
+};
+
+/// Named machines referring to each other through `RuleTransition`s. Rules are
+/// resolved when a run reaches them, so a rule can refer to itself and the
+/// indentation of the run is handed down to the rule.
+#[derive(Clone, Default)]
+pub struct Grammar {
+    rules: Arc<HashMap<String, Arc<StateMachine>>>,
+}
+
+impl Grammar {
+    pub fn rule(&self, name: &str) -> Option<&Arc<StateMachine>> {
+        self.rules.get(name)
+    }
+
+    /// Names of the rules, sorted.
+    pub fn rule_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.rules.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Validates `buffer` from its start against the rule named `rule`. Fails
+    /// with `GrammarError::MissingRule` if the grammar has no such rule, as do
+    /// the other methods taking a rule name.
+    pub fn validate(
+        &self,
+        rule: &str,
+        buffer: impl AsRef<str>,
+    ) -> Result<(bool, usize), GrammarError> {
+        self.validate_with(rule, buffer, &mut RunContext::new())
+    }
+
+    /// Like `validate`, within `context`.
+    pub fn validate_with(
+        &self,
+        rule: &str,
+        buffer: impl AsRef<str>,
+        context: &mut RunContext,
+    ) -> Result<(bool, usize), GrammarError> {
+        let machine = self.find_rule(rule)?;
+        context.with_grammar(self).start_run();
+        Ok(machine.validate_in_context(
+            &Input::new(buffer.as_ref()),
+            0,
+            &Indentation::new(),
+            context,
+        ))
+    }
+
+    pub fn check(&self, rule: &str, buffer: impl AsRef<str>) -> Result<bool, GrammarError> {
+        let buffer = buffer.as_ref();
+        let (validated, offset) = self.validate(rule, buffer)?;
+        Ok(validated && offset == buffer.len())
+    }
+
+    /// Like `check`, explaining why `buffer` is rejected.
+    pub fn check_detailed(
+        &self,
+        rule: &str,
+        buffer: impl AsRef<str>,
+    ) -> Result<Result<(), ValidationError>, GrammarError> {
+        let buffer = buffer.as_ref();
+        if self.check(rule, buffer)? {
+            return Ok(Ok(()));
+        }
+        let machine = self.find_rule(rule)?;
+        Ok(Err(machine.explain_in(
+            buffer,
+            0,
+            &Indentation::new(),
+            Some(self),
+        )))
+    }
+
+    pub(super) fn find_rule(&self, rule: &str) -> Result<&StateMachine, GrammarError> {
+        self.rule(rule)
+            .map(Arc::as_ref)
+            .ok_or_else(|| GrammarError::MissingRule {
+                rule: rule.to_string(),
+            })
+    }
+}
+
+impl fmt::Debug for Grammar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Grammar")
+            .field("rules", &self.rule_names())
+            .finish()
+    }
+}
+
+/// Collects the rules of a `Grammar`.
+#[derive(Default)]
+pub struct GrammarBuilder {
+    rules: Vec<(String, Arc<StateMachine>)>,
+}
+
+impl GrammarBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a rule, replacing any rule with the same name.
+    pub fn add_rule(&mut self, name: &str, machine: StateMachine) -> &mut Self {
+        self.rules.retain(|(rule, _)| rule != name);
+        self.rules.push((name.to_string(), Arc::new(machine)));
+        self
+    }
+
+    /// Builds the grammar, checking that every rule transition names a rule.
+    pub fn build(&self) -> Result<Grammar, GrammarError> {
+        let rules: HashMap<String, Arc<StateMachine>> = self.rules.iter().cloned().collect();
+        for (_, machine) in &self.rules {
+            check_references(machine, &rules)?;
+        }
+        Ok(Grammar {
+            rules: Arc::new(rules),
+        })
+    }
+}
+
+fn check_references(
+    machine: &StateMachine,
+    rules: &HashMap<String, Arc<StateMachine>>,
+) -> Result<(), GrammarError> {
+    for transition in machine.transitions() {
+        match transition.kind() {
+            TransitionKind::Rule(rule) if !rules.contains_key(rule) => {
+                return Err(GrammarError::UnknownRule {
+                    rule: rule.to_string(),
+                    machine: machine.name().to_string(),
+                });
+            }
+            TransitionKind::Group(inner) => check_references(inner, rules)?,
+            TransitionKind::Lookahead {
+                condition: Lookahead::Machine(inner),
+                ..
+            } => check_references(inner, rules)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::grammar::{
+        context::RunContext,
+        error::{GrammarError, RunError, StreamError},
+        state::create_state,
+        state_machine::StateMachineBuilder,
+        transition::{CharTransition, IndentationOperation, RuleTransition},
+    };
+
+    use super::{Grammar, GrammarBuilder};
+
+    #[test]
+    fn test_grammar_resolves_self_references() {
+        // parens -> '(' parens? ')'
+        let start = Arc::new(create_state(false, "start"));
+        let open = Arc::new(create_state(false, "open"));
+        let inner = Arc::new(create_state(false, "inner"));
+        let close = Arc::new(create_state(true, "close"));
+        let parens = StateMachineBuilder::new(start.clone(), " ", 0)
+            .name("parens")
+            .add_transitions(vec![
+                Arc::new(CharTransition::new(
+                    start,
+                    open.clone(),
+                    "(".to_string(),
+                    IndentationOperation::BYPASS,
+                )),
+                Arc::new(RuleTransition::new(
+                    open.clone(),
+                    inner.clone(),
+                    "parens",
+                    IndentationOperation::BYPASS,
+                )),
+                Arc::new(CharTransition::new(
+                    open,
+                    close.clone(),
+                    ")".to_string(),
+                    IndentationOperation::BYPASS,
+                )),
+                Arc::new(CharTransition::new(
+                    inner,
+                    close,
+                    ")".to_string(),
+                    IndentationOperation::BYPASS,
+                )),
+            ])
+            .build();
+        let grammar = GrammarBuilder::new()
+            .add_rule("parens", parens)
+            .build()
+            .unwrap();
+        assert!(grammar.check("parens", "((()))").unwrap());
+        assert!(!grammar.check("parens", "(()").unwrap());
+        let error = grammar
+            .check_detailed("parens", "((();")
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.offset, 4);
+        assert_eq!(error.groups, vec!["parens"]);
+    }
+
+    #[test]
+    fn test_grammar_rejects_unknown_rules() {
+        let start = Arc::new(create_state(false, "start"));
+        let end = Arc::new(create_state(true, "end"));
+        let machine = StateMachineBuilder::new(start.clone(), " ", 0)
+            .name("list")
+            .add_transition(Arc::new(RuleTransition::new(
+                start,
+                end,
+                "item",
+                IndentationOperation::BYPASS,
+            )))
+            .build();
+        let error = GrammarBuilder::new()
+            .add_rule("list", machine)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            error,
+            GrammarError::UnknownRule {
+                rule: "item".to_string(),
+                machine: "list".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_grammar_reports_missing_rules() {
+        let grammar = Grammar::parse("list = \"a\"+ ;").unwrap();
+        let missing = GrammarError::MissingRule {
+            rule: "lsit".to_string(),
+        };
+        assert_eq!(grammar.validate("lsit", "aa"), Err(missing.clone()));
+        assert_eq!(grammar.check("lsit", "aa"), Err(missing.clone()));
+        assert_eq!(grammar.check_detailed("lsit", "aa"), Err(missing.clone()));
+        assert_eq!(grammar.captures("lsit", "aa"), Err(missing.clone()));
+        assert_eq!(
+            grammar.validate_reader("lsit", "aa".as_bytes()),
+            Err(StreamError::Grammar(missing))
+        );
+        assert_eq!(grammar.check("list", "aa"), Ok(true));
+    }
+
+    #[test]
+    fn test_left_recursive_rule_is_aborted() {
+        let grammar = Grammar::parse("list = list \"a\" | \"a\" ;").unwrap();
+        let mut context = RunContext::new();
+        assert!(!grammar.validate_with("list", "aa", &mut context).unwrap().0);
+        assert!(matches!(
+            context.error(),
+            Some(RunError::NoProgress { offset: 0, .. })
+        ));
+    }
+}
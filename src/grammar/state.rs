@@ -25,7 +25,7 @@ impl State {
 
 impl fmt::Debug for State {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if (*self).is_final {
+        if self.is_final {
             return write!(f, "|{}|", self.label);
         }
         write!(f, "{}", self.label)
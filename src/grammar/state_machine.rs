@@ -1,175 +1,1476 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+    num::NonZeroUsize,
+    ops::Range,
+    panic,
+    sync::Arc,
+    thread,
+};
 
+use serde::{Deserialize, Serialize};
 use tracing::debug;
 
-use crate::grammar::transition::CharTransition;
-
 use super::{
+    context::RunContext,
+    dfa::Dfa,
+    error::{DeterminizeError, GrammarError, RunError, ValidationError},
+    indentation::Indentation,
+    input::Input,
+    registry::Grammar,
     state::State,
-    transition::{self, IndentationOperation, Transition},
+    trace::{Trace, TraceStep},
+    transition::{Transition, TransitionKind},
 };
 
+/// Called with the inner machine, offset and indentation of a group that failed to match.
+pub(super) type GroupFailureHandler<'a> = dyn FnMut(&StateMachine, usize, &Indentation) + 'a;
+
+/// How a machine picks among the transitions leaving a state.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExecutionMode {
+    /// Takes the first transition that matches and never revisits that choice.
+    #[default]
+    Deterministic,
+    /// Explores every applicable transition and keeps the longest accepting run.
+    Backtracking,
+}
+
+/// Which transition a deterministic machine takes when several leave the
+/// current state and match. Transitions are tried by decreasing priority, then
+/// in the order they were added.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MatchStrategy {
+    /// Takes the first transition that matches.
+    #[default]
+    FirstMatch,
+    /// Takes the transition consuming the most input, the first one on ties.
+    LongestMatch,
+    /// Aborts the run when several transitions of the highest matching priority
+    /// match.
+    ErrorOnAmbiguity,
+}
+
+/// A transition taken by a deterministic run.
+pub(super) struct Move {
+    /// Index of the transition in `StateMachine::transitions`.
+    pub(super) transition: usize,
+    pub(super) target: usize,
+    /// Offset and indentation once the transition was taken.
+    pub(super) offset: usize,
+    pub(super) indentation: Indentation,
+}
+
+struct Exploration {
+    /// Offsets of every accepting configuration, longest first.
+    accepting: Vec<usize>,
+    /// The furthest offset any configuration reached.
+    furthest: usize,
+    /// Ids of the states of the configurations that reached `furthest`, in
+    /// discovery order.
+    stuck: Vec<usize>,
+}
+
+/// A frozen machine. States get dense ids when the machine is built, the start
+/// state being `0`, and the transitions leaving each state are stored
+/// contiguously so a run never has to look them up.
+///
+/// Machines are `Send + Sync`: build one and share it across threads.
+#[derive(Clone)]
 pub struct StateMachine {
-    pub states: Vec<Rc<State>>,
-    pub transitions: Vec<Rc<dyn Transition>>,
-    pub start: Rc<State>,
-    pub current_indentation: i32,
-    pub indentation_character: String,
+    name: String,
+    mode: ExecutionMode,
+    strategy: MatchStrategy,
+    /// States by id.
+    states: Vec<Arc<State>>,
+    /// Transitions grouped by source state, in insertion order within a group.
+    transitions: Vec<Arc<dyn Transition>>,
+    /// `outgoing[id]` is the range of `transitions` leaving state `id`.
+    outgoing: Vec<Range<usize>>,
+    /// Id of the state each transition leads to.
+    targets: Vec<usize>,
+    /// Priority of each transition.
+    priorities: Vec<i32>,
+    current_indentation: i32,
+    indentation_character: String,
 }
 
 impl StateMachine {
-    pub fn new(start: Rc<State>, indentation_character: String, current_indentation: i32) -> Self {
-        Self {
-            states: Vec::new(),
-            transitions: Vec::new(),
-            start,
-            current_indentation,
-            indentation_character,
+    pub fn new(start: Arc<State>, indentation_character: String, current_indentation: i32) -> Self {
+        StateMachineBuilder::new(start, &indentation_character, current_indentation).build()
+    }
+
+    /// Assigns ids to the states of `builder`, then to the states only mentioned
+    /// by its transitions, and groups the transitions by source state.
+    fn freeze(builder: &StateMachineBuilder) -> Self {
+        let states = builder.states.clone();
+        let transitions = &builder.transitions;
+        let priorities = &builder.priorities;
+        let mut ids: HashMap<Arc<State>, usize> = HashMap::new();
+        let mut unique_states: Vec<Arc<State>> = Vec::new();
+        let mut id_of = |state: Arc<State>| -> usize {
+            *ids.entry(state.clone()).or_insert_with(|| {
+                unique_states.push(state);
+                unique_states.len() - 1
+            })
+        };
+
+        for state in states {
+            id_of(state);
+        }
+        let ends: Vec<(usize, usize)> = transitions
+            .iter()
+            .map(|transition| (id_of(transition.from()), id_of(transition.target())))
+            .collect();
+
+        // stable, so transitions of a state with the same priority keep the order
+        // they were added in
+        let mut order: Vec<usize> = (0..transitions.len()).collect();
+        order.sort_by_key(|index| (ends[*index].0, Reverse(priorities[*index])));
+
+        let mut outgoing = vec![0..0; unique_states.len()];
+        let mut sorted = Vec::with_capacity(transitions.len());
+        let mut targets = Vec::with_capacity(transitions.len());
+        let mut sorted_priorities = Vec::with_capacity(transitions.len());
+        for (position, index) in order.into_iter().enumerate() {
+            let (source, target) = ends[index];
+            if outgoing[source].is_empty() {
+                outgoing[source] = position..position;
+            }
+            outgoing[source].end = position + 1;
+            sorted.push(transitions[index].clone());
+            targets.push(target);
+            sorted_priorities.push(priorities[index]);
+        }
+
+        StateMachine {
+            name: builder.name.clone(),
+            mode: builder.mode,
+            strategy: builder.strategy,
+            states: unique_states,
+            transitions: sorted,
+            outgoing,
+            targets,
+            priorities: sorted_priorities,
+            current_indentation: builder.current_indentation,
+            indentation_character: builder.indentation_character.clone(),
         }
     }
 
-    pub fn validate(&self, buffer: String) -> (bool, usize) {
-        self.validate_from(buffer, 0, 0)
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn mode(&self) -> ExecutionMode {
+        self.mode
+    }
+
+    pub fn strategy(&self) -> MatchStrategy {
+        self.strategy
     }
 
-    pub fn check(&self, buffer: String) -> bool {
-        let (validated, offset) = self.validate(buffer.clone());
+    pub fn start(&self) -> &Arc<State> {
+        &self.states[0]
+    }
+
+    /// States by id, the start state first.
+    pub fn states(&self) -> &[Arc<State>] {
+        &self.states
+    }
+
+    /// Every transition, grouped by source state.
+    pub fn transitions(&self) -> &[Arc<dyn Transition>] {
+        &self.transitions
+    }
+
+    /// The transitions leaving the state with id `state`, in the order they are tried.
+    pub fn outgoing(&self, state: usize) -> &[Arc<dyn Transition>] {
+        &self.transitions[self.outgoing[state].clone()]
+    }
+
+    /// Ids of the states reached by the transitions leaving `state`, in the same
+    /// order as `outgoing`.
+    pub fn targets(&self, state: usize) -> &[usize] {
+        &self.targets[self.outgoing[state].clone()]
+    }
+
+    /// Priorities of the transitions leaving `state`, in the same order as `outgoing`.
+    pub fn priorities(&self, state: usize) -> &[i32] {
+        &self.priorities[self.outgoing[state].clone()]
+    }
+
+    pub fn current_indentation(&self) -> i32 {
+        self.current_indentation
+    }
+
+    pub fn indentation_character(&self) -> &str {
+        &self.indentation_character
+    }
+
+    /// Validates `buffer` from its start. The returned offset is a byte offset.
+    pub fn validate(&self, buffer: impl AsRef<str>) -> (bool, usize) {
+        self.validate_from(buffer, 0, &Indentation::new())
+    }
+
+    pub fn check(&self, buffer: impl AsRef<str>) -> bool {
+        let buffer = buffer.as_ref();
+        let (validated, offset) = self.validate(buffer);
         validated && offset == buffer.len()
     }
 
-    pub fn validate_from(&self, buffer: String, from: usize, indentation: i32) -> (bool, usize) {
-        let mut current_state = self.start.clone();
+    pub fn validate_detailed(&self, buffer: impl AsRef<str>) -> Result<usize, ValidationError> {
+        self.validate_detailed_from(buffer, 0, &Indentation::new())
+    }
+
+    pub fn check_detailed(&self, buffer: impl AsRef<str>) -> Result<(), ValidationError> {
+        let buffer = buffer.as_ref();
+        let (validated, offset) = self.validate(buffer);
+        if validated && offset == buffer.len() {
+            return Ok(());
+        }
+        Err(self.explain(buffer, 0, &Indentation::new()))
+    }
+
+    pub fn validate_detailed_from(
+        &self,
+        buffer: impl AsRef<str>,
+        from: usize,
+        indentation: &Indentation,
+    ) -> Result<usize, ValidationError> {
+        let buffer = buffer.as_ref();
+        let (validated, offset) = self.validate_from(buffer, from, indentation);
+        if validated {
+            return Ok(offset);
+        }
+        Err(self.explain(buffer, from, indentation))
+    }
+
+    /// Validates `buffer` starting at byte offset `from`.
+    pub fn validate_from(
+        &self,
+        buffer: impl AsRef<str>,
+        from: usize,
+        indentation: &Indentation,
+    ) -> (bool, usize) {
+        self.validate_input(&Input::new(buffer.as_ref()), from, indentation)
+    }
+
+    pub fn validate_input(
+        &self,
+        input: &Input,
+        from: usize,
+        indentation: &Indentation,
+    ) -> (bool, usize) {
+        self.validate_in_context(input, from, indentation, &mut RunContext::new())
+    }
+
+    /// Validates `input` as part of the run `context` belongs to.
+    pub fn validate_in_context(
+        &self,
+        input: &Input,
+        from: usize,
+        indentation: &Indentation,
+        context: &mut RunContext,
+    ) -> (bool, usize) {
+        let (validated, offset) = match self.mode {
+            ExecutionMode::Deterministic => {
+                let (current_state, offset) =
+                    self.run(input, from, indentation, context, &mut |_, _, _| {});
+                (self.states[current_state].is_final(), offset)
+            }
+            ExecutionMode::Backtracking => {
+                let exploration =
+                    self.explore(input, from, indentation, context, &mut |_, _, _| {});
+                match exploration.accepting.first() {
+                    Some(longest) => (true, *longest),
+                    None => (false, exploration.furthest),
+                }
+            }
+        };
+
+        if offset < 1 || context.is_aborted() {
+            return (false, offset);
+        }
+        (validated, offset)
+    }
+
+    /// Every offset at which a run starting at `from` can be accepted, longest first.
+    /// A deterministic machine has at most one.
+    pub fn accepting_ends(
+        &self,
+        buffer: impl AsRef<str>,
+        from: usize,
+        indentation: &Indentation,
+    ) -> Vec<usize> {
+        self.accepting_ends_input(&Input::new(buffer.as_ref()), from, indentation)
+    }
+
+    pub fn accepting_ends_input(
+        &self,
+        input: &Input,
+        from: usize,
+        indentation: &Indentation,
+    ) -> Vec<usize> {
+        self.accepting_ends_in_context(input, from, indentation, &mut RunContext::new())
+    }
+
+    pub fn accepting_ends_in_context(
+        &self,
+        input: &Input,
+        from: usize,
+        indentation: &Indentation,
+        context: &mut RunContext,
+    ) -> Vec<usize> {
+        match self.mode {
+            ExecutionMode::Deterministic => {
+                match self.validate_in_context(input, from, indentation, context) {
+                    (true, offset) => vec![offset],
+                    (false, _) => Vec::new(),
+                }
+            }
+            ExecutionMode::Backtracking => {
+                let exploration =
+                    self.explore(input, from, indentation, context, &mut |_, _, _| {});
+                if context.is_aborted() {
+                    return Vec::new();
+                }
+                exploration.accepting
+            }
+        }
+    }
+
+    /// Validates `buffer` from its start within `context`, which decides whether
+    /// steps are traced and group runs memoized.
+    pub fn validate_with(
+        &self,
+        buffer: impl AsRef<str>,
+        context: &mut RunContext,
+    ) -> (bool, usize) {
+        context.start_run();
+        self.validate_in_context(
+            &Input::new(buffer.as_ref()),
+            0,
+            &Indentation::new(),
+            context,
+        )
+    }
+
+    /// Like `validate_with`, failing when the run was aborted because it stopped
+    /// making progress or exceeded the step limit of `context`.
+    pub fn try_validate_with(
+        &self,
+        buffer: impl AsRef<str>,
+        context: &mut RunContext,
+    ) -> Result<(bool, usize), RunError> {
+        let result = self.validate_with(buffer, context);
+        match context.take_error() {
+            Some(error) => Err(error),
+            None => Ok(result),
+        }
+    }
+
+    /// Validates `buffer` from its start and records every step taken, nested
+    /// group machines included.
+    pub fn trace(&self, buffer: impl AsRef<str>) -> Trace {
+        self.trace_from(buffer, 0, &Indentation::new())
+    }
+
+    pub fn trace_from(
+        &self,
+        buffer: impl AsRef<str>,
+        from: usize,
+        indentation: &Indentation,
+    ) -> Trace {
+        let mut context = RunContext::new();
+        context.record_trace();
+        let (accepted, offset) = self.validate_in_context(
+            &Input::new(buffer.as_ref()),
+            from,
+            indentation,
+            &mut context,
+        );
+        Trace {
+            accepted,
+            offset,
+            steps: context.take_trace(),
+        }
+    }
+
+    /// Validates every buffer from its start, spreading the buffers over the
+    /// available cores. Results are in the order of `buffers`.
+    pub fn validate_all<S: AsRef<str> + Sync>(&self, buffers: &[S]) -> Vec<(bool, usize)> {
+        parallel_map(buffers, |buffer| self.validate(buffer))
+    }
+
+    /// Runs `check_detailed` on every buffer in parallel. Results are in the
+    /// order of `buffers`.
+    pub fn check_detailed_all<S: AsRef<str> + Sync>(
+        &self,
+        buffers: &[S],
+    ) -> Vec<Result<(), ValidationError>> {
+        parallel_map(buffers, |buffer| self.check_detailed(buffer))
+    }
+
+    /// Compiles this machine into an equivalent `Dfa` using subset construction.
+    /// Only machines made of char and epsilon transitions can be compiled, and
+    /// deterministic ones only when their first match is always the longest.
+    pub fn determinize(&self) -> Result<Dfa, DeterminizeError> {
+        Dfa::from_machine(self)
+    }
+
+    /// Replays a run and describes the point where it stopped. Group transitions
+    /// that failed along the way are explained in turn, and the furthest failure wins.
+    pub fn explain(&self, buffer: &str, from: usize, indentation: &Indentation) -> ValidationError {
+        self.explain_in(buffer, from, indentation, None)
+    }
+
+    /// Like `explain`, resolving rule transitions against `grammar`.
+    pub fn explain_in(
+        &self,
+        buffer: &str,
+        from: usize,
+        indentation: &Indentation,
+        grammar: Option<&Grammar>,
+    ) -> ValidationError {
+        let input = Input::new(buffer);
+        let mut furthest: Option<ValidationError> = None;
+        let mut on_group_failure =
+            |machine: &StateMachine, offset: usize, indentation: &Indentation| {
+                let mut nested = machine.explain_in(buffer, offset, indentation, grammar);
+                if furthest
+                    .as_ref()
+                    .is_none_or(|error| nested.offset > error.offset)
+                {
+                    nested.groups.insert(0, machine.name().to_string());
+                    furthest = Some(nested);
+                }
+            };
+
+        let mut context = RunContext::new();
+        if let Some(grammar) = grammar {
+            context.with_grammar(grammar);
+        }
+        let (stuck_states, offset) = match self.mode {
+            ExecutionMode::Deterministic => {
+                let (current_state, offset) = self.run(
+                    &input,
+                    from,
+                    indentation,
+                    &mut context,
+                    &mut on_group_failure,
+                );
+                (vec![current_state], offset)
+            }
+            ExecutionMode::Backtracking => {
+                let exploration = self.explore(
+                    &input,
+                    from,
+                    indentation,
+                    &mut context,
+                    &mut on_group_failure,
+                );
+                (exploration.stuck, exploration.furthest)
+            }
+        };
+
+        let mut expected: Vec<String> = Vec::new();
+        for state in &stuck_states {
+            for transition in self.outgoing(*state) {
+                let label = transition.kind().to_string();
+                if !expected.contains(&label) {
+                    expected.push(label);
+                }
+            }
+        }
+        let stuck = ValidationError::new(
+            buffer,
+            offset,
+            self.states[stuck_states[0]].label.clone(),
+            Vec::new(),
+            expected,
+        );
+
+        match furthest {
+            Some(nested) if nested.offset > stuck.offset => nested,
+            _ => stuck,
+        }
+    }
+
+    /// Follows the first matching transition until none applies, returning the
+    /// id of the state and the offset the machine stopped at. Coming back to a
+    /// state without consuming anything aborts the run.
+    fn run(
+        &self,
+        input: &Input,
+        from: usize,
+        indentation: &Indentation,
+        context: &mut RunContext,
+        on_group_failure: &mut GroupFailureHandler,
+    ) -> (usize, usize) {
+        let mut current_state = 0;
         let mut offset = from;
-        let mut current_indentation = indentation;
-        let mut transition_map: HashMap<Rc<State>, Vec<&Rc<(dyn Transition)>>> = HashMap::new();
+        let mut current_indentation = indentation.clone();
+        // configurations seen since the run last consumed input
+        let mut stalled = HashSet::from([(current_state, current_indentation.clone())]);
 
-        debug!("starting validating from {:?}", current_state);
+        debug!("starting validating from {:?}", self.start());
 
-        //init data structure so it gets dropped by the borrow checker when no longer needed
-        for transition in &self.transitions {
-            debug!("registering {:?}", transition.from());
-            let from = transition.from();
-            transition_map
-                .entry(from)
-                .or_insert_with(Vec::new)
-                .push(transition);
+        while !input.is_end(offset) {
+            debug!("needs to match {}, {:?}", offset, input.peek(offset));
+            match self.step(
+                current_state,
+                input,
+                offset,
+                &current_indentation,
+                context,
+                on_group_failure,
+            ) {
+                Some(next) => {
+                    if next.offset > offset {
+                        stalled.clear();
+                    }
+                    if !stalled.insert((next.target, next.indentation.clone())) {
+                        context.abort(RunError::NoProgress {
+                            machine: self.name.clone(),
+                            state: self.states[next.target].label.clone(),
+                            offset,
+                        });
+                        break;
+                    }
+                    current_state = next.target;
+                    offset = next.offset;
+                    current_indentation = next.indentation;
+                }
+                None => break,
+            }
         }
 
-        while offset < buffer.len() {
-            if !transition_map.contains_key(&current_state) {
-                if offset < 1 {
-                    return (false, offset);
+        (current_state, offset)
+    }
+
+    /// Picks the transition leaving `state` to take at `offset`, following the
+    /// match strategy of the machine.
+    pub(super) fn step(
+        &self,
+        state: usize,
+        input: &Input,
+        offset: usize,
+        indentation: &Indentation,
+        context: &mut RunContext,
+        on_group_failure: &mut GroupFailureHandler,
+    ) -> Option<Move> {
+        // index and consumed bytes of the transition to take
+        let mut chosen: Option<(usize, usize)> = None;
+        let mut matching = Vec::new();
+        for index in self.outgoing[state].clone() {
+            if context.is_aborted() {
+                return None;
+            }
+            // lower priorities cannot make the step ambiguous once a
+            // transition matched
+            if self.strategy == MatchStrategy::ErrorOnAmbiguity
+                && chosen.is_some_and(|(best, _)| self.priorities[index] < self.priorities[best])
+            {
+                break;
+            }
+            let transition = &self.transitions[index];
+            match transition.to(
+                input,
+                offset,
+                indentation,
+                &self.indentation_character,
+                context,
+            ) {
+                Ok(consumed) => {
+                    matching.push(index);
+                    if chosen.is_none_or(|(_, longest)| {
+                        self.strategy == MatchStrategy::LongestMatch && consumed > longest
+                    }) {
+                        chosen = Some((index, consumed));
+                    }
+                    if self.strategy == MatchStrategy::FirstMatch {
+                        break;
+                    }
                 }
-                return (current_state.is_final(), offset);
+                Err(_) => notify_group_failure(
+                    transition.as_ref(),
+                    context,
+                    offset,
+                    indentation,
+                    on_group_failure,
+                ),
             }
+        }
+
+        let (index, consumed) = chosen?;
+        if matching.len() > 1 && self.strategy == MatchStrategy::ErrorOnAmbiguity {
+            context.abort(RunError::Ambiguous {
+                machine: self.name.clone(),
+                state: self.states[state].label.clone(),
+                offset,
+                transitions: matching
+                    .into_iter()
+                    .map(|index| self.transitions[index].kind().to_string())
+                    .collect(),
+            });
+            return None;
+        }
+        let transition = &self.transitions[index];
+        let target = self.targets[index];
+        let next_indentation = transition.next_indentation(indentation, input, offset);
+        if context.is_tracing() {
+            let step = self.trace_step(
+                context,
+                state,
+                transition.as_ref(),
+                target,
+                (offset, offset + consumed),
+                (indentation, &next_indentation),
+            );
+            context.record(step);
+        }
+        if !context.count_step(offset) {
+            return None;
+        }
+        Some(Move {
+            transition: index,
+            target,
+            offset: offset + consumed,
+            indentation: next_indentation,
+        })
+    }
+
+    /// Explores every applicable transition from every reachable configuration of
+    /// state, offset and indentation. Each configuration is visited once, so the
+    /// work is bounded even when the grammar is ambiguous or has epsilon cycles.
+    fn explore(
+        &self,
+        input: &Input,
+        from: usize,
+        indentation: &Indentation,
+        context: &mut RunContext,
+        on_group_failure: &mut GroupFailureHandler,
+    ) -> Exploration {
+        let mut visited = HashSet::new();
+        let mut pending = vec![(0, from, indentation.clone())];
+        let mut accepting = Vec::new();
+        let mut furthest = from;
+        let mut stuck = Vec::new();
 
-            let transitions = transition_map.get(&current_state).unwrap();
-            let mut matched = false;
+        debug!("starting exploring from {:?}", self.start());
 
-            debug!("needs to match {}, {}", offset, buffer);
-            for transition in transitions {
-                match transition.to(
-                    buffer.clone(),
+        while let Some((current_state, offset, current_indentation)) = pending.pop() {
+            if context.is_aborted() {
+                break;
+            }
+            if !visited.insert((current_state, offset, current_indentation.clone())) {
+                continue;
+            }
+            if self.states[current_state].is_final() && offset >= 1 {
+                accepting.push(offset);
+            }
+            if offset > furthest {
+                furthest = offset;
+                stuck.clear();
+            }
+            if offset == furthest && !stuck.contains(&current_state) {
+                stuck.push(current_state);
+            }
+            // transitions are tried even at the end of the input, epsilon ones
+            // can still lead to a final state
+            let mut successors = Vec::new();
+            for (transition, target) in self
+                .outgoing(current_state)
+                .iter()
+                .zip(self.targets(current_state))
+            {
+                let ends = transition.to_all(
+                    input,
                     offset,
-                    current_indentation,
-                    self.indentation_character.clone(),
-                ) {
-                    Ok(next_state) => {
-                        let (new_current_state, offset_inc) = next_state;
-                        current_state = new_current_state;
-                        offset += offset_inc;
-                        matched = true;
-
-                        match transition.indentation_operation() {
-                            IndentationOperation::BYPASS => {
-                                current_indentation += 0;
-                            }
-                            IndentationOperation::INCREMENT => {
-                                current_indentation += 1;
-                            }
-                            IndentationOperation::DESINCREMENT => {
-                                current_indentation -= 1;
-                            }
-                            IndentationOperation::CONSERVE => {
-                                current_indentation += 0;
-                            }
-                            IndentationOperation::RESET => {
-                                current_indentation = 0;
-                            }
-                        };
+                    &current_indentation,
+                    &self.indentation_character,
+                    context,
+                );
+                if ends.is_empty() {
+                    notify_group_failure(
+                        transition.as_ref(),
+                        context,
+                        offset,
+                        &current_indentation,
+                        on_group_failure,
+                    );
+                }
+                let next_indentation =
+                    transition.next_indentation(&current_indentation, input, offset);
+                for consumed in ends {
+                    if context.is_tracing() {
+                        let step = self.trace_step(
+                            context,
+                            current_state,
+                            transition.as_ref(),
+                            *target,
+                            (offset, offset + consumed),
+                            (&current_indentation, &next_indentation),
+                        );
+                        context.record(step);
+                    }
+                    if !context.count_step(offset) {
                         break;
                     }
-                    Err(ErrorTransition) => {}
-                };
+                    successors.push((*target, offset + consumed, next_indentation.clone()));
+                }
             }
+            // the stack is LIFO, so push in reverse to try the first transition first
+            pending.extend(successors.into_iter().rev());
+        }
+
+        accepting.sort_unstable_by(|a, b| b.cmp(a));
+        accepting.dedup();
+        Exploration {
+            accepting,
+            furthest,
+            stuck,
+        }
+    }
+
+    fn trace_step(
+        &self,
+        context: &RunContext,
+        from: usize,
+        transition: &dyn Transition,
+        to: usize,
+        (offset_before, offset_after): (usize, usize),
+        (indentation_before, indentation_after): (&Indentation, &Indentation),
+    ) -> TraceStep {
+        TraceStep {
+            machine: self.name.clone(),
+            from: self.states[from].label.clone(),
+            transition: transition.kind().to_string(),
+            to: self.states[to].label.clone(),
+            offset_before,
+            offset_after,
+            indentation_before: indentation_before.clone(),
+            indentation_after: indentation_after.clone(),
+            depth: context.depth(),
+        }
+    }
+}
 
-            if !matched {
-                if offset < 1 {
-                    return (false, offset);
+/// One cycle per group of states `epsilon_edges` loop through, each starting at
+/// the first of its states in `states` order.
+fn epsilon_cycles(
+    states: &[Arc<State>],
+    epsilon_edges: &HashMap<String, Vec<String>>,
+) -> Vec<Vec<String>> {
+    let mut cycles: Vec<Vec<String>> = Vec::new();
+    for state in states {
+        let start = state.label.as_str();
+        if cycles.iter().flatten().any(|label| label == start) {
+            continue;
+        }
+        // breadth first so the shortest way back is reported
+        let mut parents: HashMap<&str, &str> = HashMap::new();
+        let mut queue = VecDeque::from([start]);
+        let mut closing = None;
+        while let Some(label) = queue.pop_front() {
+            for next in epsilon_edges.get(label).into_iter().flatten() {
+                if next == start {
+                    closing = Some(label);
+                    break;
+                }
+                if !parents.contains_key(next.as_str()) {
+                    parents.insert(next, label);
+                    queue.push_back(next);
                 }
-                return (current_state.is_final(), offset);
             }
+            if closing.is_some() {
+                break;
+            }
+        }
+        if let Some(mut label) = closing {
+            let mut cycle = vec![label.to_string()];
+            while label != start {
+                label = parents[label];
+                cycle.push(label.to_string());
+            }
+            cycle.reverse();
+            cycles.push(cycle);
         }
+    }
+    cycles
+}
 
-        if offset < 1 {
-            return (false, offset);
+/// Hands the machine of a failed group or rule transition to `on_group_failure`.
+fn notify_group_failure(
+    transition: &dyn Transition,
+    context: &RunContext,
+    offset: usize,
+    indentation: &Indentation,
+    on_group_failure: &mut GroupFailureHandler,
+) {
+    match transition.kind() {
+        TransitionKind::Group(machine) => on_group_failure(machine, offset, indentation),
+        TransitionKind::Rule(rule) => {
+            if let Some(machine) = context.rule(rule) {
+                on_group_failure(&machine, offset, indentation);
+            }
         }
-        (current_state.is_final(), offset)
+        _ => {}
     }
 }
 
+/// Applies `f` to every item on scoped threads, one contiguous chunk per core.
+fn parallel_map<S: Sync, T: Send>(items: &[S], f: impl Fn(&S) -> T + Sync) -> Vec<T> {
+    let workers = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let chunk_size = items.len().div_ceil(workers).max(1);
+    let f = &f;
+    thread::scope(|scope| {
+        let handles: Vec<_> = items
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(f).collect::<Vec<T>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|payload| panic::resume_unwind(payload))
+            })
+            .collect()
+    })
+}
+
 pub struct StateMachineBuilder {
-    transitions: Vec<Rc<dyn Transition>>,
-    states: Vec<Rc<State>>,
+    name: String,
+    mode: ExecutionMode,
+    strategy: MatchStrategy,
+    transitions: Vec<Arc<dyn Transition>>,
+    /// Priority of each transition.
+    priorities: Vec<i32>,
+    states: Vec<Arc<State>>,
     current_indentation: i32,
-    start: Rc<State>,
     indentation_character: String,
 }
 
 impl StateMachineBuilder {
-    pub fn new(start: Rc<State>, indentation_character: &str, current_indentation: i32) -> Self {
+    pub fn new(start: Arc<State>, indentation_character: &str, current_indentation: i32) -> Self {
         let indentation_character = indentation_character.to_string();
-        let state_machine = StateMachineBuilder {
-            states: vec![start.clone()],
+        StateMachineBuilder {
+            name: start.label.clone(),
+            mode: ExecutionMode::default(),
+            strategy: MatchStrategy::default(),
+            states: vec![start],
             transitions: Vec::new(),
-            start,
+            priorities: Vec::new(),
             current_indentation,
             indentation_character,
-        };
-        state_machine
+        }
+    }
+
+    /// Names the machine, used when reporting which group a failure happened in.
+    pub fn name(&mut self, name: &str) -> &mut Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn mode(&mut self, mode: ExecutionMode) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// How a deterministic machine picks among matching transitions.
+    pub fn strategy(&mut self, strategy: MatchStrategy) -> &mut Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Adds a transition with priority `0`.
+    pub fn add_transition(&mut self, transition: Arc<dyn Transition>) -> &mut Self {
+        self.add_transition_with_priority(transition, 0)
     }
 
-    pub fn add_transition(&mut self, transition: Rc<dyn Transition>) -> &mut Self {
+    /// Adds a transition tried before the transitions of lower priority leaving
+    /// the same state.
+    pub fn add_transition_with_priority(
+        &mut self,
+        transition: Arc<dyn Transition>,
+        priority: i32,
+    ) -> &mut Self {
         self.transitions.push(transition);
+        self.priorities.push(priority);
         self
     }
 
-    pub fn add_transitions(&mut self, transitions: Vec<Rc<dyn Transition>>) -> &mut Self {
+    pub fn add_transitions(&mut self, transitions: Vec<Arc<dyn Transition>>) -> &mut Self {
         for transition in transitions {
             self.add_transition(transition);
         }
         self
     }
 
-    pub fn add_state(&mut self, state: Rc<State>) -> &mut Self {
+    pub fn add_state(&mut self, state: Arc<State>) -> &mut Self {
         self.states.push(state);
         self
     }
 
-    pub fn add_states(&mut self, states: Vec<Rc<State>>) -> &mut Self {
+    pub fn add_states(&mut self, states: Vec<Arc<State>>) -> &mut Self {
         for state in states {
             self.add_state(state);
         }
         self
     }
 
+    /// Freezes the machine, indexing the transitions by source state once.
     pub fn build(&self) -> StateMachine {
-        StateMachine {
-            states: self.states.clone(),
-            current_indentation: self.current_indentation,
-            indentation_character: self.indentation_character.clone(),
-            start: self.start.clone(),
-            transitions: self.transitions.clone(),
+        StateMachine::freeze(self)
+    }
+
+    /// Like `build`, but rejects machines that are not well formed: duplicate
+    /// state labels, transitions on states never added, no final state, states
+    /// that are unreachable or cannot reach a final state, and epsilon cycles.
+    pub fn try_build(&self) -> Result<StateMachine, Vec<GrammarError>> {
+        let errors = self.check();
+        if errors.is_empty() {
+            Ok(self.build())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn check(&self) -> Vec<GrammarError> {
+        let machine = || self.name.clone();
+        let mut errors = Vec::new();
+
+        // states are equal when their labels are, so two distinct states sharing a
+        // label would be merged
+        let mut by_label: HashMap<&str, &Arc<State>> = HashMap::new();
+        let mut duplicates: Vec<&str> = Vec::new();
+        for state in &self.states {
+            match by_label.get(state.label.as_str()) {
+                Some(known) if !Arc::ptr_eq(known, state) => {
+                    if !duplicates.contains(&state.label.as_str()) {
+                        duplicates.push(&state.label);
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    by_label.insert(&state.label, state);
+                }
+            }
+        }
+        errors.extend(
+            duplicates
+                .into_iter()
+                .map(|label| GrammarError::DuplicateLabel {
+                    machine: machine(),
+                    label: label.to_string(),
+                }),
+        );
+
+        let mut unknown: Vec<String> = Vec::new();
+        let mut edges: HashMap<String, Vec<String>> = HashMap::new();
+        let mut epsilon_edges: HashMap<String, Vec<String>> = HashMap::new();
+        for transition in &self.transitions {
+            let (from, to) = (transition.from(), transition.target());
+            for state in [&from, &to] {
+                if !by_label.contains_key(state.label.as_str()) && !unknown.contains(&state.label) {
+                    unknown.push(state.label.clone());
+                }
+            }
+            edges
+                .entry(from.label.clone())
+                .or_default()
+                .push(to.label.clone());
+            // lookaheads consume nothing either
+            if matches!(
+                transition.kind(),
+                TransitionKind::Epsilon | TransitionKind::Lookahead { .. }
+            ) {
+                epsilon_edges
+                    .entry(from.label.clone())
+                    .or_default()
+                    .push(to.label.clone());
+            }
+        }
+        errors.extend(unknown.into_iter().map(|state| GrammarError::UnknownState {
+            machine: machine(),
+            state,
+        }));
+
+        errors.extend(
+            epsilon_cycles(&self.states, &epsilon_edges)
+                .into_iter()
+                .map(|states| GrammarError::EpsilonCycle {
+                    machine: machine(),
+                    states,
+                }),
+        );
+
+        if !self.states.iter().any(|state| state.is_final()) {
+            errors.push(GrammarError::NoFinalState { machine: machine() });
+            return errors;
+        }
+
+        let mut reachable: HashSet<&str> = HashSet::new();
+        let mut pending = vec![self.states[0].label.as_str()];
+        while let Some(label) = pending.pop() {
+            if reachable.insert(label) {
+                pending.extend(edges.get(label).into_iter().flatten().map(String::as_str));
+            }
+        }
+
+        // a state reaches a final state when one of its targets does, so iterate
+        // until nothing changes
+        let mut alive: HashSet<&str> = self
+            .states
+            .iter()
+            .filter(|state| state.is_final())
+            .map(|state| state.label.as_str())
+            .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (from, targets) in &edges {
+                if !alive.contains(from.as_str())
+                    && targets.iter().any(|to| alive.contains(to.as_str()))
+                {
+                    alive.insert(from);
+                    changed = true;
+                }
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for state in &self.states {
+            if !seen.insert(state.label.as_str()) {
+                continue;
+            }
+            if !reachable.contains(state.label.as_str()) {
+                errors.push(GrammarError::UnreachableState {
+                    machine: machine(),
+                    state: state.label.clone(),
+                });
+            } else if !alive.contains(state.label.as_str()) {
+                errors.push(GrammarError::DeadState {
+                    machine: machine(),
+                    state: state.label.clone(),
+                });
+            }
         }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::grammar::{
+        context::RunContext,
+        error::{GrammarError, RunError},
+        indentation::Indentation,
+        state::{create_state, State},
+        transition::{
+            create_class_transition, create_word_transition, CharTransition, EpsilonTransition,
+            GroupTransition, IndentationOperation, Transition,
+        },
+    };
+
+    use super::{ExecutionMode, MatchStrategy, StateMachine, StateMachineBuilder};
+
+    fn word_machine(mode: ExecutionMode) -> StateMachine {
+        let start = Arc::new(create_state(false, "start"));
+        let letters = Arc::new(create_state(true, "letters"));
+        StateMachineBuilder::new(start.clone(), " ", 0)
+            .name("word")
+            .mode(mode)
+            .add_state(letters.clone())
+            .add_transition(create_class_transition(
+                start,
+                letters.clone(),
+                "ab ",
+                IndentationOperation::BYPASS,
+            ))
+            .add_transition(create_class_transition(
+                letters.clone(),
+                letters,
+                "ab ",
+                IndentationOperation::BYPASS,
+            ))
+            .build()
+    }
+
+    // word followed by a space and a comment sign: the word must give its trailing
+    // space back for the run to succeed
+    fn comment_machine(mode: ExecutionMode) -> StateMachine {
+        let start = Arc::new(create_state(false, "start"));
+        let word = Arc::new(create_state(false, "word"));
+        let space = Arc::new(create_state(false, "space"));
+        let comment = Arc::new(create_state(true, "comment"));
+        StateMachineBuilder::new(start.clone(), " ", 0)
+            .mode(mode)
+            .add_states(vec![word.clone(), space.clone(), comment.clone()])
+            .add_transitions(vec![
+                Arc::new(GroupTransition::new(
+                    start,
+                    word.clone(),
+                    word_machine(mode),
+                    IndentationOperation::BYPASS,
+                )),
+                Arc::new(CharTransition::new(
+                    word,
+                    space.clone(),
+                    " ".to_string(),
+                    IndentationOperation::BYPASS,
+                )),
+                Arc::new(CharTransition::new(
+                    space,
+                    comment,
+                    "#".to_string(),
+                    IndentationOperation::BYPASS,
+                )),
+            ])
+            .build()
+    }
+
+    #[test]
+    fn test_deterministic_machine_does_not_backtrack() {
+        let machine = comment_machine(ExecutionMode::Deterministic);
+        let (result, offset) = machine.validate("ab #");
+        assert!(!result);
+        assert_eq!(offset, 3);
+    }
+
+    #[test]
+    fn test_backtracking_machine_gives_input_back() {
+        let machine = comment_machine(ExecutionMode::Backtracking);
+        let (result, offset) = machine.validate("ab #");
+        assert!(result);
+        assert_eq!(offset, 4);
+    }
+
+    #[test]
+    fn test_backtracking_machine_keeps_longest_run() {
+        let machine = word_machine(ExecutionMode::Backtracking);
+        assert_eq!(
+            machine.accepting_ends("ab a;", 0, &Indentation::new()),
+            vec![4, 3, 2, 1]
+        );
+        assert_eq!(machine.validate("ab a;"), (true, 4));
+    }
+
+    #[test]
+    fn test_backtracking_machine_reports_furthest_failure() {
+        let machine = comment_machine(ExecutionMode::Backtracking);
+        let error = machine.validate_detailed("ab ;").unwrap_err();
+        assert_eq!(error.offset, 3);
+        assert_eq!(error.state, "word");
+        assert_eq!(error.expected, vec!["\" \"", "\"#\""]);
+    }
+
+    #[test]
+    fn test_built_machine_indexes_transitions_by_state() {
+        let machine = comment_machine(ExecutionMode::Deterministic);
+        let labels: Vec<&str> = machine
+            .states()
+            .iter()
+            .map(|state| state.label.as_str())
+            .collect();
+        assert_eq!(labels, vec!["start", "word", "space", "comment"]);
+        assert_eq!(machine.outgoing(0).len(), 1);
+        assert_eq!(machine.targets(1), &[2]);
+        assert_eq!(machine.targets(2), &[3]);
+        assert!(machine.outgoing(3).is_empty());
+    }
+
+    #[test]
+    fn test_built_machine_keeps_transition_order_within_a_state() {
+        let start = Arc::new(create_state(false, "start"));
+        let a = Arc::new(create_state(true, "a"));
+        let b = Arc::new(create_state(true, "b"));
+        let machine = StateMachineBuilder::new(start.clone(), " ", 0)
+            .add_transitions(vec![
+                Arc::new(CharTransition::new(
+                    start.clone(),
+                    a.clone(),
+                    "x".to_string(),
+                    IndentationOperation::BYPASS,
+                )),
+                Arc::new(CharTransition::new(
+                    a,
+                    b.clone(),
+                    "y".to_string(),
+                    IndentationOperation::BYPASS,
+                )),
+                Arc::new(CharTransition::new(
+                    start,
+                    b,
+                    "x".to_string(),
+                    IndentationOperation::BYPASS,
+                )),
+            ])
+            .build();
+        // states only mentioned by transitions still get an id
+        assert_eq!(machine.states().len(), 3);
+        assert_eq!(machine.targets(0), &[1, 2]);
+        assert_eq!(machine.validate("xy"), (true, 2));
+    }
+
+    #[test]
+    fn test_machine_validates_buffers_in_parallel() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<StateMachine>();
+
+        let machine = comment_machine(ExecutionMode::Backtracking);
+        let buffers: Vec<String> = (0..64)
+            .map(|i| {
+                if i % 3 == 0 {
+                    "ab ;".to_string()
+                } else {
+                    "ab #".to_string()
+                }
+            })
+            .collect();
+        let results = machine.validate_all(&buffers);
+        assert_eq!(results.len(), 64);
+        for (i, result) in results.into_iter().enumerate() {
+            assert_eq!(result, machine.validate(&buffers[i]));
+        }
+        let errors = machine.check_detailed_all(&["ab #", "ab ;"]);
+        assert!(errors[0].is_ok());
+        assert_eq!(errors[1].as_ref().unwrap_err().offset, 3);
+        assert!(machine.validate_all::<&str>(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_try_build_reports_malformed_machine() {
+        let start = Arc::new(create_state(false, "start"));
+        let a = Arc::new(create_state(true, "a"));
+        let other_a = Arc::new(create_state(false, "a"));
+        let orphan = Arc::new(create_state(true, "orphan"));
+        let trap = Arc::new(create_state(false, "trap"));
+        let ghost = Arc::new(create_state(true, "ghost"));
+        let char_transition = |from: &Arc<State>, to: &Arc<State>| -> Arc<dyn Transition> {
+            Arc::new(CharTransition::new(
+                from.clone(),
+                to.clone(),
+                "x".to_string(),
+                IndentationOperation::BYPASS,
+            ))
+        };
+        let errors = StateMachineBuilder::new(start.clone(), " ", 0)
+            .name("faulty")
+            .add_states(vec![a.clone(), orphan, trap.clone(), other_a])
+            .add_transitions(vec![
+                char_transition(&start, &a),
+                char_transition(&start, &trap),
+                char_transition(&start, &ghost),
+            ])
+            .try_build()
+            .err()
+            .unwrap();
+        let machine = || "faulty".to_string();
+        assert_eq!(
+            errors,
+            vec![
+                GrammarError::DuplicateLabel {
+                    machine: machine(),
+                    label: "a".to_string()
+                },
+                GrammarError::UnknownState {
+                    machine: machine(),
+                    state: "ghost".to_string()
+                },
+                GrammarError::UnreachableState {
+                    machine: machine(),
+                    state: "orphan".to_string()
+                },
+                GrammarError::DeadState {
+                    machine: machine(),
+                    state: "trap".to_string()
+                },
+            ]
+        );
+
+        let errors = StateMachineBuilder::new(start.clone(), " ", 0)
+            .name("faulty")
+            .add_state(trap.clone())
+            .add_transition(char_transition(&start, &trap))
+            .try_build()
+            .err()
+            .unwrap();
+        assert_eq!(
+            errors,
+            vec![GrammarError::NoFinalState { machine: machine() }]
+        );
+    }
+
+    #[test]
+    fn test_try_build_accepts_well_formed_machine() {
+        let start = Arc::new(create_state(false, "start"));
+        let end = Arc::new(create_state(true, "end"));
+        let machine = StateMachineBuilder::new(start.clone(), " ", 0)
+            .add_state(end.clone())
+            .add_transition(Arc::new(CharTransition::new(
+                start,
+                end,
+                "x".to_string(),
+                IndentationOperation::BYPASS,
+            )))
+            .try_build()
+            .unwrap();
+        assert_eq!(machine.validate("x"), (true, 1));
+    }
+
+    #[test]
+    fn test_try_build_reports_epsilon_cycle() {
+        let start = Arc::new(create_state(false, "start"));
+        let back = Arc::new(create_state(false, "back"));
+        let end = Arc::new(create_state(true, "end"));
+        let errors = StateMachineBuilder::new(start.clone(), " ", 0)
+            .name("cycle")
+            .add_states(vec![back.clone(), end.clone()])
+            .add_transitions(vec![
+                Arc::new(EpsilonTransition::new(start.clone(), back.clone())),
+                Arc::new(EpsilonTransition::new(back.clone(), start)),
+                Arc::new(CharTransition::new(
+                    back,
+                    end,
+                    "x".to_string(),
+                    IndentationOperation::BYPASS,
+                )),
+            ])
+            .try_build()
+            .err()
+            .unwrap();
+        assert_eq!(
+            errors,
+            vec![GrammarError::EpsilonCycle {
+                machine: "cycle".to_string(),
+                states: vec!["start".to_string(), "back".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_run_aborts_when_making_no_progress() {
+        // a group accepting the empty word, taken over and over from `letter`
+        let empty_start = Arc::new(create_state(false, "empty_start"));
+        let empty_end = Arc::new(create_state(true, "empty_end"));
+        let empty = StateMachineBuilder::new(empty_start.clone(), " ", 0)
+            .name("empty")
+            .add_transition(Arc::new(EpsilonTransition::new(empty_start, empty_end)))
+            .build();
+        let start = Arc::new(create_state(false, "start"));
+        let letter = Arc::new(create_state(true, "letter"));
+        let machine = StateMachineBuilder::new(start.clone(), " ", 0)
+            .name("spinning")
+            .add_transitions(vec![
+                Arc::new(CharTransition::new(
+                    start,
+                    letter.clone(),
+                    "a".to_string(),
+                    IndentationOperation::BYPASS,
+                )),
+                Arc::new(GroupTransition::new(
+                    letter.clone(),
+                    letter,
+                    empty,
+                    IndentationOperation::BYPASS,
+                )),
+            ])
+            .build();
+
+        assert_eq!(machine.validate("ab"), (false, 1));
+        assert_eq!(
+            machine.try_validate_with("ab", &mut RunContext::new()),
+            Err(RunError::NoProgress {
+                machine: "spinning".to_string(),
+                state: "letter".to_string(),
+                offset: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn test_run_aborts_after_max_steps() {
+        let machine = comment_machine(ExecutionMode::Backtracking);
+        let mut context = RunContext::new();
+        context.max_steps(3);
+        assert!(matches!(
+            machine.try_validate_with("ab #", &mut context),
+            Err(RunError::StepLimit { limit: 3, .. })
+        ));
+        context.max_steps(100);
+        assert_eq!(
+            machine.try_validate_with("ab #", &mut context),
+            Ok((true, 4))
+        );
+    }
+
+    // `a` and `ab` both match "ab", the word being added last with `priority`
+    fn prefix_machine(strategy: MatchStrategy, priority: i32) -> StateMachine {
+        let start = Arc::new(create_state(false, "start"));
+        let short = Arc::new(create_state(true, "short"));
+        let long = Arc::new(create_state(true, "long"));
+        StateMachineBuilder::new(start.clone(), " ", 0)
+            .name("prefix")
+            .strategy(strategy)
+            .add_states(vec![short.clone(), long.clone()])
+            .add_transition(Arc::new(CharTransition::new(
+                start.clone(),
+                short,
+                "a".to_string(),
+                IndentationOperation::BYPASS,
+            )))
+            .add_transition_with_priority(
+                create_word_transition(
+                    start,
+                    long,
+                    "ab".to_string(),
+                    IndentationOperation::BYPASS,
+                    0,
+                ),
+                priority,
+            )
+            .build()
+    }
+
+    #[test]
+    fn test_higher_priority_transitions_are_tried_first() {
+        let machine = prefix_machine(MatchStrategy::FirstMatch, 0);
+        assert_eq!(machine.validate("ab"), (true, 1));
+        let machine = prefix_machine(MatchStrategy::FirstMatch, 1);
+        assert_eq!(machine.priorities(0), &[1, 0]);
+        assert_eq!(machine.validate("ab"), (true, 2));
+    }
+
+    #[test]
+    fn test_longest_match_strategy_takes_longest_transition() {
+        let machine = prefix_machine(MatchStrategy::LongestMatch, 0);
+        assert_eq!(machine.validate("ab"), (true, 2));
+        assert_eq!(machine.validate("ac"), (true, 1));
+    }
+
+    #[test]
+    fn test_error_on_ambiguity_strategy_aborts_ambiguous_runs() {
+        let machine = prefix_machine(MatchStrategy::ErrorOnAmbiguity, 0);
+        assert_eq!(
+            machine.try_validate_with("ab", &mut RunContext::new()),
+            Err(RunError::Ambiguous {
+                machine: "prefix".to_string(),
+                state: "start".to_string(),
+                offset: 0,
+                transitions: vec!["\"a\"".to_string(), "<\"ab\">".to_string()],
+            })
+        );
+        assert_eq!(machine.validate("ac"), (true, 1));
+
+        // a priority settles the ambiguity
+        let machine = prefix_machine(MatchStrategy::ErrorOnAmbiguity, 1);
+        assert_eq!(
+            machine.try_validate_with("ab", &mut RunContext::new()),
+            Ok((true, 2))
+        );
     }
 }
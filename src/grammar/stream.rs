@@ -0,0 +1,360 @@
+use std::{
+    io::{BufRead, BufReader, Read},
+    ops::ControlFlow,
+};
+
+use super::{
+    context::RunContext,
+    error::StreamError,
+    execution::{drive, Nesting, Outcome, Source},
+    indentation::Indentation,
+    input::Input,
+    registry::Grammar,
+    state_machine::StateMachine,
+};
+
+/// Bytes of input a stream keeps in memory unless told otherwise.
+pub const DEFAULT_WINDOW: usize = 1 << 16;
+
+/// Validates input read from a `BufRead` without holding all of it in memory.
+///
+/// Deterministic first-match and backtracking machines, as groups, rules or the
+/// machine validated, are run one transition at a time: the state, indentation
+/// and stack of groups of the run are kept across reads, and input is dropped
+/// once no transition can come back to it. Backtracking runs explore their
+/// alternatives in the order of their offsets, so they only keep the input
+/// from their earliest pending alternative or accepting end on. Any other
+/// transition, and any other machine, must fit in the window along with the
+/// input it looks at.
+pub struct StreamValidator<'m> {
+    machine: &'m StateMachine,
+    grammar: Option<&'m Grammar>,
+    window: usize,
+}
+
+impl StateMachine {
+    /// Validates everything `reader` yields, with the default window.
+    pub fn validate_reader(&self, reader: impl Read) -> Result<(bool, usize), StreamError> {
+        StreamValidator::new(self).validate(BufReader::new(reader))
+    }
+}
+
+impl Grammar {
+    /// Validates everything `reader` yields against the rule named `rule`, with
+    /// the default window.
+    pub fn validate_reader(
+        &self,
+        rule: &str,
+        reader: impl Read,
+    ) -> Result<(bool, usize), StreamError> {
+        let machine = self.find_rule(rule).map_err(StreamError::Grammar)?;
+        StreamValidator::new(machine)
+            .grammar(self)
+            .validate(BufReader::new(reader))
+    }
+}
+
+impl<'m> StreamValidator<'m> {
+    pub fn new(machine: &'m StateMachine) -> Self {
+        StreamValidator {
+            machine,
+            grammar: None,
+            window: DEFAULT_WINDOW,
+        }
+    }
+
+    /// Resolves rule transitions against `grammar`.
+    pub fn grammar(&mut self, grammar: &'m Grammar) -> &mut Self {
+        self.grammar = Some(grammar);
+        self
+    }
+
+    /// The most bytes of input kept in memory at once.
+    pub fn window(&mut self, bytes: usize) -> &mut Self {
+        self.window = bytes;
+        self
+    }
+
+    /// Validates the stream from its start. The result is the one `validate`
+    /// gives on the whole text.
+    pub fn validate(&self, reader: impl BufRead) -> Result<(bool, usize), StreamError> {
+        let mut window = Window::new(reader, self.window);
+        let mut context = RunContext::new();
+        if let Some(grammar) = self.grammar {
+            context.with_grammar(grammar);
+        }
+        let nesting = Nesting {
+            backtracking: true,
+            grammar: self.grammar,
+        };
+        if !nesting.runs(self.machine) {
+            return window.run(0, &mut context, |input, context| {
+                self.machine
+                    .validate_in_context(input, 0, &Indentation::new(), context)
+            });
+        }
+
+        let mut frames = vec![nesting.frame(self.machine, 0, Indentation::new(), true)];
+        match drive(&mut frames, &mut window, &mut context, nesting, |_, _| {
+            ControlFlow::Continue(())
+        })? {
+            Outcome::Done(accepted, offset) => Ok((accepted, offset)),
+            Outcome::Paused => unreachable!("streams are never paused"),
+        }
+    }
+}
+
+/// The part of the stream kept in memory.
+struct Window<R> {
+    reader: R,
+    text: String,
+    /// Offset of the first byte of `text` in the stream.
+    base: usize,
+    /// Bytes read after `text` that do not make a whole character yet.
+    partial: Vec<u8>,
+    done: bool,
+    capacity: usize,
+}
+
+impl<R: BufRead> Source for Window<R> {
+    fn run<T>(
+        &mut self,
+        offset: usize,
+        context: &mut RunContext,
+        mut attempt: impl FnMut(&Input, &mut RunContext) -> T,
+    ) -> Result<T, StreamError> {
+        loop {
+            let input = Input::window(&self.text, self.base, self.done);
+            let result = attempt(&input, context);
+            if !input.is_starved() {
+                return Ok(result);
+            }
+            self.read(offset)?;
+        }
+    }
+
+    fn forget_before(&mut self, offset: usize) {
+        if offset > self.base {
+            self.text.drain(..offset - self.base);
+            self.base = offset;
+        }
+    }
+}
+
+impl<R: BufRead> Window<R> {
+    fn new(reader: R, capacity: usize) -> Self {
+        Window {
+            reader,
+            text: String::new(),
+            base: 0,
+            partial: Vec::new(),
+            done: false,
+            capacity,
+        }
+    }
+
+    /// Reads the next chunk of the stream.
+    fn read(&mut self, offset: usize) -> Result<(), StreamError> {
+        let available = self
+            .capacity
+            .saturating_sub(self.text.len() + self.partial.len());
+        if available == 0 {
+            return Err(StreamError::WindowExceeded {
+                offset,
+                window: self.capacity,
+            });
+        }
+        let chunk = self.reader.fill_buf().map_err(|error| StreamError::Io {
+            message: error.to_string(),
+        })?;
+        if chunk.is_empty() {
+            if !self.partial.is_empty() {
+                return Err(invalid_utf8());
+            }
+            self.done = true;
+            return Ok(());
+        }
+        let read = chunk.len().min(available);
+        self.partial.extend_from_slice(&chunk[..read]);
+        self.reader.consume(read);
+
+        let valid = match std::str::from_utf8(&self.partial) {
+            Ok(text) => text.len(),
+            Err(error) if error.error_len().is_none() => error.valid_up_to(),
+            Err(_) => return Err(invalid_utf8()),
+        };
+        let decoded = std::str::from_utf8(&self.partial[..valid]).expect("checked above");
+        self.text.push_str(decoded);
+        self.partial.drain(..valid);
+        Ok(())
+    }
+}
+
+fn invalid_utf8() -> StreamError {
+    StreamError::Io {
+        message: "stream is not valid UTF-8".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufReader, Cursor},
+        sync::Arc,
+    };
+
+    use crate::{
+        grammar::{
+            error::StreamError,
+            state::create_state,
+            state_machine::{MatchStrategy, StateMachineBuilder},
+            transition::{create_class_transition, IndentationOperation},
+        },
+        yaml::{document::document_state_machine, mapping::yaml_grammar},
+    };
+
+    use super::StreamValidator;
+
+    fn validate_stream(
+        buffer: &[u8],
+        chunk: usize,
+        window: usize,
+    ) -> Result<(bool, usize), StreamError> {
+        let machine = document_state_machine(0);
+        StreamValidator::new(&machine)
+            .window(window)
+            .validate(BufReader::with_capacity(chunk, Cursor::new(buffer)))
+    }
+
+    #[test]
+    fn test_stream_gives_same_result_as_validate() {
+        let machine = document_state_machine(0);
+        for buffer in [
+            "---\ntest:test\n---",
+            "---\nzob:test\nlist:\n -zob\n -zizi\n---",
+            "---\nzob:test\ntest:\n zob:\n  -\n---",
+            "---\nclé:valeur\n---",
+            "---\ntest:te;st\n---",
+            "---\n",
+            "",
+        ] {
+            for chunk in [1, 3, 64] {
+                assert_eq!(
+                    validate_stream(buffer.as_bytes(), chunk, 64),
+                    Ok(machine.validate(buffer)),
+                    "{:?} read {} bytes at a time",
+                    buffer,
+                    chunk
+                );
+            }
+        }
+        assert_eq!(
+            machine.validate_reader("---\ntest:test\n---".as_bytes()),
+            Ok((true, 17))
+        );
+    }
+
+    #[test]
+    fn test_stream_keeps_a_bounded_window() {
+        let mut buffer = String::from("---\n");
+        for i in 0..2000 {
+            buffer.push_str(if i % 2 == 0 {
+                "key:value\n"
+            } else {
+                "list:\n -a\n -b\n"
+            });
+        }
+        buffer.push_str("---");
+        assert_eq!(
+            validate_stream(buffer.as_bytes(), 7, 48),
+            Ok((true, buffer.len()))
+        );
+    }
+
+    #[test]
+    fn test_stream_holds_backtracking_groups_longer_than_the_window() {
+        let machine = document_state_machine(0);
+        let buffer = format!(
+            "---\nkey:{}\nother:{}\n---",
+            "a b".repeat(400),
+            "c".repeat(99)
+        );
+        for chunk in [1, 8, 48] {
+            assert_eq!(
+                validate_stream(buffer.as_bytes(), chunk, 48),
+                Ok((true, buffer.len()))
+            );
+        }
+        let broken = format!("---\nkey:{};\n---", "a".repeat(200));
+        assert_eq!(
+            validate_stream(broken.as_bytes(), 8, 48),
+            Ok(machine.validate(&broken))
+        );
+    }
+
+    #[test]
+    fn test_stream_runs_grammar_rules() {
+        let grammar = yaml_grammar();
+        let mut buffer = String::new();
+        for i in 0..200 {
+            buffer.push_str(&format!(
+                "entry{}:\n nested:\n  leaf:value\n  list:\n   -a\n   -b\n other:x\n",
+                char::from(b'a' + (i % 26) as u8)
+            ));
+        }
+        buffer.push_str("last:value");
+        let mapping = grammar.rule("mapping").unwrap();
+        for (text, window) in [(buffer.as_str(), 64), ("a:\n b:c\n  d:e", 64), ("a:b\n", 8)] {
+            assert_eq!(
+                StreamValidator::new(mapping)
+                    .grammar(&grammar)
+                    .window(window)
+                    .validate(BufReader::with_capacity(5, Cursor::new(text))),
+                Ok(grammar.validate("mapping", text).unwrap()),
+                "{:?}",
+                text
+            );
+        }
+        assert_eq!(
+            grammar.validate_reader("mapping", buffer.as_bytes()),
+            Ok((true, buffer.len()))
+        );
+    }
+
+    #[test]
+    fn test_stream_reports_what_it_cannot_hold() {
+        // longest-match runs try every transition at once, so they are not streamed
+        let start = Arc::new(create_state(false, "start"));
+        let letters = Arc::new(create_state(true, "letters"));
+        let machine = StateMachineBuilder::new(start.clone(), " ", 0)
+            .strategy(MatchStrategy::LongestMatch)
+            .add_state(letters.clone())
+            .add_transition(create_class_transition(
+                start,
+                letters.clone(),
+                "a",
+                IndentationOperation::BYPASS,
+            ))
+            .add_transition(create_class_transition(
+                letters.clone(),
+                letters,
+                "a",
+                IndentationOperation::BYPASS,
+            ))
+            .build();
+        let buffer = "a".repeat(100);
+        assert_eq!(
+            StreamValidator::new(&machine)
+                .window(48)
+                .validate(BufReader::with_capacity(8, Cursor::new(buffer))),
+            Err(StreamError::WindowExceeded {
+                offset: 0,
+                window: 48
+            })
+        );
+        assert!(matches!(
+            validate_stream(b"---\nkey:\xff\n---", 8, 48),
+            Err(StreamError::Io { .. })
+        ));
+    }
+}
@@ -0,0 +1,473 @@
+use std::{collections::HashSet, fs, path::Path, sync::Arc};
+
+use super::{
+    class::{CharClass, ClassSyntax},
+    error::{line_column, GrammarError},
+    fragment::{Fragment, FragmentBuilder},
+    registry::{Grammar, GrammarBuilder},
+    transition::{CharTransition, ClassTransition, IndentationOperation, RuleTransition},
+};
+
+enum Expr {
+    Literal {
+        text: String,
+        operation: IndentationOperation,
+    },
+    Class {
+        class: CharClass,
+        operation: IndentationOperation,
+    },
+    Rule {
+        name: String,
+        offset: usize,
+        operation: IndentationOperation,
+    },
+    Sequence(Vec<Expr>),
+    Alternation(Vec<Expr>),
+    Star(Box<Expr>),
+    Plus(Box<Expr>),
+    Optional(Box<Expr>),
+}
+
+struct Rule {
+    name: String,
+    offset: usize,
+    expr: Expr,
+}
+
+impl Grammar {
+    /// Parses a grammar definition made of rules such as
+    ///
+    /// ```text
+    /// # comments run to the end of the line
+    /// sequence = "-" scalar ( "\n"@conserve "-" scalar )* ;
+    /// ```
+    ///
+    /// Expressions are made of string literals, character classes like
+    /// `[^:#\n]` or `[\p{Lu}]`, references to other rules, groups, alternatives
+    /// separated by `|` and the `*`, `+` and `?` repetitions. A literal, class or
+    /// rule reference can be followed by an indentation operator: `@increment`,
+    /// `@decrement`, `@conserve`, `@reset` or `@bypass`, the default. On a
+    /// literal the operator applies to its last character.
+    pub fn parse(source: &str) -> Result<Grammar, GrammarError> {
+        let rules = Parser::new(source).rules()?;
+
+        let mut defined = HashSet::new();
+        for rule in &rules {
+            if !defined.insert(rule.name.as_str()) {
+                return Err(syntax_error(
+                    source,
+                    rule.offset,
+                    format!("rule {} is defined twice", rule.name),
+                ));
+            }
+        }
+        for rule in &rules {
+            check_references(source, &rule.expr, &defined)?;
+        }
+
+        let mut grammar = GrammarBuilder::new();
+        for rule in &rules {
+            let mut builder = FragmentBuilder::new("s");
+            let fragment = compile(&mut builder, &rule.expr);
+            grammar.add_rule(&rule.name, builder.build(fragment, &rule.name, " "));
+        }
+        grammar.build()
+    }
+
+    /// Reads and parses the grammar file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Grammar, GrammarError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|error| GrammarError::Io {
+            path: path.display().to_string(),
+            message: error.to_string(),
+        })?;
+        Grammar::parse(&source)
+    }
+}
+
+fn syntax_error(source: &str, offset: usize, message: String) -> GrammarError {
+    let (line, column) = line_column(source, offset);
+    GrammarError::Syntax {
+        line,
+        column,
+        message,
+    }
+}
+
+fn check_references(
+    source: &str,
+    expr: &Expr,
+    defined: &HashSet<&str>,
+) -> Result<(), GrammarError> {
+    match expr {
+        Expr::Rule { name, offset, .. } if !defined.contains(name.as_str()) => Err(syntax_error(
+            source,
+            *offset,
+            format!("unknown rule {}", name),
+        )),
+        Expr::Sequence(items) | Expr::Alternation(items) => items
+            .iter()
+            .try_for_each(|item| check_references(source, item, defined)),
+        Expr::Star(inner) | Expr::Plus(inner) | Expr::Optional(inner) => {
+            check_references(source, inner, defined)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn compile(builder: &mut FragmentBuilder, expr: &Expr) -> Fragment {
+    match expr {
+        Expr::Literal { text, operation } => {
+            let count = text.chars().count();
+            let fragments = text
+                .chars()
+                .enumerate()
+                .map(|(index, c)| {
+                    let operation = if index + 1 == count {
+                        operation.clone()
+                    } else {
+                        IndentationOperation::BYPASS
+                    };
+                    builder.transition(|from, to| {
+                        Arc::new(CharTransition::new(from, to, c.to_string(), operation))
+                    })
+                })
+                .collect();
+            builder.concat(fragments)
+        }
+        Expr::Class { class, operation } => builder.transition(|from, to| {
+            Arc::new(ClassTransition::new(
+                from,
+                to,
+                class.clone(),
+                operation.clone(),
+            ))
+        }),
+        Expr::Rule {
+            name, operation, ..
+        } => builder.transition(|from, to| {
+            Arc::new(RuleTransition::new(from, to, name, operation.clone()))
+        }),
+        Expr::Sequence(items) => {
+            let fragments = items.iter().map(|item| compile(builder, item)).collect();
+            builder.concat(fragments)
+        }
+        Expr::Alternation(items) => {
+            let fragments = items.iter().map(|item| compile(builder, item)).collect();
+            builder.union(fragments)
+        }
+        Expr::Star(inner) => {
+            let fragment = compile(builder, inner);
+            builder.star(fragment)
+        }
+        Expr::Plus(inner) => {
+            let fragment = compile(builder, inner);
+            builder.plus(fragment)
+        }
+        Expr::Optional(inner) => {
+            let fragment = compile(builder, inner);
+            builder.optional(fragment)
+        }
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Self {
+        Parser { source, offset: 0 }
+    }
+
+    /// Skips whitespace and comments.
+    fn skip_trivia(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '#' {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.bump();
+                }
+            } else if c.is_whitespace() {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        self.skip_trivia();
+        if self.peek() == Some(expected) {
+            self.bump();
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), GrammarError> {
+        if self.eat(expected) {
+            return Ok(());
+        }
+        Err(self.error(self.offset, format!("expected {:?}", expected)))
+    }
+
+    fn rules(&mut self) -> Result<Vec<Rule>, GrammarError> {
+        let mut rules = Vec::new();
+        loop {
+            self.skip_trivia();
+            if self.peek().is_none() {
+                return Ok(rules);
+            }
+            let offset = self.offset;
+            let name = self
+                .identifier()
+                .ok_or_else(|| self.error(offset, "expected a rule name"))?;
+            self.expect('=')?;
+            let expr = self.alternation()?;
+            self.expect(';')?;
+            rules.push(Rule { name, offset, expr });
+        }
+    }
+
+    fn identifier(&mut self) -> Option<String> {
+        let start = self.offset;
+        if !self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        {
+            return None;
+        }
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            self.bump();
+        }
+        Some(self.source[start..self.offset].to_string())
+    }
+
+    fn alternation(&mut self) -> Result<Expr, GrammarError> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.eat('|') {
+            alternatives.push(self.sequence()?);
+        }
+        Ok(match alternatives.len() {
+            1 => alternatives.remove(0),
+            _ => Expr::Alternation(alternatives),
+        })
+    }
+
+    fn sequence(&mut self) -> Result<Expr, GrammarError> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_trivia();
+            match self.peek() {
+                Some('"' | '[' | '(') => items.push(self.postfix()?),
+                Some(c) if c.is_ascii_alphabetic() || c == '_' => items.push(self.postfix()?),
+                _ => break,
+            }
+        }
+        Ok(match items.len() {
+            0 => return Err(self.error(self.offset, "expected an expression")),
+            1 => items.remove(0),
+            _ => Expr::Sequence(items),
+        })
+    }
+
+    fn postfix(&mut self) -> Result<Expr, GrammarError> {
+        let grouped = self.peek() == Some('(');
+        let mut expr = self.atom()?;
+        if self.eat('@') {
+            let offset = self.offset;
+            let operation = match self.identifier().as_deref() {
+                Some("increment") => IndentationOperation::INCREMENT,
+                Some("decrement") => IndentationOperation::DESINCREMENT,
+                Some("conserve") => IndentationOperation::CONSERVE,
+                Some("reset") => IndentationOperation::RESET,
+                Some("bypass") => IndentationOperation::BYPASS,
+                _ => {
+                    return Err(self.error(offset, "expected an indentation operator"));
+                }
+            };
+            match &mut expr {
+                Expr::Literal { operation: op, .. }
+                | Expr::Class { operation: op, .. }
+                | Expr::Rule { operation: op, .. }
+                    if !grouped =>
+                {
+                    *op = operation
+                }
+                _ => {
+                    return Err(self.error(
+                        offset,
+                        "indentation operators apply to literals, classes and rules",
+                    ));
+                }
+            }
+        }
+        loop {
+            expr = if self.eat('*') {
+                Expr::Star(Box::new(expr))
+            } else if self.eat('+') {
+                Expr::Plus(Box::new(expr))
+            } else if self.eat('?') {
+                Expr::Optional(Box::new(expr))
+            } else {
+                return Ok(expr);
+            };
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, GrammarError> {
+        let offset = self.offset;
+        match self.peek() {
+            Some('"') => {
+                self.bump();
+                let mut text = String::new();
+                loop {
+                    match self.bump() {
+                        Some('"') => break,
+                        Some('\\') => text.push(self.escape()?),
+                        Some(c) => text.push(c),
+                        None => return Err(self.error(offset, "unterminated string")),
+                    }
+                }
+                if text.is_empty() {
+                    return Err(self.error(offset, "empty string"));
+                }
+                Ok(Expr::Literal {
+                    text,
+                    operation: IndentationOperation::BYPASS,
+                })
+            }
+            Some('[') => {
+                self.bump();
+                Ok(Expr::Class {
+                    class: self.class(offset)?,
+                    operation: IndentationOperation::BYPASS,
+                })
+            }
+            Some('(') => {
+                self.bump();
+                let expr = self.alternation()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            _ => {
+                let name = self
+                    .identifier()
+                    .ok_or_else(|| self.error(offset, "expected an expression"))?;
+                Ok(Expr::Rule {
+                    name,
+                    offset,
+                    operation: IndentationOperation::BYPASS,
+                })
+            }
+        }
+    }
+}
+
+impl ClassSyntax for Parser<'_> {
+    type Error = GrammarError;
+
+    const UNTERMINATED_CLASS: &'static str = "unterminated class";
+
+    fn source(&self) -> &str {
+        self.source
+    }
+
+    fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn set_offset(&mut self, offset: usize) {
+        self.offset = offset;
+    }
+
+    fn error(&self, offset: usize, message: impl Into<String>) -> GrammarError {
+        syntax_error(self.source, offset, message.into())
+    }
+
+    fn other_escape(&mut self, offset: usize, c: Option<char>) -> Result<char, GrammarError> {
+        match c {
+            Some(c @ ('\\' | '"' | '[' | ']' | '^' | '-')) => Ok(c),
+            _ => Err(self.error(offset, "unknown escape sequence")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grammar::{error::GrammarError, registry::Grammar};
+
+    fn syntax_error(source: &str) -> (usize, usize, String) {
+        match Grammar::parse(source).unwrap_err() {
+            GrammarError::Syntax {
+                line,
+                column,
+                message,
+            } => (line, column, message),
+            error => panic!("unexpected error {}", error),
+        }
+    }
+
+    #[test]
+    fn test_grammar_parses_literals_classes_and_repetitions() {
+        let grammar = Grammar::parse(
+            "# a list of words
+            list = word ( \", \" word )* \";\"? ;
+            word = [a-z\\p{Lu}]+ ;",
+        )
+        .unwrap();
+        assert_eq!(grammar.rule_names(), vec!["list", "word"]);
+        assert!(grammar.check("list", "ab, Cd, ÉTÉ").unwrap());
+        assert!(grammar.check("list", "ab;").unwrap());
+        assert!(!grammar.check("list", "ab,cd").unwrap());
+        assert!(!grammar.check("list", "ab, ").unwrap());
+    }
+
+    #[test]
+    fn test_grammar_parses_indentation_operators() {
+        let grammar = Grammar::parse(
+            "block = \"a:\" ( \"\\n\"@increment block@decrement | \"b\" ) ( \"\\n\"@conserve block )? ;",
+        )
+        .unwrap();
+        assert!(grammar.check("block", "a:\n a:b\n a:b\na:b").unwrap());
+        assert!(grammar.check("block", "a:\n  a:b\n  a:b").unwrap());
+        assert!(!grammar.check("block", "a:\n  a:b\n a:b").unwrap());
+    }
+
+    #[test]
+    fn test_grammar_reports_positioned_errors() {
+        assert_eq!(
+            syntax_error("a = \"x\" ;\nb = [x-a] ;"),
+            (2, 6, "range is out of order".to_string())
+        );
+        assert_eq!(
+            syntax_error("a = \"x\" c ;"),
+            (1, 9, "unknown rule c".to_string())
+        );
+        assert_eq!(
+            syntax_error("a = \"x\"\n"),
+            (2, 1, "expected ';'".to_string())
+        );
+        assert_eq!(
+            syntax_error("a = ( \"x\" )@increment ;"),
+            (
+                1,
+                13,
+                "indentation operators apply to literals, classes and rules".to_string()
+            )
+        );
+        assert_eq!(
+            syntax_error("a = \"x ;"),
+            (1, 5, "unterminated string".to_string())
+        );
+        assert_eq!(
+            syntax_error("a = \"x\" ;\na = \"y\" ;"),
+            (2, 1, "rule a is defined twice".to_string())
+        );
+    }
+}
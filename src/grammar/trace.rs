@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+
+use super::indentation::Indentation;
+
+/// One transition taken during a run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceStep {
+    /// Name of the machine the transition belongs to.
+    pub machine: String,
+    /// Label of the state the transition left.
+    pub from: String,
+    /// What the transition matched, as shown in validation errors.
+    pub transition: String,
+    /// Label of the state the transition reached.
+    pub to: String,
+    pub offset_before: usize,
+    pub offset_after: usize,
+    pub indentation_before: Indentation,
+    pub indentation_after: Indentation,
+    /// How many group transitions deep the machine was, 0 being the machine the
+    /// run was started on.
+    pub depth: usize,
+}
+
+/// Every step of a run, in the order they were taken, and its outcome.
+///
+/// Steps of a group's inner machine come before the step of the group itself.
+/// Backtracking machines record every step they explore, including the ones of
+/// abandoned paths.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trace {
+    pub accepted: bool,
+    pub offset: usize,
+    pub steps: Vec<TraceStep>,
+}
+
+impl Trace {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a trace is always serialisable")
+    }
+
+    pub fn from_json(json: &str) -> Result<Trace, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Index of the first step where the two traces differ, if any. Useful to
+    /// compare the runs of two versions of a grammar.
+    pub fn first_divergence(&self, other: &Trace) -> Option<usize> {
+        let common = self.steps.len().min(other.steps.len());
+        (0..common)
+            .find(|index| self.steps[*index] != other.steps[*index])
+            .or((self.steps.len() != other.steps.len()).then_some(common))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::yaml::kv::kv_state_machine;
+
+    use super::Trace;
+
+    #[test]
+    fn test_trace_records_nested_steps() {
+        let trace = kv_state_machine(0).trace("ab:c");
+        assert!(trace.accepted);
+        assert_eq!(trace.offset, 4);
+        assert_eq!(trace.steps.len(), 7);
+
+        let first = &trace.steps[0];
+        assert_eq!(first.machine, "key");
+        assert_eq!(first.depth, 1);
+        assert_eq!((first.offset_before, first.offset_after), (0, 1));
+
+        let key = &trace.steps[2];
+        assert_eq!(key.machine, "kv");
+        assert_eq!((key.from.as_str(), key.to.as_str()), ("start", "key"));
+        assert_eq!(key.transition, "<key>");
+        assert_eq!((key.offset_before, key.offset_after), (0, 2));
+        assert_eq!(key.depth, 0);
+
+        let scalar = &trace.steps[4];
+        assert_eq!(scalar.machine, "scalar");
+        assert_eq!(scalar.depth, 2);
+    }
+
+    #[test]
+    fn test_trace_round_trips_through_json() {
+        let trace = kv_state_machine(0).trace("ab:\n -c");
+        let json = trace.to_json();
+        assert!(json.contains("\"indentation_after\""));
+        assert_eq!(Trace::from_json(&json).unwrap(), trace);
+    }
+
+    #[test]
+    fn test_traces_diverge_where_runs_differ() {
+        let machine = kv_state_machine(0);
+        let short = machine.trace("ab:c");
+        let long = machine.trace("ab:cd");
+        assert_eq!(short.first_divergence(&short.clone()), None);
+        // the scalar takes one more step on the longer value
+        assert_eq!(short.first_divergence(&long), Some(5));
+    }
+}
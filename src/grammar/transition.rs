@@ -1,9 +1,15 @@
 use core::fmt;
-use std::rc::Rc;
+use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
 use tracing::debug;
 
 use super::{
+    class::{CharClass, CharClassBuilder},
+    context::RunContext,
+    error::RunError,
+    indentation::{measure, Indentation, LineIndentation},
+    input::Input,
     state::{create_state, State},
     state_machine::{StateMachine, StateMachineBuilder},
 };
@@ -12,161 +18,554 @@ pub enum ErrorTransition {
     InvalidTransition,
 }
 
-#[derive(Clone)]
+/// What a transition does with the indentation stack. Char and class
+/// transitions check the blanks following the character they match against it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum IndentationOperation {
+    /// Leaves the stack alone.
     BYPASS = 2,
+    /// Pushes a block indented deeper than the innermost one.
     INCREMENT = 1,
+    /// Pops the innermost block, the line being as deep as the one around it.
     DESINCREMENT = -1,
+    /// Compares the line with the innermost block, which must be at least as
+    /// deep. Only the blanks of the block are consumed, so a deeper line
+    /// fails on its extra blanks instead of closing the block.
     CONSERVE = 0,
+    /// Empties the stack.
     RESET = -2,
 }
+
+impl IndentationOperation {
+    /// Whether a line starting with `line` fits this operation.
+    pub fn allows(&self, indentation: &Indentation, line: &LineIndentation) -> bool {
+        match self {
+            IndentationOperation::BYPASS | IndentationOperation::RESET => true,
+            IndentationOperation::INCREMENT => line.column > indentation.column(),
+            IndentationOperation::DESINCREMENT => indentation.parent_column() == Some(line.column),
+            IndentationOperation::CONSERVE => line.column >= indentation.column(),
+        }
+    }
+
+    /// Returns the indentation once this operation has been applied, after a
+    /// line starting with `line` if the transition measured it. Otherwise an
+    /// increment pushes a block one column deeper.
+    pub fn apply(&self, indentation: &Indentation, line: Option<&LineIndentation>) -> Indentation {
+        let mut next = indentation.clone();
+        match self {
+            IndentationOperation::BYPASS | IndentationOperation::CONSERVE => {}
+            IndentationOperation::INCREMENT => match line {
+                Some(line) => next.push(line.column, line.character),
+                None => next.push(indentation.column() + 1, None),
+            },
+            IndentationOperation::DESINCREMENT => next.pop(),
+            IndentationOperation::RESET => next = Indentation::new(),
+        }
+        next
+    }
+}
 pub struct CharTransition {
-    //using rc because state can be shared between multiple transitions but no mutation should
-    //occur
-    pub from: Rc<State>,
-    pub to: Rc<State>,
+    //using arc because state can be shared between multiple transitions, and machines
+    //between threads, but no mutation should occur
+    pub from: Arc<State>,
+    pub to: Arc<State>,
     pub value: String,
     pub indentation_operation: IndentationOperation,
 }
 
+pub struct ClassTransition {
+    pub from: Arc<State>,
+    pub to: Arc<State>,
+    pub class: CharClass,
+    pub indentation_operation: IndentationOperation,
+}
+
 pub struct EpsilonTransition {
-    pub from: Rc<State>,
-    pub to: Rc<State>,
+    pub from: Arc<State>,
+    pub to: Arc<State>,
 }
 
 pub struct GroupTransition {
-    pub from: Rc<State>,
-    pub to: Rc<State>,
-    pub value: Box<StateMachine>,
+    pub from: Arc<State>,
+    pub to: Arc<State>,
+    pub value: Arc<StateMachine>,
     pub indentation_operation: IndentationOperation,
 }
 
-pub trait Transition {
-    fn from(&self) -> Rc<State>;
+/// Runs the rule named `rule` of the grammar the validation is done with, as
+/// a group. The rule is looked up when the transition is tried, so rules can
+/// refer to themselves.
+pub struct RuleTransition {
+    pub from: Arc<State>,
+    pub to: Arc<State>,
+    pub rule: String,
+    pub indentation_operation: IndentationOperation,
+}
+
+/// Matches whatever `transition` matches and labels the matched text, so runs
+/// can report it as a capture span.
+pub struct CaptureTransition {
+    pub label: String,
+    pub transition: Arc<dyn Transition>,
+}
+
+/// What a lookahead transition checks at the current offset.
+#[derive(Clone)]
+pub enum Lookahead {
+    Char(char),
+    Class(CharClass),
+    /// Holds when the machine, run as a group, accepts a prefix of the rest of
+    /// the input.
+    Machine(Arc<StateMachine>),
+}
+
+impl fmt::Display for Lookahead {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Lookahead::Char(c) => write!(f, "{:?}", c),
+            Lookahead::Class(class) => write!(f, "{}", class),
+            Lookahead::Machine(machine) => write!(f, "<{}>", machine.name()),
+        }
+    }
+}
+
+/// Goes to `to` without consuming anything when `condition` holds at the
+/// current offset.
+pub struct LookaheadTransition {
+    pub from: Arc<State>,
+    pub to: Arc<State>,
+    pub condition: Lookahead,
+}
+
+/// Goes to `to` without consuming anything when `condition` does not hold at
+/// the current offset, the end of the input included.
+pub struct NegativeLookaheadTransition {
+    pub from: Arc<State>,
+    pub to: Arc<State>,
+    pub condition: Lookahead,
+}
+
+/// What a transition matches, used to describe it without downcasting.
+pub enum TransitionKind<'a> {
+    Char(&'a str),
+    Class(&'a CharClass),
+    Epsilon,
+    Group(&'a StateMachine),
+    Rule(&'a str),
+    Lookahead {
+        condition: &'a Lookahead,
+        negative: bool,
+    },
+}
+
+impl fmt::Display for TransitionKind<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransitionKind::Char(value) => write!(f, "{:?}", value),
+            TransitionKind::Class(class) => write!(f, "{}", class),
+            TransitionKind::Epsilon => write!(f, "ε"),
+            TransitionKind::Group(machine) => write!(f, "<{}>", machine.name()),
+            TransitionKind::Rule(rule) => write!(f, "<{}>", rule),
+            TransitionKind::Lookahead {
+                condition,
+                negative: false,
+            } => write!(f, "&{}", condition),
+            TransitionKind::Lookahead {
+                condition,
+                negative: true,
+            } => write!(f, "!{}", condition),
+        }
+    }
+}
+
+pub trait Transition: Send + Sync {
+    fn from(&self) -> Arc<State>;
+    /// The state reached when the transition matches.
+    fn target(&self) -> Arc<State>;
+    /// Tries the transition at byte `offset` and returns the number of bytes
+    /// consumed. The state reached is always `target`. Group transitions run
+    /// their inner machine within `context`.
     fn to(
         &self,
-        buffer: String,
+        input: &Input,
+        offset: usize,
+        current_indentation: &Indentation,
+        indentation_character: &str,
+        context: &mut RunContext,
+    ) -> Result<usize, ErrorTransition>;
+    /// Every way this transition can match at `offset`, longest first. Only groups
+    /// over backtracking machines can match in more than one way.
+    fn to_all(
+        &self,
+        input: &Input,
         offset: usize,
-        current_indentation: i32,
-        indentation_character: String,
-    ) -> Result<(Rc<State>, usize), ErrorTransition>;
+        current_indentation: &Indentation,
+        indentation_character: &str,
+        context: &mut RunContext,
+    ) -> Vec<usize> {
+        self.to(
+            input,
+            offset,
+            current_indentation,
+            indentation_character,
+            context,
+        )
+        .into_iter()
+        .collect()
+    }
     fn indentation_operation(&self) -> IndentationOperation;
+    /// The indentation once the transition matched at `offset`.
+    fn next_indentation(
+        &self,
+        indentation: &Indentation,
+        _input: &Input,
+        _offset: usize,
+    ) -> Indentation {
+        self.indentation_operation().apply(indentation, None)
+    }
+    fn kind(&self) -> TransitionKind<'_>;
+    /// The same transition, going from `from` to `to`. Group machines are
+    /// shared with the copy.
+    fn reconnect(&self, from: Arc<State>, to: Arc<State>) -> Arc<dyn Transition>;
+    /// The label of the spans this transition captures, if any.
+    fn capture(&self) -> Option<&str> {
+        None
+    }
 }
 
 impl Transition for GroupTransition {
-    fn from(&self) -> Rc<State> {
+    fn from(&self) -> Arc<State> {
         self.from.clone()
     }
 
+    fn target(&self) -> Arc<State> {
+        self.to.clone()
+    }
+
     fn to(
         &self,
-        buffer: String,
+        input: &Input,
         offset: usize,
-        _current_indentation: i32,
-        _indentation_character: String,
-    ) -> Result<(Rc<State>, usize), ErrorTransition> {
-        let (validation, new_offset) =
-            (*self.value).validate_from(buffer, offset, _current_indentation);
-        if validation {
-            Ok((self.to.clone(), new_offset - offset))
-        } else {
-            Err(ErrorTransition::InvalidTransition)
-        }
+        current_indentation: &Indentation,
+        _indentation_character: &str,
+        context: &mut RunContext,
+    ) -> Result<usize, ErrorTransition> {
+        run_group(&self.value, input, offset, current_indentation, context)
+    }
+
+    fn to_all(
+        &self,
+        input: &Input,
+        offset: usize,
+        current_indentation: &Indentation,
+        _indentation_character: &str,
+        context: &mut RunContext,
+    ) -> Vec<usize> {
+        run_group_all(&self.value, input, offset, current_indentation, context)
     }
 
     fn indentation_operation(&self) -> IndentationOperation {
         self.indentation_operation.clone()
     }
+
+    fn reconnect(&self, from: Arc<State>, to: Arc<State>) -> Arc<dyn Transition> {
+        Arc::new(GroupTransition::shared(
+            from,
+            to,
+            self.value.clone(),
+            self.indentation_operation.clone(),
+        ))
+    }
+
+    fn kind(&self) -> TransitionKind<'_> {
+        TransitionKind::Group(&self.value)
+    }
+}
+
+/// Runs `machine` as a group from `offset` and returns the bytes it consumed.
+fn run_group(
+    machine: &StateMachine,
+    input: &Input,
+    offset: usize,
+    current_indentation: &Indentation,
+    context: &mut RunContext,
+) -> Result<usize, ErrorTransition> {
+    if context.is_memoizing() {
+        // the longest accepting end is the one a plain run stops at
+        return run_group_all(machine, input, offset, current_indentation, context)
+            .first()
+            .copied()
+            .ok_or(ErrorTransition::InvalidTransition);
+    }
+    if !context.enter_group(machine, offset, current_indentation) {
+        return Err(ErrorTransition::InvalidTransition);
+    }
+    let (validation, new_offset) =
+        machine.validate_in_context(input, offset, current_indentation, context);
+    context.leave_group();
+    if validation {
+        Ok(new_offset - offset)
+    } else {
+        Err(ErrorTransition::InvalidTransition)
+    }
+}
+
+/// Every number of bytes `machine` can consume as a group from `offset`,
+/// longest first.
+fn run_group_all(
+    machine: &StateMachine,
+    input: &Input,
+    offset: usize,
+    current_indentation: &Indentation,
+    context: &mut RunContext,
+) -> Vec<usize> {
+    let ends = match context.recall(machine, offset, current_indentation) {
+        Some(ends) => ends.to_vec(),
+        None => {
+            if !context.enter_group(machine, offset, current_indentation) {
+                return Vec::new();
+            }
+            let ends =
+                machine.accepting_ends_in_context(input, offset, current_indentation, context);
+            context.leave_group();
+            // an aborted run may have cut the group short
+            if context.is_memoizing() && !context.is_aborted() {
+                context.remember(machine, offset, current_indentation, ends.clone());
+            }
+            ends
+        }
+    };
+    ends.into_iter().map(|end| end - offset).collect()
 }
 
 impl GroupTransition {
     pub fn new(
-        from: Rc<State>,
-        to: Rc<State>,
+        from: Arc<State>,
+        to: Arc<State>,
         state_machine: StateMachine,
         indentation_operation: IndentationOperation,
+    ) -> Self {
+        GroupTransition::shared(from, to, Arc::new(state_machine), indentation_operation)
+    }
+
+    /// A group over a machine other transitions may run as well. Memoized runs
+    /// of a shared machine are reused across all of them.
+    pub fn shared(
+        from: Arc<State>,
+        to: Arc<State>,
+        state_machine: Arc<StateMachine>,
+        indentation_operation: IndentationOperation,
     ) -> Self {
         GroupTransition {
             from,
             to,
-            value: Box::new(state_machine),
+            value: state_machine,
+            indentation_operation,
+        }
+    }
+}
+
+impl Transition for RuleTransition {
+    fn from(&self) -> Arc<State> {
+        self.from.clone()
+    }
+
+    fn target(&self) -> Arc<State> {
+        self.to.clone()
+    }
+
+    fn to(
+        &self,
+        input: &Input,
+        offset: usize,
+        current_indentation: &Indentation,
+        _indentation_character: &str,
+        context: &mut RunContext,
+    ) -> Result<usize, ErrorTransition> {
+        let machine = context
+            .rule(&self.rule)
+            .ok_or(ErrorTransition::InvalidTransition)?;
+        run_group(&machine, input, offset, current_indentation, context)
+    }
+
+    fn to_all(
+        &self,
+        input: &Input,
+        offset: usize,
+        current_indentation: &Indentation,
+        _indentation_character: &str,
+        context: &mut RunContext,
+    ) -> Vec<usize> {
+        match context.rule(&self.rule) {
+            Some(machine) => run_group_all(&machine, input, offset, current_indentation, context),
+            None => Vec::new(),
+        }
+    }
+
+    fn indentation_operation(&self) -> IndentationOperation {
+        self.indentation_operation.clone()
+    }
+
+    fn reconnect(&self, from: Arc<State>, to: Arc<State>) -> Arc<dyn Transition> {
+        Arc::new(RuleTransition::new(
+            from,
+            to,
+            &self.rule,
+            self.indentation_operation.clone(),
+        ))
+    }
+
+    fn kind(&self) -> TransitionKind<'_> {
+        TransitionKind::Rule(&self.rule)
+    }
+}
+
+impl RuleTransition {
+    pub fn new(
+        from: Arc<State>,
+        to: Arc<State>,
+        rule: &str,
+        indentation_operation: IndentationOperation,
+    ) -> Self {
+        RuleTransition {
+            from,
+            to,
+            rule: rule.to_string(),
             indentation_operation,
         }
     }
 }
 
 impl Transition for CharTransition {
-    fn from(&self) -> Rc<State> {
+    fn from(&self) -> Arc<State> {
         self.from.clone()
     }
+
+    fn target(&self) -> Arc<State> {
+        self.to.clone()
+    }
     fn to(
         &self,
-        buffer: String,
+        input: &Input,
         offset: usize,
-        current_indentation: i32,
-        indentation_character: String,
-    ) -> Result<(Rc<State>, usize), ErrorTransition> {
-        if buffer.chars().nth(offset).unwrap().to_string() == self.value {
-            debug!("is: {}", self.to.label);
-            match self.indentation_operation {
-                IndentationOperation::BYPASS => Ok((self.to.clone(), 1)),
-                IndentationOperation::INCREMENT => {
-                    let offset = offset as i32;
-                    for n in (offset + 1)..(offset + 2 + current_indentation) {
-                        if buffer.chars().nth(n as usize).unwrap().to_string()
-                            == indentation_character
-                        {
-                            continue;
-                        }
-                        return Err(ErrorTransition::InvalidTransition);
-                    }
-                    debug!("adding offset : {} ", current_indentation + 2);
-                    Ok((self.to.clone(), (current_indentation + 2) as usize))
-                }
-                IndentationOperation::DESINCREMENT => {
-                    let offset = offset as i32;
-                    if current_indentation == 0 {
-                        return Err(ErrorTransition::InvalidTransition);
-                    }
-                    for n in (offset + 1)..(offset + 1 + current_indentation - 1) {
-                        if buffer.chars().nth(n as usize).unwrap().to_string()
-                            == indentation_character
-                        {
-                            continue;
-                        }
-                        return Err(ErrorTransition::InvalidTransition);
-                    }
-                    Ok((self.to.clone(), (current_indentation) as usize))
-                }
-                IndentationOperation::CONSERVE => {
-                    let offset = offset as i32;
-                    for n in (offset + 1)..(offset + 1 + current_indentation) {
-                        if buffer.chars().nth(n as usize).unwrap().to_string()
-                            == indentation_character
-                        {
-                            continue;
-                        }
-                        return Err(ErrorTransition::InvalidTransition);
-                    }
-                    Ok((self.to.clone(), (current_indentation + 1) as usize))
-                }
-                IndentationOperation::RESET => Ok((self.to.clone(), 1)),
+        current_indentation: &Indentation,
+        indentation_character: &str,
+        context: &mut RunContext,
+    ) -> Result<usize, ErrorTransition> {
+        // the value is a single character, anything else never matches
+        let mut value = self.value.chars();
+        match (input.peek(offset), value.next(), value.next()) {
+            (Some(c), Some(expected), None) if c == expected => {
+                debug!("is: {}", self.to.label);
+                let consumed = consume_indentation(
+                    &self.indentation_operation,
+                    input,
+                    offset,
+                    current_indentation,
+                    indentation_character,
+                    context,
+                )?;
+                Ok(consumed)
+            }
+            _ => {
+                debug!("not: {}", self.to.label);
+                Err(ErrorTransition::InvalidTransition)
             }
-        } else {
-            debug!("not: {}", self.to.label);
-            Err(ErrorTransition::InvalidTransition)
         }
     }
 
     fn indentation_operation(&self) -> IndentationOperation {
         self.indentation_operation.clone()
     }
+
+    fn next_indentation(
+        &self,
+        indentation: &Indentation,
+        input: &Input,
+        offset: usize,
+    ) -> Indentation {
+        indentation_after(&self.indentation_operation, indentation, input, offset)
+    }
+
+    fn reconnect(&self, from: Arc<State>, to: Arc<State>) -> Arc<dyn Transition> {
+        Arc::new(CharTransition::new(
+            from,
+            to,
+            self.value.clone(),
+            self.indentation_operation.clone(),
+        ))
+    }
+
+    fn kind(&self) -> TransitionKind<'_> {
+        TransitionKind::Char(&self.value)
+    }
+}
+
+/// Checks the indentation following the character matched at `offset` and returns
+/// how many bytes are consumed, the matched character included. Blanks mixing
+/// tabs and spaces abort the run.
+fn consume_indentation(
+    operation: &IndentationOperation,
+    input: &Input,
+    offset: usize,
+    current_indentation: &Indentation,
+    indentation_character: &str,
+    context: &mut RunContext,
+) -> Result<usize, ErrorTransition> {
+    let matched = input
+        .peek(offset)
+        .ok_or(ErrorTransition::InvalidTransition)?;
+    let start = offset + matched.len_utf8();
+    if matches!(
+        operation,
+        IndentationOperation::BYPASS | IndentationOperation::RESET
+    ) {
+        return Ok(start - offset);
+    }
+
+    let line = measure(input, start, current_indentation).map_err(|mixed| {
+        context.abort(RunError::MixedIndentation { offset: mixed });
+        ErrorTransition::InvalidTransition
+    })?;
+    debug!(
+        "line indented to {} in {:?}",
+        line.column,
+        current_indentation.columns()
+    );
+    let allowed = line
+        .character
+        .is_none_or(|c| indentation_character.contains(c));
+    if !allowed || !operation.allows(current_indentation, &line) {
+        Err(ErrorTransition::InvalidTransition)
+    } else if *operation == IndentationOperation::CONSERVE {
+        // Blanks are one byte wide.
+        Ok(start + current_indentation.column() - offset)
+    } else {
+        Ok(line.end - offset)
+    }
+}
+
+/// The indentation once a char or class transition matched at `offset`,
+/// measuring the line it opens a block with.
+fn indentation_after(
+    operation: &IndentationOperation,
+    indentation: &Indentation,
+    input: &Input,
+    offset: usize,
+) -> Indentation {
+    let line = match operation {
+        IndentationOperation::INCREMENT => input
+            .peek(offset)
+            .and_then(|c| measure(input, offset + c.len_utf8(), indentation).ok()),
+        _ => None,
+    };
+    operation.apply(indentation, line.as_ref())
 }
 
 impl CharTransition {
     pub fn new(
-        from: Rc<State>,
-        to: Rc<State>,
+        from: Arc<State>,
+        to: Arc<State>,
         check: String,
         indentation_operation: IndentationOperation,
     ) -> CharTransition {
@@ -185,63 +584,370 @@ impl fmt::Debug for CharTransition {
     }
 }
 
+impl Transition for ClassTransition {
+    fn from(&self) -> Arc<State> {
+        self.from.clone()
+    }
+
+    fn target(&self) -> Arc<State> {
+        self.to.clone()
+    }
+
+    fn to(
+        &self,
+        input: &Input,
+        offset: usize,
+        current_indentation: &Indentation,
+        indentation_character: &str,
+        context: &mut RunContext,
+    ) -> Result<usize, ErrorTransition> {
+        match input.peek(offset) {
+            Some(c) if self.class.matches(c) => {
+                debug!("is: {}", self.to.label);
+                let consumed = consume_indentation(
+                    &self.indentation_operation,
+                    input,
+                    offset,
+                    current_indentation,
+                    indentation_character,
+                    context,
+                )?;
+                Ok(consumed)
+            }
+            _ => {
+                debug!("not: {}", self.to.label);
+                Err(ErrorTransition::InvalidTransition)
+            }
+        }
+    }
+
+    fn indentation_operation(&self) -> IndentationOperation {
+        self.indentation_operation.clone()
+    }
+
+    fn next_indentation(
+        &self,
+        indentation: &Indentation,
+        input: &Input,
+        offset: usize,
+    ) -> Indentation {
+        indentation_after(&self.indentation_operation, indentation, input, offset)
+    }
+
+    fn reconnect(&self, from: Arc<State>, to: Arc<State>) -> Arc<dyn Transition> {
+        Arc::new(ClassTransition::new(
+            from,
+            to,
+            self.class.clone(),
+            self.indentation_operation.clone(),
+        ))
+    }
+
+    fn kind(&self) -> TransitionKind<'_> {
+        TransitionKind::Class(&self.class)
+    }
+}
+
+impl ClassTransition {
+    pub fn new(
+        from: Arc<State>,
+        to: Arc<State>,
+        class: CharClass,
+        indentation_operation: IndentationOperation,
+    ) -> ClassTransition {
+        ClassTransition {
+            from,
+            to,
+            class,
+            indentation_operation,
+        }
+    }
+}
+
+impl fmt::Debug for ClassTransition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} -> {} -> {:?}", self.from, self.class, self.to)
+    }
+}
+
 impl Transition for EpsilonTransition {
-    fn from(&self) -> Rc<State> {
+    fn from(&self) -> Arc<State> {
         self.from.clone()
     }
+
+    fn target(&self) -> Arc<State> {
+        self.to.clone()
+    }
     fn to(
         &self,
-        _buffer: String,
+        _input: &Input,
         _offset: usize,
-        _current_indentation: i32,
-        _indentation_character: String,
-    ) -> Result<(Rc<State>, usize), ErrorTransition> {
-        Ok((self.to.clone(), 0))
+        _current_indentation: &Indentation,
+        _indentation_character: &str,
+        _context: &mut RunContext,
+    ) -> Result<usize, ErrorTransition> {
+        Ok(0)
     }
     fn indentation_operation(&self) -> IndentationOperation {
         IndentationOperation::BYPASS
     }
+
+    fn reconnect(&self, from: Arc<State>, to: Arc<State>) -> Arc<dyn Transition> {
+        Arc::new(EpsilonTransition::new(from, to))
+    }
+
+    fn kind(&self) -> TransitionKind<'_> {
+        TransitionKind::Epsilon
+    }
 }
 
 impl EpsilonTransition {
-    pub fn new(from: Rc<State>, to: Rc<State>) -> EpsilonTransition {
+    pub fn new(from: Arc<State>, to: Arc<State>) -> EpsilonTransition {
         EpsilonTransition { from, to }
     }
 }
 
-pub fn create_char_transitions(
-    from: Rc<State>,
-    to: Rc<State>,
-    alphabet: String,
-    indentation_operation: IndentationOperation,
-) -> Vec<Rc<dyn Transition>> {
-    let mut transitions: Vec<Rc<dyn Transition>> = Vec::new();
-    let letters: Vec<&str> = alphabet.split("").collect();
-    for letter in letters {
-        transitions.push(Rc::new(CharTransition::new(
-            from.clone(),
-            to.clone(),
-            letter.to_string(),
-            indentation_operation.clone(),
-        )));
+impl Transition for CaptureTransition {
+    fn from(&self) -> Arc<State> {
+        self.transition.from()
+    }
+
+    fn target(&self) -> Arc<State> {
+        self.transition.target()
+    }
+
+    fn to(
+        &self,
+        input: &Input,
+        offset: usize,
+        current_indentation: &Indentation,
+        indentation_character: &str,
+        context: &mut RunContext,
+    ) -> Result<usize, ErrorTransition> {
+        self.transition.to(
+            input,
+            offset,
+            current_indentation,
+            indentation_character,
+            context,
+        )
+    }
+
+    fn to_all(
+        &self,
+        input: &Input,
+        offset: usize,
+        current_indentation: &Indentation,
+        indentation_character: &str,
+        context: &mut RunContext,
+    ) -> Vec<usize> {
+        self.transition.to_all(
+            input,
+            offset,
+            current_indentation,
+            indentation_character,
+            context,
+        )
+    }
+
+    fn indentation_operation(&self) -> IndentationOperation {
+        self.transition.indentation_operation()
+    }
+
+    fn next_indentation(
+        &self,
+        indentation: &Indentation,
+        input: &Input,
+        offset: usize,
+    ) -> Indentation {
+        self.transition.next_indentation(indentation, input, offset)
+    }
+
+    fn reconnect(&self, from: Arc<State>, to: Arc<State>) -> Arc<dyn Transition> {
+        Arc::new(CaptureTransition::new(
+            &self.label,
+            self.transition.reconnect(from, to),
+        ))
+    }
+
+    fn kind(&self) -> TransitionKind<'_> {
+        self.transition.kind()
+    }
+
+    fn capture(&self) -> Option<&str> {
+        Some(&self.label)
+    }
+}
+
+impl CaptureTransition {
+    pub fn new(label: &str, transition: Arc<dyn Transition>) -> CaptureTransition {
+        CaptureTransition {
+            label: label.to_string(),
+            transition,
+        }
+    }
+}
+
+impl Lookahead {
+    /// Whether the condition holds at `offset`. Nothing is consumed either way.
+    fn holds(
+        &self,
+        input: &Input,
+        offset: usize,
+        current_indentation: &Indentation,
+        context: &mut RunContext,
+    ) -> bool {
+        match self {
+            Lookahead::Char(expected) => input.peek(offset) == Some(*expected),
+            Lookahead::Class(class) => input.peek(offset).is_some_and(|c| class.matches(c)),
+            Lookahead::Machine(machine) => {
+                run_group(machine, input, offset, current_indentation, context).is_ok()
+            }
+        }
+    }
+}
+
+impl Transition for LookaheadTransition {
+    fn from(&self) -> Arc<State> {
+        self.from.clone()
+    }
+
+    fn target(&self) -> Arc<State> {
+        self.to.clone()
+    }
+
+    fn to(
+        &self,
+        input: &Input,
+        offset: usize,
+        current_indentation: &Indentation,
+        _indentation_character: &str,
+        context: &mut RunContext,
+    ) -> Result<usize, ErrorTransition> {
+        if self
+            .condition
+            .holds(input, offset, current_indentation, context)
+        {
+            Ok(0)
+        } else {
+            Err(ErrorTransition::InvalidTransition)
+        }
+    }
+
+    fn indentation_operation(&self) -> IndentationOperation {
+        IndentationOperation::BYPASS
+    }
+
+    fn reconnect(&self, from: Arc<State>, to: Arc<State>) -> Arc<dyn Transition> {
+        Arc::new(LookaheadTransition::new(from, to, self.condition.clone()))
+    }
+
+    fn kind(&self) -> TransitionKind<'_> {
+        TransitionKind::Lookahead {
+            condition: &self.condition,
+            negative: false,
+        }
     }
-    transitions
+}
+
+impl LookaheadTransition {
+    pub fn new(from: Arc<State>, to: Arc<State>, condition: Lookahead) -> LookaheadTransition {
+        LookaheadTransition {
+            from,
+            to,
+            condition,
+        }
+    }
+}
+
+impl Transition for NegativeLookaheadTransition {
+    fn from(&self) -> Arc<State> {
+        self.from.clone()
+    }
+
+    fn target(&self) -> Arc<State> {
+        self.to.clone()
+    }
+
+    fn to(
+        &self,
+        input: &Input,
+        offset: usize,
+        current_indentation: &Indentation,
+        _indentation_character: &str,
+        context: &mut RunContext,
+    ) -> Result<usize, ErrorTransition> {
+        if self
+            .condition
+            .holds(input, offset, current_indentation, context)
+            || context.is_aborted()
+        {
+            Err(ErrorTransition::InvalidTransition)
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn indentation_operation(&self) -> IndentationOperation {
+        IndentationOperation::BYPASS
+    }
+
+    fn reconnect(&self, from: Arc<State>, to: Arc<State>) -> Arc<dyn Transition> {
+        Arc::new(NegativeLookaheadTransition::new(
+            from,
+            to,
+            self.condition.clone(),
+        ))
+    }
+
+    fn kind(&self) -> TransitionKind<'_> {
+        TransitionKind::Lookahead {
+            condition: &self.condition,
+            negative: true,
+        }
+    }
+}
+
+impl NegativeLookaheadTransition {
+    pub fn new(
+        from: Arc<State>,
+        to: Arc<State>,
+        condition: Lookahead,
+    ) -> NegativeLookaheadTransition {
+        NegativeLookaheadTransition {
+            from,
+            to,
+            condition,
+        }
+    }
+}
+
+/// Creates the class transition matching any single character of `alphabet`.
+pub fn create_class_transition(
+    from: Arc<State>,
+    to: Arc<State>,
+    alphabet: &str,
+    indentation_operation: IndentationOperation,
+) -> Arc<dyn Transition> {
+    let class = CharClassBuilder::new().add_chars(alphabet).build();
+    Arc::new(ClassTransition::new(from, to, class, indentation_operation))
 }
 
 pub fn create_word_transition(
-    from: Rc<State>,
-    to: Rc<State>,
+    from: Arc<State>,
+    to: Arc<State>,
     word: String,
     indentation_operation: IndentationOperation,
     current_indentation: i32,
-) -> Rc<dyn Transition> {
-    let mut states: Vec<Rc<State>> = Vec::new();
-    let mut transitions: Vec<Rc<dyn Transition>> = Vec::new();
-    let mut start = Rc::new(create_state(true, "zob"));
-
-    for i in 0..word.len() + 1 {
-        let state = Rc::new(create_state(
-            i == word.len(),
+) -> Arc<dyn Transition> {
+    let mut states: Vec<Arc<State>> = Vec::new();
+    let mut transitions: Vec<Arc<dyn Transition>> = Vec::new();
+    let mut start = Arc::new(create_state(true, "zob"));
+    let letters: Vec<char> = word.chars().collect();
+
+    for i in 0..letters.len() + 1 {
+        let state = Arc::new(create_state(
+            i == letters.len(),
             format!("letter-{}", i).as_str(),
         ));
 
@@ -253,8 +959,8 @@ pub fn create_word_transition(
         states.push(state.clone());
 
         if i > 0 {
-            let char = word.chars().nth(i - 1).unwrap().to_string();
-            let transition = Rc::new(CharTransition::new(
+            let char = letters[i - 1].to_string();
+            let transition = Arc::new(CharTransition::new(
                 states[i - 1].clone(),
                 state.clone(),
                 char.clone(),
@@ -265,11 +971,12 @@ pub fn create_word_transition(
     }
 
     let state_machine = StateMachineBuilder::new(start.clone(), " ", current_indentation)
+        .name(&format!("{:?}", word))
         .add_states(states.clone())
         .add_transitions(transitions)
         .build();
 
-    Rc::new(GroupTransition::new(
+    Arc::new(GroupTransition::new(
         from,
         to,
         state_machine,
@@ -279,17 +986,64 @@ pub fn create_word_transition(
 
 #[cfg(test)]
 mod tests {
-    use std::rc::Rc;
+    use std::sync::Arc;
 
-    use crate::grammar::{state::create_state, state_machine::StateMachineBuilder};
+    use crate::grammar::{
+        class::CharClassBuilder,
+        context::RunContext,
+        error::RunError,
+        state::create_state,
+        state_machine::{ExecutionMode, StateMachine, StateMachineBuilder},
+    };
 
-    use super::{create_word_transition, IndentationOperation};
+    use super::{
+        create_word_transition, CharTransition, ClassTransition, IndentationOperation, Lookahead,
+        LookaheadTransition, NegativeLookaheadTransition, Transition, TransitionKind,
+    };
+
+    /// `-`, checked against `condition`, then any single character.
+    fn dash_machine(condition: Lookahead, negative: bool) -> StateMachine {
+        let start = Arc::new(create_state(false, "start"));
+        let dash = Arc::new(create_state(false, "dash"));
+        let checked = Arc::new(create_state(false, "checked"));
+        let end = Arc::new(create_state(true, "end"));
+        let lookahead: Arc<dyn Transition> = if negative {
+            Arc::new(NegativeLookaheadTransition::new(
+                dash.clone(),
+                checked.clone(),
+                condition,
+            ))
+        } else {
+            Arc::new(LookaheadTransition::new(
+                dash.clone(),
+                checked.clone(),
+                condition,
+            ))
+        };
+        let any = CharClassBuilder::new().add_range('\0', char::MAX).build();
+        StateMachineBuilder::new(start.clone(), " ", 0)
+            .add_states(vec![dash.clone(), checked.clone(), end.clone()])
+            .add_transition(Arc::new(CharTransition::new(
+                start,
+                dash,
+                "-".to_string(),
+                IndentationOperation::BYPASS,
+            )))
+            .add_transition(lookahead)
+            .add_transition(Arc::new(ClassTransition::new(
+                checked,
+                end,
+                any,
+                IndentationOperation::BYPASS,
+            )))
+            .build()
+    }
 
     #[test]
     fn test_word_transition() {
         let word = "---".to_string();
-        let start = Rc::new(create_state(false, "start"));
-        let end = Rc::new(create_state(true, "end"));
+        let start = Arc::new(create_state(false, "start"));
+        let end = Arc::new(create_state(true, "end"));
         let transition = create_word_transition(
             start.clone(),
             end.clone(),
@@ -304,7 +1058,100 @@ mod tests {
             .build();
 
         let (result, offset) = machine.validate(word.clone());
-        assert_eq!(result, true);
+        assert!(result);
         assert_eq!(word.len(), offset);
     }
+
+    #[test]
+    fn test_lookahead_consumes_nothing() {
+        let blank = CharClassBuilder::new().add_chars(" \n").build();
+        let machine = dash_machine(Lookahead::Class(blank), false);
+        assert_eq!(machine.validate("- "), (true, 2));
+        assert!(!machine.validate("-a").0);
+        assert!(!machine.validate("-").0);
+
+        let word = StateMachineBuilder::new(Arc::new(create_state(false, "start")), " ", 0)
+            .name("word")
+            .add_state(Arc::new(create_state(true, "end")))
+            .add_transition(create_word_transition(
+                Arc::new(create_state(false, "start")),
+                Arc::new(create_state(true, "end")),
+                "ab".to_string(),
+                IndentationOperation::BYPASS,
+                0,
+            ))
+            .build();
+        let machine = dash_machine(Lookahead::Machine(Arc::new(word)), false);
+        assert_eq!(machine.validate("-ab"), (true, 2));
+        assert!(!machine.validate("-ac").0);
+        assert_eq!(machine.transitions()[1].kind().to_string(), "&<word>");
+    }
+
+    #[test]
+    fn test_negative_lookahead_consumes_nothing() {
+        let machine = dash_machine(Lookahead::Char(' '), true);
+        assert_eq!(machine.validate("-a"), (true, 2));
+        assert!(!machine.validate("- ").0);
+        assert_eq!(machine.transitions()[1].kind().to_string(), "!' '");
+        assert!(matches!(
+            machine.transitions()[1].kind(),
+            TransitionKind::Lookahead { negative: true, .. }
+        ));
+
+        // backtracking runs try transitions at the end of the input too, where
+        // a negative lookahead always holds
+        let colon = Arc::new(create_state(false, "colon"));
+        let machine = StateMachineBuilder::new(Arc::new(create_state(false, "start")), " ", 0)
+            .add_state(colon.clone())
+            .mode(ExecutionMode::Backtracking)
+            .add_state(Arc::new(create_state(true, "end")))
+            .add_transition(Arc::new(CharTransition::new(
+                Arc::new(create_state(false, "start")),
+                colon.clone(),
+                ":".to_string(),
+                IndentationOperation::BYPASS,
+            )))
+            .add_transition(Arc::new(NegativeLookaheadTransition::new(
+                colon,
+                Arc::new(create_state(true, "end")),
+                Lookahead::Char(' '),
+            )))
+            .build();
+        assert_eq!(machine.validate(":"), (true, 1));
+    }
+
+    #[test]
+    fn test_blocks_indented_with_tabs() {
+        let start = Arc::new(create_state(false, "start"));
+        let block = Arc::new(create_state(false, "block"));
+        let line = Arc::new(create_state(true, "line"));
+        let machine = StateMachineBuilder::new(start.clone(), "\t", 0)
+            .add_states(vec![block.clone(), line.clone()])
+            .add_transition(Arc::new(CharTransition::new(
+                start,
+                block.clone(),
+                "\n".to_string(),
+                IndentationOperation::INCREMENT,
+            )))
+            .add_transition(Arc::new(CharTransition::new(
+                block.clone(),
+                line.clone(),
+                "x".to_string(),
+                IndentationOperation::BYPASS,
+            )))
+            .add_transition(Arc::new(CharTransition::new(
+                line,
+                block,
+                "\n".to_string(),
+                IndentationOperation::CONSERVE,
+            )))
+            .build();
+        assert!(machine.check("\n\t\tx\n\t\tx"));
+        assert!(!machine.check("\n\t\tx\n\tx"));
+        assert!(!machine.check("\n  x"));
+        assert_eq!(
+            machine.try_validate_with("\n\tx\n x", &mut RunContext::new()),
+            Err(RunError::MixedIndentation { offset: 4 })
+        );
+    }
 }
@@ -1,3 +1,4 @@
+use grammar::indentation::Indentation;
 use tracing::info;
 use yaml::document::document_state_machine;
 pub mod grammar;
@@ -24,11 +25,14 @@ list:
 
     let machine = document_state_machine(0);
 
-    let (result, offset) = machine.validate_from(val.to_string(), 0, 0);
-    info!("offset is : {}, {}", offset, val.len());
-    if result {
-        println!("valid");
-    } else {
-        println!("non valid");
+    match machine.validate_detailed_from(val, 0, &Indentation::new()) {
+        Ok(offset) => {
+            info!("offset is : {}, {}", offset, val.len());
+            println!("valid");
+        }
+        Err(error) => {
+            info!("offset is : {}, {}", error.offset, val.len());
+            println!("non valid: {}", error);
+        }
     }
 }
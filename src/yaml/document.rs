@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::grammar::{
     state::create_state,
@@ -6,15 +6,14 @@ use crate::grammar::{
     transition::{create_word_transition, CharTransition, IndentationOperation},
 };
 
-use super::{kv::kv_transition, scalar::scalar_transition, value::value_transition};
+use super::kv::kv_transition;
 
 pub fn document_state_machine(indentation: i32) -> StateMachine {
-    let begin_doc = Rc::new(create_state(false, "begin_doc"));
-    let header = Rc::new(create_state(false, "header"));
-    let header_end = Rc::new(create_state(false, "header_end"));
-    let body = Rc::new(create_state(false, "body"));
-    let back = Rc::new(create_state(false, "back"));
-    let end = Rc::new(create_state(true, "end"));
+    let begin_doc = Arc::new(create_state(false, "begin_doc"));
+    let header = Arc::new(create_state(false, "header"));
+    let header_end = Arc::new(create_state(false, "header_end"));
+    let body = Arc::new(create_state(false, "body"));
+    let end = Arc::new(create_state(true, "end"));
 
     let header_ts = create_word_transition(
         begin_doc.clone(),
@@ -24,21 +23,21 @@ pub fn document_state_machine(indentation: i32) -> StateMachine {
         indentation,
     );
 
-    let header_end_ts = Rc::new(CharTransition::new(
+    let header_end_ts = Arc::new(CharTransition::new(
         header.clone(),
         header_end.clone(),
         "\n".to_string(),
         IndentationOperation::RESET,
     ));
 
-    let body_ts = Rc::new(kv_transition(
+    let body_ts = Arc::new(kv_transition(
         header_end.clone(),
         body.clone(),
         indentation,
         IndentationOperation::BYPASS,
     ));
 
-    let back_ts = Rc::new(CharTransition::new(
+    let back_ts = Arc::new(CharTransition::new(
         body.clone(),
         header_end.clone(),
         "\n".to_string(),
@@ -54,9 +53,11 @@ pub fn document_state_machine(indentation: i32) -> StateMachine {
     );
 
     StateMachineBuilder::new(begin_doc.clone(), " ", indentation)
-        .add_states(vec![header, header_end, body, back, end])
+        .name("document")
+        .add_states(vec![header, header_end, body, end])
         .add_transitions(vec![header_ts, header_end_ts, body_ts, back_ts, end_ts])
-        .build()
+        .try_build()
+        .expect("the document machine is well formed")
 }
 
 #[cfg(test)]
@@ -69,8 +70,8 @@ mod tests {
 test:test
 ---";
         let machine = document_state_machine(0);
-        let (result, offset) = machine.validate(val.to_string());
-        assert_eq!(result, true);
+        let (result, offset) = machine.validate(val);
+        assert!(result);
         assert_eq!(val.len(), offset);
     }
 
@@ -83,8 +84,24 @@ test:
   -test
 ---";
         let machine = document_state_machine(0);
-        let (result, offset) = machine.validate(val.to_string());
-        assert_eq!(result, true);
+        let (result, offset) = machine.validate(val);
+        assert!(result);
         assert_eq!(val.len(), offset);
     }
+
+    #[test]
+    fn test_document_state_machine_reports_nested_error() {
+        let val = "---
+zob:test
+test:
+ zob:
+  -
+---";
+        let machine = document_state_machine(0);
+        let error = machine.check_detailed(val).unwrap_err();
+        assert_eq!((error.line, error.column), (5, 4));
+        assert_eq!(error.groups, vec!["kv", "value", "sequence"]);
+        assert_eq!(error.state, "tick");
+        assert_eq!(error.expected, vec!["<scalar>"]);
+    }
 }
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+
+use crate::grammar::{
+    class::{CharClass, CharClassBuilder},
+    state::{create_state, State},
+    state_machine::{StateMachine, StateMachineBuilder},
+    transition::{ClassTransition, GroupTransition, IndentationOperation},
+};
+
+use super::scalar::scalar_class;
+
+/// A key can't start with a space, which belongs to indentation.
+fn key_start_class() -> CharClass {
+    CharClassBuilder::new()
+        .add_range('A', 'Z')
+        .add_range('a', 'z')
+        .build()
+}
+
+/// Matches a key up to its column: letters and spaces, like a scalar, starting
+/// with a letter.
+pub fn key_state_machine(indentation: i32) -> StateMachine {
+    let begin = Arc::new(create_state(false, "start_key"));
+    let key = Arc::new(create_state(true, "key"));
+
+    let b_k = ClassTransition::new(
+        begin.clone(),
+        key.clone(),
+        key_start_class(),
+        IndentationOperation::BYPASS,
+    );
+    let k_k = ClassTransition::new(
+        key.clone(),
+        key.clone(),
+        scalar_class(),
+        IndentationOperation::BYPASS,
+    );
+
+    StateMachineBuilder::new(begin, " ", indentation)
+        .name("key")
+        .add_state(key)
+        .add_transitions(vec![Arc::new(b_k), Arc::new(k_k)])
+        .build()
+}
+
+pub fn key_transition(
+    from: Arc<State>,
+    to: Arc<State>,
+    indentation: i32,
+    operation: IndentationOperation,
+) -> GroupTransition {
+    GroupTransition::new(from, to, key_state_machine(indentation), operation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::key_state_machine;
+
+    #[test]
+    fn test_key_state_machine_recognize_key() {
+        let key = "api version:";
+        let machine = key_state_machine(0);
+        let (result, offset) = machine.validate(key);
+        assert!(result);
+        assert_eq!(offset, key.len() - 1);
+    }
+
+    #[test]
+    fn test_key_state_machine_accepts_scalars_only() {
+        let machine = key_state_machine(0);
+        for key in ["name", "Api Version", "a key "] {
+            assert!(machine.check(key), "{}", key);
+        }
+        for key in [
+            "", " key", "port8080", "_private", "a.b", "clé", "a:b", "a\nb",
+        ] {
+            assert!(!machine.check(key), "{:?}", key);
+        }
+        assert_eq!(machine.validate("api_version"), (true, 3));
+    }
+
+    #[test]
+    fn test_key_state_machine_dont_recognize_dash() {
+        let machine = key_state_machine(0);
+        let (result, _) = machine.validate("-item");
+        assert!(!result);
+    }
+}
@@ -1,61 +1,27 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 use tracing::debug;
 
-use crate::grammar::{
-    state::{create_state, State},
-    state_machine::{StateMachine, StateMachineBuilder},
-    transition::{CharTransition, GroupTransition, IndentationOperation},
+use crate::{
+    grammar::{
+        state::State,
+        state_machine::StateMachine,
+        transition::{GroupTransition, IndentationOperation},
+    },
+    machine,
 };
 
-use super::{scalar::scalar_transition, value::value_transition};
+use super::{key::key_state_machine, value::value_state_machine};
 
 pub fn kv_state_machine(indentation: i32) -> StateMachine {
-    let begin = Rc::new(create_state(false, "start"));
-    let key = Rc::new(create_state(false, "key"));
-    let column = Rc::new(create_state(false, "column"));
-    let value = Rc::new(create_state(true, "value"));
-    let nested_kv = Rc::new(create_state(false, "nested_kv"));
-
-    let b_k = Rc::new(scalar_transition(
-        begin.clone(),
-        key.clone(),
-        indentation,
-        IndentationOperation::BYPASS,
-    ));
-
-    let k_c = Rc::new(CharTransition::new(
-        key.clone(),
-        column.clone(),
-        ":".to_string(),
-        IndentationOperation::BYPASS,
-    ));
-
-    let c_v = Rc::new(value_transition(
-        column.clone(),
-        value.clone(),
-        indentation,
-        IndentationOperation::BYPASS,
-    ));
-
-    let c_n = Rc::new(CharTransition::new(
-        column.clone(),
-        nested_kv.clone(),
-        "\n".to_string(),
-        IndentationOperation::INCREMENT,
-    ));
-
-    let n_b = Rc::new(scalar_transition(
-        nested_kv.clone(),
-        key.clone(),
-        indentation,
-        IndentationOperation::BYPASS,
-    ));
-
-    let automaton = StateMachineBuilder::new(begin, " ", indentation)
-        .add_states(vec![key, column, value, nested_kv])
-        .add_transitions(vec![b_k, k_c, c_v, c_n, n_b])
-        .build();
+    let automaton = machine! {
+        name: "kv", indentation: indentation;
+        start -> key: key_state_machine(indentation), capture: "key";
+        key -> column: ':';
+        column -> value*: value_state_machine(indentation), capture: "value";
+        column -> nested_kv: '\n' => INCREMENT;
+        nested_kv -> key: key_state_machine(indentation), capture: "key";
+    };
 
     debug!("built kv state machine");
 
@@ -63,8 +29,8 @@ pub fn kv_state_machine(indentation: i32) -> StateMachine {
 }
 
 pub fn kv_transition(
-    from: Rc<State>,
-    to: Rc<State>,
+    from: Arc<State>,
+    to: Arc<State>,
     indentation: i32,
     operation: IndentationOperation,
 ) -> GroupTransition {
@@ -73,6 +39,7 @@ pub fn kv_transition(
 
 #[cfg(test)]
 mod tests {
+    use crate::grammar::{context::RunContext, error::RunError};
 
     use super::kv_state_machine;
 
@@ -80,18 +47,27 @@ mod tests {
     fn test_kv_state_machine_recognize_kv() {
         let kv = "salut:poulet";
         let machine = kv_state_machine(0);
-        let (result, offset) = machine.validate(kv.to_string());
-        assert_eq!(result, true);
+        let (result, offset) = machine.validate(kv);
+        assert!(result);
         assert_eq!(kv.len(), offset);
     }
 
+    #[test]
+    fn test_kv_state_machine_uses_byte_offsets() {
+        let machine = kv_state_machine(0);
+        let error = machine.check_detailed("clé:valeur").unwrap_err();
+        assert_eq!(error.offset, "cl".len());
+        assert_eq!((error.line, error.column), (1, 3));
+        assert_eq!(machine.validate("clé:valeur"), (false, "cl".len()));
+    }
+
     #[test]
     fn test_kv_state_machine_recognize_kv_nested() {
         let kv = "salut:
  test:zob";
         let machine = kv_state_machine(0);
-        let (result, offset) = machine.validate(kv.to_string());
-        assert_eq!(result, true);
+        let (result, offset) = machine.validate(kv);
+        assert!(result);
         assert_eq!(kv.len(), offset);
     }
 
@@ -102,8 +78,8 @@ mod tests {
  -deux
  -trois";
         let machine = kv_state_machine(0);
-        let (result, offset) = machine.validate(kv.to_string());
-        assert_eq!(result, true);
+        let (result, offset) = machine.validate(kv);
+        assert!(result);
         assert_eq!(kv.len(), offset);
     }
 
@@ -115,8 +91,8 @@ mod tests {
  -deux
  -trois";
         let machine = kv_state_machine(0);
-        let (result, offset) = machine.validate(kv.to_string());
-        assert_eq!(result, false);
+        let (result, offset) = machine.validate(kv);
+        assert!(!result);
         assert_eq!("salut:".len(), offset);
     }
 
@@ -127,8 +103,68 @@ mod tests {
     -deux
  -trois";
         let machine = kv_state_machine(0);
-        let (result, _) = machine.validate(kv.to_string());
-        assert_eq!(result, false);
-        assert_ne!("salut:".len(), kv.len());
+        assert!(!machine.validate(kv).0);
+    }
+
+    #[test]
+    fn test_kv_state_machine_recognize_any_indent_width() {
+        let machine = kv_state_machine(0);
+        assert!(machine.check("key:\n  nested:x"));
+        assert!(machine.check("a:\n  b:\n      c:x"));
+        assert!(machine.check("list:\n   -a\n   -b"));
+        assert!(!machine.check("list:\n   -a\n  -b"));
+    }
+
+    #[test]
+    fn test_kv_state_machine_reports_mixed_indentation() {
+        let machine = kv_state_machine(0);
+        for (kv, offset) in [("list:\n -a\n\t-b", 10), ("list:\n \t-a", 7)] {
+            assert_eq!(
+                machine.try_validate_with(kv, &mut RunContext::new()),
+                Err(RunError::MixedIndentation { offset })
+            );
+        }
+        // yaml blocks are indented with spaces only
+        assert!(!machine.check("list:\n\t-a\n\t-b"));
+    }
+
+    #[test]
+    fn test_kv_state_machine_reports_validation_error() {
+        let kv = "salut:
+
+ -poulet";
+        let machine = kv_state_machine(0);
+        let error = machine.validate_detailed(kv).unwrap_err();
+        assert_eq!(error.offset, "salut:".len());
+        assert_eq!((error.line, error.column), (1, 7));
+        assert_eq!(error.state, "column");
+        assert!(error.groups.is_empty());
+        assert_eq!(error.expected, vec!["<value>", "\"\\n\""]);
+    }
+
+    #[test]
+    fn test_kv_state_machine_captures_key_and_value() {
+        let kv = "salut:poulet";
+        let spans = kv_state_machine(0).captures(kv).unwrap();
+        let captured: Vec<(&str, &str)> = spans
+            .iter()
+            .map(|span| (span.label.as_str(), span.text(kv)))
+            .collect();
+        assert_eq!(captured, vec![("key", "salut"), ("value", "poulet")]);
+    }
+
+    #[test]
+    fn test_kv_state_machine_captures_sequence_items() {
+        let kv = "salut:
+ -poulet
+ -deux";
+        let spans = kv_state_machine(0).captures(kv).unwrap();
+        let value = &spans[1];
+        assert_eq!(value.label, "value");
+        let sequence = &value.children[0];
+        assert_eq!(sequence.label, "sequence");
+        let items: Vec<&str> = sequence.children.iter().map(|item| item.text(kv)).collect();
+        assert_eq!(items, vec!["poulet", "deux"]);
+        assert!(kv_state_machine(0).captures("salut").is_none());
     }
 }
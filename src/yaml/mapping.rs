@@ -0,0 +1,192 @@
+use std::sync::Arc;
+
+use crate::grammar::{
+    registry::{Grammar, GrammarBuilder},
+    state::create_state,
+    state_machine::{ExecutionMode, StateMachine, StateMachineBuilder},
+    transition::{CharTransition, IndentationOperation, RuleTransition},
+};
+
+use super::{
+    key::key_state_machine, scalar::scalar_state_machine, sequence::sequence_state_machine,
+};
+
+/// `mapping -> key ':' (scalar | '\n' mapping | '\n' sequence) ('\n' mapping)?`
+///
+/// Nested mappings and sequences are blocks indented deeper, entries of the
+/// same mapping share the column of their block. The machine backtracks so
+/// a nested mapping can give back the newline of a shallower entry.
+pub fn mapping_state_machine(indentation: i32) -> StateMachine {
+    let begin = Arc::new(create_state(false, "start"));
+    let key = Arc::new(create_state(false, "key"));
+    let column = Arc::new(create_state(false, "column"));
+    let nested = Arc::new(create_state(false, "nested"));
+    let entry = Arc::new(create_state(true, "entry"));
+
+    let b_k = RuleTransition::new(
+        begin.clone(),
+        key.clone(),
+        "key",
+        IndentationOperation::BYPASS,
+    );
+    let k_c = CharTransition::new(
+        key,
+        column.clone(),
+        ":".to_string(),
+        IndentationOperation::BYPASS,
+    );
+    let c_e = RuleTransition::new(
+        column.clone(),
+        entry.clone(),
+        "scalar",
+        IndentationOperation::BYPASS,
+    );
+    let c_n = CharTransition::new(
+        column,
+        nested.clone(),
+        "\n".to_string(),
+        IndentationOperation::INCREMENT,
+    );
+    // leaving the nested block brings the indentation back to the entry's
+    let n_m = RuleTransition::new(
+        nested.clone(),
+        entry.clone(),
+        "mapping",
+        IndentationOperation::DESINCREMENT,
+    );
+    let n_s = RuleTransition::new(
+        nested,
+        entry.clone(),
+        "sequence",
+        IndentationOperation::DESINCREMENT,
+    );
+    let e_b = CharTransition::new(
+        entry,
+        begin.clone(),
+        "\n".to_string(),
+        IndentationOperation::CONSERVE,
+    );
+
+    StateMachineBuilder::new(begin, " ", indentation)
+        .name("mapping")
+        .mode(ExecutionMode::Backtracking)
+        .add_transitions(vec![
+            Arc::new(b_k),
+            Arc::new(k_c),
+            Arc::new(c_e),
+            Arc::new(c_n),
+            Arc::new(n_m),
+            Arc::new(n_s),
+            Arc::new(e_b),
+        ])
+        .build()
+}
+
+/// The rules of the recursive YAML grammar, `mapping` being the entry point.
+pub fn yaml_grammar() -> Grammar {
+    GrammarBuilder::new()
+        .add_rule("key", key_state_machine(0))
+        .add_rule("scalar", scalar_state_machine(0))
+        .add_rule("sequence", sequence_state_machine(0))
+        .add_rule("mapping", mapping_state_machine(0))
+        .build()
+        .expect("the yaml grammar only refers to its own rules")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grammar::registry::Grammar;
+
+    use super::yaml_grammar;
+
+    fn grammar_file() -> Grammar {
+        Grammar::load(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/grammars/yaml.grammar"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_mapping_recognize_flat_mapping() {
+        let grammar = yaml_grammar();
+        assert!(grammar.check("mapping", "name:poulet").unwrap());
+        assert!(grammar
+            .check("mapping", "name:poulet\nkind:chicken")
+            .unwrap());
+    }
+
+    #[test]
+    fn test_mapping_recognize_arbitrarily_deep_mapping() {
+        let grammar = yaml_grammar();
+        let mut buffer = String::from("root:");
+        for depth in 1..12 {
+            buffer.push('\n');
+            buffer.push_str(&" ".repeat(depth));
+            buffer.push_str(&format!("level {}:", "x".repeat(depth)));
+        }
+        buffer.push_str("leaf");
+        assert!(grammar.check("mapping", &buffer).unwrap());
+    }
+
+    #[test]
+    fn test_mapping_recognize_siblings_after_nested_blocks() {
+        let val = "salut:
+ zob:
+  zizi:
+   -test
+ test:test
+list:
+ -zob
+ -zizi
+end:x";
+        assert!(yaml_grammar().check("mapping", val).unwrap());
+    }
+
+    #[test]
+    fn test_mapping_reports_errors_inside_nested_rules() {
+        let val = "salut:\n zob:\n  -x\n  -;";
+        let error = yaml_grammar()
+            .check_detailed("mapping", val)
+            .unwrap()
+            .unwrap_err();
+        assert_eq!((error.line, error.column), (4, 4));
+        assert_eq!(error.groups, vec!["mapping", "sequence"]);
+        assert_eq!(error.state, "tick");
+    }
+
+    #[test]
+    fn test_mapping_rejects_entry_between_blocks() {
+        let grammar = yaml_grammar();
+        assert!(grammar
+            .check("mapping", "salut:\n  zob:x\n  zizi:y\nend:x")
+            .unwrap());
+        let val = "salut:\n  zob:x\n zizi:y";
+        let error = grammar.check_detailed("mapping", val).unwrap().unwrap_err();
+        assert_eq!((error.line, error.column), (3, 1));
+        assert_eq!(error.state, "start");
+        assert_eq!(error.expected, vec!["<key>"]);
+    }
+
+    #[test]
+    fn test_grammar_file_agrees_with_builder_grammar() {
+        let built = yaml_grammar();
+        let loaded = grammar_file();
+        for buffer in [
+            "name:poulet",
+            "name:poulet\nkind:chicken",
+            "salut:\n zob:\n  zizi:\n   -test\n test:test\nlist:\n -zob\n -zizi\nend:x",
+            "salut:\n  zob:x",
+            "salut:\n zob:\n  -x\n  -;",
+            "-item",
+            "a:b\n",
+        ] {
+            assert_eq!(
+                built.check("mapping", buffer).unwrap(),
+                loaded.check("mapping", buffer).unwrap(),
+                "{:?}",
+                buffer
+            );
+        }
+    }
+}
@@ -1,5 +1,7 @@
 pub mod document;
+pub mod key;
 pub mod kv;
+pub mod mapping;
 pub mod scalar;
 pub mod sequence;
 pub mod value;
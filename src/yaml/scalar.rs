@@ -1,49 +1,59 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::grammar::{
+    class::{CharClass, CharClassBuilder},
     state::{create_state, State},
-    state_machine::{StateMachine, StateMachineBuilder},
-    transition::{
-        self, create_char_transitions, EpsilonTransition, GroupTransition, IndentationOperation,
-    },
+    state_machine::{ExecutionMode, StateMachine, StateMachineBuilder},
+    transition::{ClassTransition, GroupTransition, IndentationOperation},
 };
 
+pub fn scalar_class() -> CharClass {
+    CharClassBuilder::new()
+        .add_range('A', 'Z')
+        .add_range('a', 'z')
+        .add_char(' ')
+        .build()
+}
+
 pub fn scalar_state_machine(indentation: i32) -> StateMachine {
-    let alphabet = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz ".to_string();
-    let state_start = Rc::new(create_state(false, "start_word"));
-    let state_end_pair = Rc::new(create_state(true, "end_pair_word"));
-    let state_end_impair = Rc::new(create_state(true, "end_impair_word"));
-    let transitions_alpha_start = create_char_transitions(
+    let state_start = Arc::new(create_state(false, "start_word"));
+    let state_end_pair = Arc::new(create_state(true, "end_pair_word"));
+    let state_end_impair = Arc::new(create_state(true, "end_impair_word"));
+    let transition_alpha_start = ClassTransition::new(
         state_start.clone(),
         state_end_impair.clone(),
-        alphabet.clone(),
-        transition::IndentationOperation::BYPASS,
+        scalar_class(),
+        IndentationOperation::BYPASS,
     );
-    let transitions_alpha_pair = create_char_transitions(
+    let transition_alpha_pair = ClassTransition::new(
         state_end_impair.clone(),
         state_end_pair.clone(),
-        alphabet.clone(),
-        transition::IndentationOperation::BYPASS,
+        scalar_class(),
+        IndentationOperation::BYPASS,
     );
-    let transitions_alpha_impair = create_char_transitions(
+    let transition_alpha_impair = ClassTransition::new(
         state_end_pair.clone(),
         state_end_impair.clone(),
-        alphabet,
-        transition::IndentationOperation::BYPASS,
+        scalar_class(),
+        IndentationOperation::BYPASS,
     );
     let automaton = StateMachineBuilder::new(state_start, " ", indentation)
+        .name("scalar")
+        .mode(ExecutionMode::Backtracking)
         .add_states(vec![state_end_pair, state_end_impair])
-        .add_transitions(transitions_alpha_pair)
-        .add_transitions(transitions_alpha_start)
-        .add_transitions(transitions_alpha_impair)
+        .add_transitions(vec![
+            Arc::new(transition_alpha_pair),
+            Arc::new(transition_alpha_start),
+            Arc::new(transition_alpha_impair),
+        ])
         .build();
 
     automaton
 }
 
 pub fn scalar_transition(
-    from: Rc<State>,
-    to: Rc<State>,
+    from: Arc<State>,
+    to: Arc<State>,
     indentation: i32,
     operation: IndentationOperation,
 ) -> GroupTransition {
@@ -53,14 +63,16 @@ pub fn scalar_transition(
 #[cfg(test)]
 mod tests {
 
+    use crate::grammar::indentation::Indentation;
+
     use super::scalar_state_machine;
 
     #[test]
     fn test_scalar_state_machine_recognize_words() {
         let word = "Bonjour je suis tristan";
         let machine = scalar_state_machine(0);
-        let (result, offset) = machine.validate(word.to_string());
-        assert_eq!(result, true);
+        let (result, offset) = machine.validate(word);
+        assert!(result);
         assert_eq!(offset, word.len());
     }
 
@@ -68,8 +80,8 @@ mod tests {
     fn test_scalar_state_machine_recognize_words_pair() {
         let word = "Bon;j";
         let machine = scalar_state_machine(0);
-        let (result, offset) = machine.validate(word.to_string());
-        assert_eq!(result, true);
+        let (result, offset) = machine.validate(word);
+        assert!(result);
         assert_eq!(offset, word.len() - 2);
     }
 
@@ -77,8 +89,16 @@ mod tests {
     fn test_scalar_state_machine_doesnt_recognize_words() {
         let word = "wqejklwq;s"; // ; is an invalid character
         let machine = scalar_state_machine(0);
-        let (result, offset) = machine.validate(word.to_string());
-        assert_eq!(result, true);
+        let (result, offset) = machine.validate(word);
+        assert!(result);
         assert_eq!(offset, word.len() - 2);
     }
+
+    #[test]
+    fn test_scalar_state_machine_can_give_back_trailing_spaces() {
+        let word = "ab  :";
+        let machine = scalar_state_machine(0);
+        let ends = machine.accepting_ends(word, 0, &Indentation::new());
+        assert_eq!(ends, vec![4, 3, 2, 1]);
+    }
 }
@@ -1,48 +1,29 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
-use crate::grammar::{
-    state::{create_state, State},
-    state_machine::{StateMachine, StateMachineBuilder},
-    transition::{CharTransition, EpsilonTransition, GroupTransition, IndentationOperation},
+use crate::{
+    grammar::{
+        state::State,
+        state_machine::StateMachine,
+        transition::{GroupTransition, IndentationOperation},
+    },
+    machine,
 };
 
-use super::scalar::scalar_transition;
+use super::scalar::scalar_state_machine;
 
 pub fn sequence_state_machine(indentation: i32) -> StateMachine {
-    let begin = Rc::new(create_state(false, "start"));
-    let tick = Rc::new(create_state(false, "tick"));
-    let val = Rc::new(create_state(true, "val"));
-    let next = Rc::new(create_state(false, "next"));
-
-    let b_t = CharTransition::new(
-        begin.clone(),
-        tick.clone(),
-        "-".to_string(),
-        IndentationOperation::BYPASS,
-    );
-    let t_v = scalar_transition(
-        tick.clone(),
-        val.clone(),
-        indentation,
-        IndentationOperation::BYPASS,
-    );
-    let v_n = CharTransition::new(
-        val.clone(),
-        next.clone(),
-        "\n".to_string(),
-        IndentationOperation::CONSERVE,
-    );
-    let n_b = EpsilonTransition::new(next.clone(), begin.clone());
-    let automaton = StateMachineBuilder::new(begin, " ", indentation)
-        .add_states(vec![tick, val, next])
-        .add_transitions(vec![Rc::new(b_t), Rc::new(t_v), Rc::new(v_n), Rc::new(n_b)])
-        .build();
-    automaton
+    machine! {
+        name: "sequence", indentation: indentation;
+        start -> tick: '-';
+        tick -> val*: scalar_state_machine(indentation), capture: "sequence_item";
+        val -> next: '\n' => CONSERVE;
+        next -> start: epsilon;
+    }
 }
 
 pub fn sequence_transition(
-    from: Rc<State>,
-    to: Rc<State>,
+    from: Arc<State>,
+    to: Arc<State>,
     indentation: i32,
     operation: IndentationOperation,
 ) -> GroupTransition {
@@ -59,8 +40,8 @@ mod tests {
 -val
 -val";
         let machine = sequence_state_machine(0);
-        let (result, offset) = machine.validate(kv.to_string());
-        assert_eq!(result, true);
+        let (result, offset) = machine.validate(kv);
+        assert!(result);
         assert_eq!(kv.len(), offset);
     }
 }
@@ -1,48 +1,29 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
-use crate::grammar::{
-    state::{create_state, State},
-    state_machine::{StateMachine, StateMachineBuilder},
-    transition::{CharTransition, GroupTransition, IndentationOperation},
+use crate::{
+    grammar::{
+        state::State,
+        state_machine::StateMachine,
+        transition::{GroupTransition, IndentationOperation},
+    },
+    machine,
 };
 
-use super::{scalar::scalar_transition, sequence::sequence_transition};
+use super::{scalar::scalar_state_machine, sequence::sequence_state_machine};
 
 pub fn value_state_machine(indentation: i32) -> StateMachine {
-    let begin = Rc::new(create_state(false, "start_value")); //value can't be empty
-    let scalar = Rc::new(create_state(true, "scalar"));
-    let multiline = Rc::new(create_state(false, "multiline"));
-    let sequence = Rc::new(create_state(true, "sequence"));
-
-    let b_s = Rc::new(scalar_transition(
-        begin.clone(),
-        scalar.clone(),
-        indentation,
-        crate::grammar::transition::IndentationOperation::BYPASS,
-    ));
-
-    let b_m = Rc::new(CharTransition::new(
-        begin.clone(),
-        multiline.clone(),
-        "\n".to_string(),
-        crate::grammar::transition::IndentationOperation::INCREMENT,
-    ));
-    let m_s = Rc::new(sequence_transition(
-        multiline.clone(),
-        sequence.clone(),
-        indentation,
-        crate::grammar::transition::IndentationOperation::BYPASS,
-    ));
-
-    StateMachineBuilder::new(begin, " ", indentation)
-        .add_transitions(vec![b_s, b_m, m_s])
-        .add_states(vec![scalar, multiline, sequence])
-        .build()
+    //value can't be empty, a newline starts a nested sequence rather than a scalar
+    machine! {
+        name: "value", indentation: indentation;
+        start_value -> scalar*: scalar_state_machine(indentation);
+        start_value -> multiline: '\n' => INCREMENT, priority: 1;
+        multiline -> sequence*: sequence_state_machine(indentation), capture: "sequence";
+    }
 }
 
 pub fn value_transition(
-    from: Rc<State>,
-    to: Rc<State>,
+    from: Arc<State>,
+    to: Arc<State>,
     indentation: i32,
     operation: IndentationOperation,
 ) -> GroupTransition {
@@ -51,6 +32,8 @@ pub fn value_transition(
 
 #[cfg(test)]
 mod tests {
+    use crate::grammar::indentation::Indentation;
+
     use super::value_state_machine;
 
     #[test]
@@ -58,8 +41,8 @@ mod tests {
         let val = "ewqewq";
         let machine = value_state_machine(0);
 
-        let (result, offset) = machine.validate(val.to_string());
-        assert_eq!(result, true);
+        let (result, offset) = machine.validate(val);
+        assert!(result);
         assert_eq!(val.len(), offset);
     }
 
@@ -71,8 +54,8 @@ mod tests {
  -adssca";
         let machine = value_state_machine(0);
 
-        let (result, offset) = machine.validate(val.to_string());
-        assert_eq!(result, true);
+        let (result, offset) = machine.validate(val);
+        assert!(result);
         assert_eq!(val.len(), offset);
     }
 
@@ -84,8 +67,8 @@ mod tests {
   -adssca";
         let machine = value_state_machine(1);
 
-        let (result, offset) = machine.validate_from(val.to_string(), 0, 1);
-        assert_eq!(result, true);
+        let (result, offset) = machine.validate_from(val, 0, &Indentation::levels(1));
+        assert!(result);
         assert_eq!(val.len(), offset);
     }
 }